@@ -43,4 +43,21 @@ impl Blockchain {
             network: Network::Main,
         }
     }
+
+    /// EIP-155 chain id of the network, if it is an EVM chain.
+    pub fn chain_id(&self) -> Option<u64> {
+        if self.system != System::Ethereum {
+            return None;
+        }
+        match self.network {
+            Network::Main => Some(1),
+            Network::Ropsten => Some(3),
+            Network::Rinkeby => Some(4),
+            Network::Goerli => Some(5),
+            Network::Kovan => Some(42),
+            Network::XDai => Some(100),
+            Network::BSC => Some(56),
+            Network::Polygon => Some(137),
+        }
+    }
 }
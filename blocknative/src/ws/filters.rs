@@ -0,0 +1,333 @@
+//! Typed construction of `WatchConfig` filters.
+//!
+//! Every term added to a [`FilterBuilder`] becomes one entry of `WatchConfig.filters`,
+//! which Blocknative combines with AND. Alternatives are expressed with an
+//! `{"_join": "OR", "terms": [...]}` entry.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use super::models::WatchConfig;
+
+pub const METHOD_NAME: &str = "contractCall.methodName";
+pub const CONTRACT_ADDRESS: &str = "contractCall.contractAddress";
+pub const PARAMS_PATH: &str = "contractCall.params.path";
+pub const TO: &str = "to";
+pub const VALUE: &str = "value";
+pub const GAS: &str = "gas";
+pub const GAS_PRICE: &str = "gasPrice";
+
+/// Router methods that swap along a `path` parameter.
+pub const ROUTER_SWAP_METHODS: [&str; 9] = [
+    "swapExactTokensForTokens",
+    "swapTokensForExactTokens",
+    "swapExactETHForTokens",
+    "swapTokensForExactETH",
+    "swapExactTokensForETH",
+    "swapETHForExactTokens",
+    "swapExactTokensForTokensSupportingFeeOnTransferTokens",
+    "swapExactETHForTokensSupportingFeeOnTransferTokens",
+    "swapExactTokensForETHSupportingFeeOnTransferTokens",
+];
+
+pub type Filter = HashMap<String, Value>;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+/// Error thrown when a filter can't be sent to Blocknative
+pub enum FilterError {
+    #[error("invalid address {0:?}")]
+    InvalidAddress(String),
+
+    #[error("empty range on {0}")]
+    EmptyRange(&'static str),
+
+    #[error("{0} filter has no alternatives")]
+    EmptyJoin(&'static str),
+
+    #[error("scope must be an address when watching an address")]
+    InvalidScope,
+}
+
+fn is_address(value: &str) -> bool {
+    value.len() == 42
+        && value.starts_with("0x")
+        && value[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Builder for a `WatchConfig`. Errors are collected and reported by `build`.
+#[derive(Debug, Clone, Default)]
+pub struct FilterBuilder {
+    scope: String,
+    watch_address: bool,
+    filters: Vec<Filter>,
+    errors: Vec<FilterError>,
+}
+
+impl WatchConfig {
+    /// Starts a filter on transactions touching the address `scope`.
+    pub fn builder(scope: impl Into<String>) -> FilterBuilder {
+        FilterBuilder {
+            scope: scope.into(),
+            watch_address: true,
+            ..Default::default()
+        }
+    }
+}
+
+impl FilterBuilder {
+    fn term(mut self, key: &str, value: Value) -> Self {
+        let mut filter = Filter::new();
+        filter.insert(key.to_string(), value);
+        self.filters.push(filter);
+        self
+    }
+
+    fn address_term(mut self, key: &str, address: &str) -> Self {
+        if !is_address(address) {
+            self.errors
+                .push(FilterError::InvalidAddress(address.to_string()));
+        }
+        self.term(key, Value::String(address.to_string()))
+    }
+
+    fn range_term(mut self, key: &'static str, min: Option<u128>, max: Option<u128>) -> Self {
+        let mut bounds = serde_json::Map::new();
+        if let Some(min) = min {
+            bounds.insert("gte".to_string(), Value::String(min.to_string()));
+        }
+        if let Some(max) = max {
+            bounds.insert("lte".to_string(), Value::String(max.to_string()));
+        }
+        match (min, max) {
+            (None, None) => return self,
+            (Some(min), Some(max)) if min > max => self.errors.push(FilterError::EmptyRange(key)),
+            _ => {}
+        }
+        self.term(key, Value::Object(bounds))
+    }
+
+    /// Matches any of `alternatives`, each a single `key: value` term.
+    fn any_of(mut self, key: &'static str, alternatives: Vec<Value>) -> Self {
+        if alternatives.is_empty() {
+            self.errors.push(FilterError::EmptyJoin(key));
+            return self;
+        }
+        if alternatives.len() == 1 {
+            return self.term(key, alternatives.into_iter().next().unwrap());
+        }
+        let terms = alternatives
+            .into_iter()
+            .map(|value| json!({ key: value }))
+            .collect::<Vec<_>>();
+        let mut filter = Filter::new();
+        filter.insert("_join".to_string(), Value::String("OR".to_string()));
+        filter.insert("terms".to_string(), Value::Array(terms));
+        self.filters.push(filter);
+        self
+    }
+
+    /// Sets whether `scope` is watched as an address (the default) or a
+    /// transaction/global scope.
+    pub fn watch_address(mut self, watch_address: bool) -> Self {
+        self.watch_address = watch_address;
+        self
+    }
+
+    pub fn method_name(self, method: &str) -> Self {
+        self.term(METHOD_NAME, Value::String(method.to_string()))
+    }
+
+    pub fn method_names<'a>(self, methods: impl IntoIterator<Item = &'a str>) -> Self {
+        let methods = methods
+            .into_iter()
+            .map(|method| Value::String(method.to_string()))
+            .collect();
+        self.any_of(METHOD_NAME, methods)
+    }
+
+    pub fn contract_address(self, address: &str) -> Self {
+        self.address_term(CONTRACT_ADDRESS, address)
+    }
+
+    pub fn to(self, address: &str) -> Self {
+        self.address_term(TO, address)
+    }
+
+    /// Matches router calls whose swap `path` contains `token`.
+    pub fn path_contains(self, token: &str) -> Self {
+        self.address_term(PARAMS_PATH, token)
+    }
+
+    /// Matches router calls whose swap `path` contains any of `tokens`.
+    pub fn path_contains_any<'a>(mut self, tokens: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut alternatives = Vec::new();
+        for token in tokens {
+            if !is_address(token) {
+                self.errors.push(FilterError::InvalidAddress(token.to_string()));
+            }
+            alternatives.push(Value::String(token.to_string()));
+        }
+        self.any_of(PARAMS_PATH, alternatives)
+    }
+
+    /// Bounds the transferred ether, in wei. Both bounds are inclusive.
+    pub fn value_range(self, min: Option<u128>, max: Option<u128>) -> Self {
+        self.range_term(VALUE, min, max)
+    }
+
+    /// Bounds the gas limit. Both bounds are inclusive.
+    pub fn gas_range(self, min: Option<u128>, max: Option<u128>) -> Self {
+        self.range_term(GAS, min, max)
+    }
+
+    /// Bounds the legacy gas price, in wei. Both bounds are inclusive.
+    pub fn gas_price_range(self, min: Option<u128>, max: Option<u128>) -> Self {
+        self.range_term(GAS_PRICE, min, max)
+    }
+
+    /// Adds a hand written filter entry, for keys the builder doesn't cover.
+    pub fn raw(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn build(self) -> Result<WatchConfig, FilterError> {
+        if let Some(error) = self.errors.into_iter().next() {
+            return Err(error);
+        }
+        if self.watch_address && !is_address(&self.scope) {
+            return Err(FilterError::InvalidScope);
+        }
+        Ok(WatchConfig {
+            scope: self.scope,
+            // Blocknative expects at least one (possibly empty) filter entry.
+            filters: if self.filters.is_empty() {
+                vec![Filter::new()]
+            } else {
+                self.filters
+            },
+            watch_address: self.watch_address,
+        })
+    }
+
+    /// All router swaps whose path touches any of `tokens`.
+    pub fn swaps_touching_tokens<'a>(
+        router: &str,
+        tokens: impl IntoIterator<Item = &'a str>,
+    ) -> Result<WatchConfig, FilterError> {
+        WatchConfig::builder(router)
+            .method_names(ROUTER_SWAP_METHODS)
+            .path_contains_any(tokens)
+            .build()
+    }
+
+    /// One `swaps_touching_tokens` config per router, for `(router, token)` pairs
+    /// such as the two tokens of every tracked pool keyed by its router.
+    pub fn router_swaps<'a>(
+        routes: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Vec<WatchConfig>, FilterError> {
+        let mut tokens_by_router: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for (router, token) in routes {
+            tokens_by_router.entry(router).or_default().insert(token);
+        }
+        tokens_by_router
+            .into_iter()
+            .map(|(router, tokens)| Self::swaps_touching_tokens(router, tokens))
+            .collect()
+    }
+
+    /// Direct `swap` calls on a pool, e.g. from other searchers and aggregators.
+    pub fn swaps_on_pool(pool: &str) -> Result<WatchConfig, FilterError> {
+        WatchConfig::builder(pool).method_name("swap").build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WETH: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+    const USDC: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48";
+    const ROUTER: &str = "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D";
+
+    #[test]
+    fn test_matches_hand_written_json() {
+        let config = WatchConfig::builder(ROUTER)
+            .path_contains(WETH)
+            .build()
+            .unwrap();
+
+        let mut filter = HashMap::new();
+        filter.insert(PARAMS_PATH.to_string(), Value::String(WETH.to_string()));
+        let expected = WatchConfig {
+            scope: ROUTER.to_string(),
+            filters: vec![filter],
+            watch_address: true,
+        };
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_ranges_and_joins() {
+        let config = WatchConfig::builder(ROUTER)
+            .value_range(Some(1), None)
+            .gas_range(Some(21_000), Some(500_000))
+            .path_contains_any([WETH, USDC])
+            .build()
+            .unwrap();
+        let json = serde_json::to_value(&config).unwrap();
+
+        assert_eq!(json["filters"][0]["value"], json!({"gte": "1"}));
+        assert_eq!(json["filters"][1]["gas"], json!({"gte": "21000", "lte": "500000"}));
+        assert_eq!(json["filters"][2]["_join"], "OR");
+        assert_eq!(json["filters"][2]["terms"][1][PARAMS_PATH], USDC);
+    }
+
+    #[test]
+    fn test_router_swaps() {
+        const SUSHI: &str = "0xd9e1cE17f2641f24aE83637ab66a2cca9C378B9F";
+        let routes = [
+            (ROUTER, WETH),
+            (ROUTER, USDC),
+            (SUSHI, WETH),
+            (ROUTER, WETH),
+        ];
+        let configs = FilterBuilder::router_swaps(routes).unwrap();
+
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0].scope, ROUTER);
+        assert_eq!(configs[1].scope, SUSHI);
+        let json = serde_json::to_value(&configs[0]).unwrap();
+        assert_eq!(json["filters"][1]["terms"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            FilterBuilder::router_swaps([(ROUTER, "WETH")]),
+            Err(FilterError::InvalidAddress("WETH".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(
+            WatchConfig::builder(ROUTER).path_contains("WETH").build(),
+            Err(FilterError::InvalidAddress("WETH".to_string()))
+        );
+        assert_eq!(
+            WatchConfig::builder(ROUTER)
+                .gas_range(Some(2), Some(1))
+                .build(),
+            Err(FilterError::EmptyRange(GAS))
+        );
+        assert_eq!(
+            FilterBuilder::swaps_touching_tokens(ROUTER, []),
+            Err(FilterError::EmptyJoin(PARAMS_PATH))
+        );
+        assert_eq!(
+            WatchConfig::builder("router").build(),
+            Err(FilterError::InvalidScope)
+        );
+    }
+}
@@ -0,0 +1,414 @@
+//! Local stand-in for `wss://api.blocknative.com/v0`, for exercising the client offline.
+//!
+//! The server answers the `initialize`/`checkDappId` handshake, acknowledges `configs`
+//! and `activeTransaction` requests and pushes whatever events the test scripts.
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_std::net::{TcpListener, TcpStream};
+use async_tungstenite::tungstenite::Message;
+use chrono::Utc;
+use futures_channel::mpsc;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{task::JoinHandle, time::error::Elapsed};
+use tracing::warn;
+
+use super::models::{Event, Response};
+
+const SERVER_VERSION: &str = "mock";
+/// How long `MockServer::wait_for` waits for a request
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Instructions for a single mock connection.
+#[derive(Debug)]
+enum Command {
+    Send(String),
+    Close,
+}
+
+#[derive(Default)]
+struct MockState {
+    api_key: Option<String>,
+    connections: Vec<mpsc::UnboundedSender<Command>>,
+    accepted: usize,
+    received: Vec<Value>,
+}
+
+/// Stops accepting and closes every connection once the last `MockServer` handle
+/// is dropped.
+struct Shutdown {
+    accept: JoinHandle<()>,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl Drop for Shutdown {
+    fn drop(&mut self) {
+        self.accept.abort();
+        self.state.lock().unwrap().connections.clear();
+    }
+}
+
+/// Handle on a running mock server. The server stops when the last handle is dropped.
+#[derive(Clone)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    _shutdown: Arc<Shutdown>,
+}
+
+impl MockServer {
+    /// Starts a server on a free local port which accepts any dapp id.
+    pub async fn start() -> std::io::Result<Self> {
+        Self::start_with_api_key(None).await
+    }
+
+    /// Starts a server which rejects the handshake unless the dapp id matches `api_key`.
+    pub async fn start_with_api_key(api_key: Option<&str>) -> std::io::Result<Self> {
+        Self::start_on("127.0.0.1:0".parse().unwrap(), api_key).await
+    }
+
+    /// Starts a server on `addr`, e.g. to bring back one which was dropped.
+    pub async fn start_on(addr: SocketAddr, api_key: Option<&str>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(MockState {
+            api_key: api_key.map(str::to_string),
+            ..Default::default()
+        }));
+
+        let accepting = state.clone();
+        let accept = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, accepting.clone()));
+            }
+        });
+
+        Ok(Self {
+            addr,
+            state: state.clone(),
+            _shutdown: Arc::new(Shutdown { accept, state }),
+        })
+    }
+
+    /// Websocket url to hand to `Ws::connect`.
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    /// Number of connections accepted so far, including closed ones.
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().accepted
+    }
+
+    /// Every request received so far, in order.
+    pub fn received(&self) -> Vec<Value> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// Address the server listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Waits until a request with the given category and event code arrived, for at
+    /// most `WAIT_TIMEOUT`.
+    pub async fn wait_for(&self, category_code: &str, event_code: &str) -> Result<Value, Elapsed> {
+        tokio::time::timeout(WAIT_TIMEOUT, async {
+            loop {
+                let found = self.received().into_iter().find(|request| {
+                    request["categoryCode"] == category_code && request["eventCode"] == event_code
+                });
+                if let Some(request) = found {
+                    return request;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+    }
+
+    /// Pushes a raw text frame to every open connection.
+    pub fn push_raw(&self, text: impl Into<String>) {
+        let text = text.into();
+        self.state
+            .lock()
+            .unwrap()
+            .connections
+            .retain(|conn| conn.unbounded_send(Command::Send(text.clone())).is_ok());
+    }
+
+    pub fn push(&self, response: &Response) {
+        self.push_raw(serde_json::to_string(response).unwrap());
+    }
+
+    /// Wraps `event` in a `Response` envelope and pushes it.
+    pub fn push_event(&self, event: Event) {
+        self.push(&Response {
+            version: 0,
+            server_version: SERVER_VERSION.to_string(),
+            time_stamp: Utc::now().to_rfc3339(),
+            connection_id: "mock".to_string(),
+            status: "ok".to_string(),
+            raw: None,
+            event: Some(event),
+            reason: None,
+            dispatch_timestamp: Some(Utc::now().to_rfc3339()),
+        });
+    }
+
+    /// Pushes an error response, as Blocknative sends for rate limits or bad filters.
+    pub fn push_error(&self, reason: &str) {
+        self.push_raw(error_response(reason, None).to_string());
+    }
+
+    /// Closes every open connection with a close frame.
+    pub fn disconnect(&self) {
+        for conn in self.state.lock().unwrap().connections.drain(..) {
+            let _ = conn.unbounded_send(Command::Close);
+        }
+    }
+}
+
+fn ok_response(raw: &str) -> Value {
+    json!({
+        "version": 0,
+        "serverVersion": SERVER_VERSION,
+        "timeStamp": Utc::now().to_rfc3339(),
+        "connectionId": "mock",
+        "status": "ok",
+        "raw": raw,
+    })
+}
+
+fn error_response(reason: &str, raw: Option<&str>) -> Value {
+    json!({
+        "version": 0,
+        "serverVersion": SERVER_VERSION,
+        "timeStamp": Utc::now().to_rfc3339(),
+        "connectionId": "mock",
+        "status": "error",
+        "reason": reason,
+        "raw": raw,
+    })
+}
+
+/// Builds the reply for one client request, or `None` if nothing is sent back.
+fn reply(state: &Mutex<MockState>, text: &str) -> Option<Value> {
+    let request: Value = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(e) => return Some(error_response(&format!("invalid json: {}", e), Some(text))),
+    };
+    let mut state = state.lock().unwrap();
+    state.received.push(request.clone());
+
+    let category = request["categoryCode"].as_str().unwrap_or_default();
+    let event = request["eventCode"].as_str().unwrap_or_default();
+    match (category, event) {
+        ("initialize", "checkDappId") => {
+            let dapp_id = request["dappId"].as_str().unwrap_or_default();
+            match &state.api_key {
+                Some(key) if key != dapp_id => {
+                    Some(error_response("invalid dappId", Some(text)))
+                }
+                _ => Some(json!({
+                    "version": 0,
+                    "serverVersion": SERVER_VERSION,
+                    "status": "ok",
+                    "showUX": false,
+                    "connectionId": "mock",
+                })),
+            }
+        }
+        ("configs", "put") if request.get("config").is_none() => {
+            Some(error_response("missing config", Some(text)))
+        }
+        ("configs", "put") | ("activeTransaction", _) | ("activeAddress", _) => {
+            Some(ok_response(text))
+        }
+        _ => Some(error_response(
+            &format!("unsupported request {}/{}", category, event),
+            Some(text),
+        )),
+    }
+}
+
+async fn serve(stream: TcpStream, state: Arc<Mutex<MockState>>) {
+    let mut ws = match async_tungstenite::accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            warn!("mock handshake failed: {}", e);
+            return;
+        }
+    };
+
+    let (tx, mut rx) = mpsc::unbounded();
+    {
+        let mut state = state.lock().unwrap();
+        state.accepted += 1;
+        state.connections.push(tx);
+    }
+
+    loop {
+        tokio::select! {
+            command = rx.next() => match command {
+                Some(Command::Send(text)) => {
+                    if ws.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Command::Close) | None => {
+                    let _ = ws.close(None).await;
+                    break;
+                }
+            },
+            msg = ws.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    if let Some(reply) = reply(&state, &text) {
+                        if ws.send(Message::Text(reply.to_string())).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Some(Ok(Message::Ping(inner))) => {
+                    let _ = ws.send(Message::Pong(inner)).await;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        models::Blockchain,
+        ws::{models::WatchConfig, ws::Ws},
+    };
+
+    use super::*;
+
+    fn event() -> Event {
+        serde_json::from_str(r#"{"timeStamp":"2022-02-05T05:47:08.506Z","categoryCode":"activeAddress","eventCode":"txPool","dappId":"key","blockchain":{"system":"ethereum","network":"main"},"contractCall":{"contractType":"Uniswap V2: Router 2","contractAddress":"0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D","methodName":"swapExactTokensForTokens","params":{}}}"#).unwrap()
+    }
+
+    fn config() -> WatchConfig {
+        WatchConfig {
+            scope: "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D".to_string(),
+            filters: vec![HashMap::new()],
+            watch_address: true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_subscription() {
+        let server = MockServer::start_with_api_key(Some("key")).await.unwrap();
+        let ws = Ws::connect(server.url(), "key", Blockchain::main())
+            .await
+            .unwrap();
+        let mut stream = ws.listen(config()).await.unwrap();
+
+        server.wait_for("initialize", "checkDappId").await.unwrap();
+        let put = server.wait_for("configs", "put").await.unwrap();
+        assert_eq!(put["config"]["scope"], config().scope);
+
+        server.push_event(event());
+        let resp = stream.next().await.unwrap();
+        assert_eq!(
+            resp.event.unwrap().contract_call.unwrap().method_name,
+            "swapExactTokensForTokens"
+        );
+
+        server.push_error("rate limit exceeded");
+        let resp = stream.next().await.unwrap();
+        assert_eq!(resp.status, "error");
+        assert_eq!(resp.reason.as_deref(), Some("rate limit exceeded"));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_ends_stream() {
+        let server = MockServer::start().await.unwrap();
+        let ws = Ws::connect(server.url(), "", Blockchain::main())
+            .await
+            .unwrap();
+        let mut stream = ws.listen(config()).await.unwrap();
+        server.wait_for("configs", "put").await.unwrap();
+
+        server.disconnect();
+        assert!(stream.next().await.is_none());
+
+        // A new client can connect to the same server afterwards
+        Ws::connect(server.url(), "", Blockchain::main())
+            .await
+            .unwrap();
+        tokio::time::timeout(WAIT_TIMEOUT, async {
+            while server.connections() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_before_put() {
+        let server = MockServer::start().await.unwrap();
+        let ws = Ws::connect(server.url(), "", Blockchain::main())
+            .await
+            .unwrap();
+        server.wait_for("initialize", "checkDappId").await.unwrap();
+
+        server.disconnect();
+        tokio::time::timeout(WAIT_TIMEOUT, async {
+            while ws.ready() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        // An error for `listen_reconnecting` to retry on, not a panic
+        assert!(ws.listen(config()).await.is_err());
+        assert!(server.wait_for("configs", "put").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_reconnects() {
+        let server = MockServer::start().await.unwrap();
+        let addr = server.addr();
+        let mut stream = Ws::listen_reconnecting(
+            server.url(),
+            "",
+            Blockchain::main(),
+            config(),
+            Duration::from_millis(20),
+        );
+        server.wait_for("configs", "put").await.unwrap();
+
+        // The port is free again once the accept task is gone
+        drop(server);
+        let server = tokio::time::timeout(WAIT_TIMEOUT, async {
+            loop {
+                match MockServer::start_on(addr, None).await {
+                    Ok(server) => return server,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        // Subscribed again on the new server, and events flow
+        server.wait_for("configs", "put").await.unwrap();
+        server.push_event(event());
+        let resp = tokio::time::timeout(WAIT_TIMEOUT, stream.next())
+            .await
+            .unwrap();
+        assert_eq!(resp.unwrap().event.unwrap().event_code, "txPool");
+    }
+}
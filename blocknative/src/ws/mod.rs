@@ -1,2 +1,6 @@
+pub mod filters;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod models;
+pub mod replay;
 pub mod ws;
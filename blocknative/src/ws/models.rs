@@ -157,36 +157,294 @@ pub struct Transaction {
     pub gas_info: GasInfo,
     #[serde(rename = "type")]
     pub type_field: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_list: Option<Vec<AccessListEntry>>,
     pub asset: String,
     #[serde(flatten)]
     pub watch_info: Option<WatchedAddressInfo>,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessListEntry {
+    pub address: String,
+    pub storage_keys: Vec<String>,
+}
+
+#[derive(Error, Debug)]
+/// Error thrown when a Blocknative transaction can't be converted into an ethers type
+pub enum ConversionError {
+    /// A numeric field could not be parsed
+    #[error("invalid {field}: {value:?}")]
+    InvalidNumber { field: &'static str, value: String },
+
+    /// An address or hash field could not be parsed
+    #[error("invalid {field}: {value:?}")]
+    InvalidHex { field: &'static str, value: String },
+
+    /// The event doesn't carry a transaction
+    #[error("event has no transaction")]
+    MissingTransaction,
+
+    /// Typed transactions need a chain id, which Blocknative doesn't always send
+    #[error("missing chain id for typed transaction {0}")]
+    MissingChainId(String),
+
+    /// The transaction type is neither legacy, EIP-2930 nor EIP-1559
+    #[error("unsupported transaction type {0}")]
+    UnsupportedType(i64),
+
+    /// The fee fields don't match the transaction type
+    #[error("gas info {gas_info:?} doesn't match transaction type {type_field:?}")]
+    GasInfoMismatch {
+        type_field: Option<i64>,
+        gas_info: GasInfo,
+    },
+}
+
 #[cfg(feature = "ethers")]
-impl From<Transaction> for ethers::prelude::Transaction {
-    fn from(val: Transaction) -> Self {
-        ethers::prelude::Transaction {
-            from: val.from.parse().unwrap(),
-            to: Some(val.to.parse().unwrap()),
-            gas: val.gas.into(),
-            gas_price: Some(val.gas_price.parse().unwrap()),
-            value: val.value.parse().unwrap(),
-            nonce: val.nonce.into(),
-            block_hash: None,
-            block_number: None,
-            transaction_index: None,
-            input: hex::decode(val.input.strip_prefix("0x").unwrap())
-                .unwrap()
-                .into(),
-            v: val.v.parse().unwrap(),
-            r: val.r.parse().unwrap(),
-            s: val.s.parse().unwrap(),
-            transaction_type: val.type_field.map(|n| n.into()),
-            access_list: None,
-            max_priority_fee_per_gas: None,
-            max_fee_per_gas: None,
-            chain_id: None,
-            hash: val.hash.parse().unwrap(),
+mod conversion {
+    use std::convert::TryFrom;
+
+    use ethers::prelude::{
+        transaction::eip2718::TypedTransaction,
+        transaction::eip2930::{AccessList, AccessListItem},
+        Address, Bytes, Eip1559TransactionRequest, Eip2930TransactionRequest, NameOrAddress,
+        Signature, TransactionRequest, H256, U256, U64,
+    };
+    use ethers::utils::keccak256;
+
+    use super::{ConversionError, Event, GasInfo, Transaction};
+
+    fn strip(value: &str) -> &str {
+        value.strip_prefix("0x").unwrap_or(value)
+    }
+
+    fn dec_u256(field: &'static str, value: &str) -> Result<U256, ConversionError> {
+        U256::from_dec_str(value).map_err(|_| ConversionError::InvalidNumber {
+            field,
+            value: value.to_string(),
+        })
+    }
+
+    fn hex_u256(field: &'static str, value: &str) -> Result<U256, ConversionError> {
+        U256::from_str_radix(strip(value), 16).map_err(|_| ConversionError::InvalidNumber {
+            field,
+            value: value.to_string(),
+        })
+    }
+
+    fn hex_bytes(field: &'static str, value: &str) -> Result<Bytes, ConversionError> {
+        hex::decode(strip(value))
+            .map(Bytes::from)
+            .map_err(|_| ConversionError::InvalidHex {
+                field,
+                value: value.to_string(),
+            })
+    }
+
+    fn address(field: &'static str, value: &str) -> Result<Address, ConversionError> {
+        value.parse().map_err(|_| ConversionError::InvalidHex {
+            field,
+            value: value.to_string(),
+        })
+    }
+
+    fn hash(field: &'static str, value: &str) -> Result<H256, ConversionError> {
+        value.parse().map_err(|_| ConversionError::InvalidHex {
+            field,
+            value: value.to_string(),
+        })
+    }
+
+    impl Transaction {
+        /// Chain id carried by the transaction itself: the explicit field if
+        /// present, otherwise recovered from an EIP-155 legacy `v`.
+        pub fn chain_id(&self) -> Result<Option<u64>, ConversionError> {
+            if let Some(chain_id) = &self.chain_id {
+                return Ok(Some(hex_u256("chainId", chain_id)?.low_u64()));
+            }
+            let v = hex_u256("v", &self.v)?.low_u64();
+            if self.type_field.unwrap_or(0) == 0 && v >= 35 {
+                return Ok(Some((v - 35) / 2));
+            }
+            Ok(None)
+        }
+
+        pub fn access_list(&self) -> Result<AccessList, ConversionError> {
+            let entries = self.access_list.as_deref().unwrap_or_default();
+            let mut items = Vec::with_capacity(entries.len());
+            for entry in entries {
+                let mut storage_keys = Vec::with_capacity(entry.storage_keys.len());
+                for key in &entry.storage_keys {
+                    storage_keys.push(hash("accessList.storageKeys", key)?);
+                }
+                items.push(AccessListItem {
+                    address: address("accessList.address", &entry.address)?,
+                    storage_keys,
+                });
+            }
+            Ok(AccessList(items))
+        }
+
+        pub fn signature(&self) -> Result<Signature, ConversionError> {
+            Ok(Signature {
+                r: hex_u256("r", &self.r)?,
+                s: hex_u256("s", &self.s)?,
+                v: hex_u256("v", &self.v)?.low_u64(),
+            })
+        }
+
+        /// Builds the unsigned typed transaction. `chain_id` is used when the
+        /// transaction doesn't carry one, e.g. `Blockchain::chain_id()` of the event.
+        pub fn typed_transaction(
+            &self,
+            chain_id: Option<u64>,
+        ) -> Result<TypedTransaction, ConversionError> {
+            let chain_id = self.chain_id()?.or(chain_id);
+            let to = if self.to.is_empty() {
+                None
+            } else {
+                Some(NameOrAddress::Address(address("to", &self.to)?))
+            };
+            let from = address("from", &self.from)?;
+            let value = dec_u256("value", &self.value)?;
+            let data = hex_bytes("input", &self.input)?;
+
+            match (self.type_field.unwrap_or(0), &self.gas_info) {
+                (0, GasInfo::Legacy { gas_price }) | (1, GasInfo::Legacy { gas_price }) => {
+                    let mut request = TransactionRequest {
+                        from: Some(from),
+                        to,
+                        gas: Some(self.gas.into()),
+                        gas_price: Some(dec_u256("gasPrice", gas_price)?),
+                        value: Some(value),
+                        data: Some(data),
+                        nonce: Some(self.nonce.into()),
+                        chain_id: chain_id.map(U64::from),
+                    };
+                    if self.type_field == Some(1) {
+                        let chain_id = chain_id
+                            .ok_or_else(|| ConversionError::MissingChainId(self.hash.clone()))?;
+                        request.chain_id = Some(chain_id.into());
+                        return Ok(TypedTransaction::Eip2930(Eip2930TransactionRequest::new(
+                            request,
+                            self.access_list()?,
+                        )));
+                    }
+                    Ok(TypedTransaction::Legacy(request))
+                }
+                (
+                    2,
+                    GasInfo::ERC1559 {
+                        max_fee_per_gas,
+                        max_priority_fee_per_gas,
+                        ..
+                    },
+                ) => {
+                    let chain_id = chain_id
+                        .ok_or_else(|| ConversionError::MissingChainId(self.hash.clone()))?;
+                    Ok(TypedTransaction::Eip1559(Eip1559TransactionRequest {
+                        from: Some(from),
+                        to,
+                        gas: Some(self.gas.into()),
+                        value: Some(value),
+                        data: Some(data),
+                        nonce: Some(self.nonce.into()),
+                        access_list: self.access_list()?,
+                        max_priority_fee_per_gas: Some(dec_u256(
+                            "maxPriorityFeePerGas",
+                            max_priority_fee_per_gas,
+                        )?),
+                        max_fee_per_gas: Some(dec_u256("maxFeePerGas", max_fee_per_gas)?),
+                        chain_id: Some(chain_id.into()),
+                    }))
+                }
+                (0..=2, gas_info) => Err(ConversionError::GasInfoMismatch {
+                    type_field: self.type_field,
+                    gas_info: gas_info.clone(),
+                }),
+                (other, _) => Err(ConversionError::UnsupportedType(other)),
+            }
+        }
+
+        /// Signed RLP encoding of the transaction, as it is submitted in a bundle.
+        pub fn rlp_signed(&self, chain_id: Option<u64>) -> Result<Bytes, ConversionError> {
+            Ok(self
+                .typed_transaction(chain_id)?
+                .rlp_signed(&self.signature()?))
+        }
+
+        /// Converts into an ethers `Transaction`, falling back to `chain_id` when
+        /// the transaction doesn't carry its own.
+        pub fn to_ethers(
+            &self,
+            chain_id: Option<u64>,
+        ) -> Result<ethers::prelude::Transaction, ConversionError> {
+            let typed = self.typed_transaction(chain_id)?;
+            let signature = self.signature()?;
+            let (gas_price, max_fee_per_gas, max_priority_fee_per_gas) = match &typed {
+                TypedTransaction::Eip1559(tx) => {
+                    (None, tx.max_fee_per_gas, tx.max_priority_fee_per_gas)
+                }
+                _ => (typed.gas_price(), None, None),
+            };
+            let access_list = match &typed {
+                TypedTransaction::Legacy(_) => None,
+                TypedTransaction::Eip2930(tx) => Some(tx.access_list.clone()),
+                TypedTransaction::Eip1559(tx) => Some(tx.access_list.clone()),
+            };
+            let rlp = typed.rlp_signed(&signature);
+            let computed = H256::from(keccak256(&rlp));
+            let hash = hash("hash", &self.hash)?;
+            if computed != hash {
+                tracing::warn!(?hash, ?computed, "blocknative tx hash doesn't match encoding");
+            }
+
+            Ok(ethers::prelude::Transaction {
+                hash,
+                nonce: self.nonce.into(),
+                from: typed.from().copied().unwrap_or_default(),
+                to: match typed.to() {
+                    Some(NameOrAddress::Address(to)) => Some(*to),
+                    _ => None,
+                },
+                value: typed.value().copied().unwrap_or_default(),
+                gas_price,
+                gas: self.gas.into(),
+                input: typed.data().cloned().unwrap_or_default(),
+                v: signature.v.into(),
+                r: signature.r,
+                s: signature.s,
+                transaction_type: self.type_field.map(|n| (n as u64).into()),
+                access_list,
+                max_priority_fee_per_gas,
+                max_fee_per_gas,
+                chain_id: typed.chain_id().map(|id| id.as_u64().into()),
+                ..Default::default()
+            })
+        }
+    }
+
+    impl TryFrom<Transaction> for ethers::prelude::Transaction {
+        type Error = ConversionError;
+
+        fn try_from(val: Transaction) -> Result<Self, Self::Error> {
+            val.to_ethers(None)
+        }
+    }
+
+    impl TryFrom<Event> for ethers::prelude::Transaction {
+        type Error = ConversionError;
+
+        /// Uses the event's blockchain for the chain id when the transaction omits it.
+        fn try_from(val: Event) -> Result<Self, Self::Error> {
+            let chain_id = val.blockchain.chain_id();
+            val.transaction
+                .ok_or(ConversionError::MissingTransaction)?
+                .to_ethers(chain_id)
         }
     }
 }
@@ -269,7 +527,7 @@ pub struct HelloMsg {
 #[serde(rename_all = "camelCase")]
 pub struct WatchConfig {
     pub scope: String,
-    pub filters: Vec<HashMap<String, String>>,
+    pub filters: Vec<HashMap<String, Value>>,
     pub watch_address: bool,
 }
 
@@ -335,4 +593,44 @@ mod tests {
         let json = r#"{"version":0,"serverVersion":"0.127.0","timeStamp":"2022-02-05T05:32:53.837Z","connectionId":"d4-bf0707bb-a594-478a-be8d-9cbe0bf9dc37","status":"ok","event":{"timeStamp":"2022-02-05T05:32:53.837Z","categoryCode":"activeAddress","eventCode":"txPool","dappId":"7d507b2c-48f2-48bb-bd79-fc16ced6f8cf","blockchain":{"system":"ethereum","network":"main"},"contractCall":{"methodName":"purchase","params":{"maturity":"1645171200","strike64x64":"55340232221128654848000","contractSize":"100000000000000000","isCall":false,"maxCost":"12288749964831000000"},"contractAddress":"0xa4492fcDa2520cB68657d220f4D4aE3116359C10","contractType":"customAbi"},"transaction":{"status":"pending","monitorId":"Geth_1_C_PROD","monitorVersion":"0.108.0","pendingTimeStamp":"2022-02-05T05:32:53.837Z","pendingBlockNumber":14144116,"hash":"0xd63c3f04c0f85f6bb5402644bbb09290148318c5acb9e9f17d0773e9a7492101","from":"0x1FF60C59246A7b6B4A5090218881Af7f844458b0","to":"0xa4492fcDa2520cB68657d220f4D4aE3116359C10","value":"0","gas":1003494,"nonce":708,"blockHash":null,"blockNumber":null,"v":"0x1","r":"0xacd250a48251b0a83e3d6fa5653c780aa67f838a338dbbcf06c09ddefe1aef35","s":"0x6b376e7ec18fcf302b87729b9155f9a078a70b0cc27b980ecf0249b0b0042f1f","input":"0x677956f100000000000000000000000000000000000000000000000000000000620f5200000000000000000000000000000000000000000000000bb80000000000000000000000000000000000000000000000000000000000000000016345785d8a00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000aa8a68fc035885c0","type":2,"maxFeePerGas":"5000000000","maxFeePerGasGwei":5,"maxPriorityFeePerGas":"1410000000","maxPriorityFeePerGasGwei":1.41,"asset":"ETH","estimatedBlocksUntilConfirmed":null,"watchedAddress":"0xa4492fcda2520cb68657d220f4d4ae3116359c10","direction":"incoming","counterparty":"0x1FF60C59246A7b6B4A5090218881Af7f844458b0"}},"dispatchTimestamp":"2022-02-05T05:32:53.849Z"}"#;
         let resp: Response = serde_json::from_str(json).unwrap();
     }
+
+    #[cfg(feature = "ethers")]
+    #[test]
+    fn test_convert_eip1559() {
+        use ethers::prelude::transaction::eip2718::TypedTransaction;
+        use ethers::prelude::H256;
+        use ethers::utils::keccak256;
+
+        let json = r#"{"status":"pending","monitorId":"Geth_1_C2_PROD","monitorVersion":"0.108.0","pendingTimeStamp":"2022-02-05T05:47:08.506Z","pendingBlockNumber":14144167,"hash":"0x90702ff7d6ceac84f889e0520ef2373dbfc7046a686c63a99433fbe93905ffff","from":"0x1FF60C59246A7b6B4A5090218881Af7f844458b0","to":"0xa4492fcDa2520cB68657d220f4D4aE3116359C10","value":"0","gas":1003544,"nonce":710,"blockHash":null,"blockNumber":null,"v":"0x1","r":"0x135a68dbc7fd639d8c1ec2b7f6a05e4748cb460a2662d852fd79c32cb846fca3","s":"0x11f6ee30bde1efdd58048b7179ddad945115979ffb077ed30b44af1be8317d25","input":"0x677956f100000000000000000000000000000000000000000000000000000000620f5200000000000000000000000000000000000000000000000bb80000000000000000000000000000000000000000000000000000000000000000016345785d8a00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000ac1541126c04cf00","type":2,"maxFeePerGas":"5000000000","maxFeePerGasGwei":5,"maxPriorityFeePerGas":"1500000000","maxPriorityFeePerGasGwei":1.5,"asset":"ETH","estimatedBlocksUntilConfirmed":null,"watchedAddress":"0xa4492fcda2520cb68657d220f4d4ae3116359c10","direction":"incoming","counterparty":"0x1FF60C59246A7b6B4A5090218881Af7f844458b0"}"#;
+        let tx: Transaction = serde_json::from_str(json).unwrap();
+
+        let typed = tx.typed_transaction(Blockchain::main().chain_id()).unwrap();
+        assert!(matches!(typed, TypedTransaction::Eip1559(_)));
+        assert_eq!(typed.chain_id(), Some(1u64.into()));
+
+        let rlp = tx.rlp_signed(Blockchain::main().chain_id()).unwrap();
+        assert_eq!(H256::from(keccak256(&rlp)), tx.hash.parse().unwrap());
+
+        // Without a chain id a typed transaction can't be encoded
+        assert!(matches!(
+            tx.typed_transaction(None),
+            Err(ConversionError::MissingChainId(_))
+        ));
+    }
+
+    #[cfg(feature = "ethers")]
+    #[test]
+    fn test_convert_legacy_eip155() {
+        use std::convert::TryFrom;
+
+        let json = r#"{"status":"pending","monitorId":"Geth_137_C_PROD","monitorVersion":"0.102.0","pendingTimeStamp":"2021-12-07T10:20:22.066Z","pendingBlockNumber":22235980,"hash":"0xe0b1cf2bea578f49ba78cacd0d12d9c013f07cdd987936e71965edf6bd972b78","from":"0x21F3bB63e775ccDf0CC04559Be142971D241aB0E","to":"0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff","value":"0","gas":387473,"nonce":45,"v":"0x136","r":"0xb1fa90713d69a05869823607cc4bc67de6c7d4599b9fe8b00c54d8bc902739f9","s":"0x297a6aba5a47be29475d037b41619ad4003048e82305f20a3b18927cbfe2a343","input":"0x38ed1739","gasPrice":"113000000000","gasPriceGwei":113,"asset":""}"#;
+        let tx: Transaction = serde_json::from_str(json).unwrap();
+        assert_eq!(tx.chain_id().unwrap(), Some(137));
+
+        let converted = ethers::prelude::Transaction::try_from(tx).unwrap();
+        assert_eq!(converted.gas_price, Some(113_000_000_000u64.into()));
+        assert_eq!(converted.chain_id, Some(137u64.into()));
+        assert_eq!(converted.max_fee_per_gas, None);
+        assert_eq!(converted.nonce, 45u64.into());
+    }
 }
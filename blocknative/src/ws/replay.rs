@@ -0,0 +1,346 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_tungstenite::tungstenite::Message;
+use chrono::Utc;
+use futures_channel::mpsc;
+use futures_util::{ready, Sink, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncWriteExt, BufWriter},
+    task::JoinHandle,
+};
+use tracing::warn;
+
+use super::ws::{ClientError, WsError, WsStreamItem};
+
+/// One incoming websocket frame, as stored on a line of a recording.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedFrame {
+    /// Milliseconds since the recording started
+    pub elapsed_ms: u64,
+    /// Wall clock time the frame was received
+    pub time_stamp: String,
+    /// Raw text of the frame
+    pub frame: String,
+}
+
+/// Reads a JSONL recording written by [`Recorder`].
+pub fn read_recording(path: impl AsRef<Path>) -> Result<Vec<RecordedFrame>, ClientError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut frames = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        frames.push(serde_json::from_str(&line)?);
+    }
+    Ok(frames)
+}
+
+/// Wraps a websocket transport and appends every incoming text frame to a JSONL file.
+///
+/// Frames are handed to a writer task, so polling the transport never waits on the disk.
+pub struct Recorder<S> {
+    inner: S,
+    frames: mpsc::UnboundedSender<RecordedFrame>,
+    writer: JoinHandle<()>,
+    started: Instant,
+}
+
+impl<S> Recorder<S> {
+    /// Creates (or truncates) the recording at `path`. Must be called from within a
+    /// Tokio runtime, which runs the writer task.
+    pub fn create(inner: S, path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let file = File::create(path)?;
+        let (frames, rx) = mpsc::unbounded();
+        Ok(Self {
+            inner,
+            frames,
+            writer: tokio::spawn(write_frames(file, rx)),
+            started: Instant::now(),
+        })
+    }
+
+    /// Stops recording. The handle resolves once every recorded frame is on disk.
+    pub fn finish(self) -> JoinHandle<()> {
+        self.writer
+    }
+
+    fn record(&self, text: &str) {
+        let frame = RecordedFrame {
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            time_stamp: Utc::now().to_rfc3339(),
+            frame: text.to_string(),
+        };
+        if self.frames.unbounded_send(frame).is_err() {
+            warn!("Failed to record frame: the writer stopped");
+        }
+    }
+}
+
+/// Appends `frames` to `file` as they come, flushing whenever it has caught up.
+async fn write_frames(file: File, mut frames: mpsc::UnboundedReceiver<RecordedFrame>) {
+    let mut writer = BufWriter::new(tokio::fs::File::from_std(file));
+    while let Some(frame) = frames.next().await {
+        let mut pending = vec![frame];
+        while let Ok(Some(frame)) = frames.try_next() {
+            pending.push(frame);
+        }
+        for frame in pending {
+            let mut line = match serde_json::to_vec(&frame) {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("Failed to record frame: {}", e);
+                    continue;
+                }
+            };
+            line.push(b'\n');
+            if let Err(e) = writer.write_all(&line).await {
+                warn!("Failed to record frame: {}", e);
+            }
+        }
+        if let Err(e) = writer.flush().await {
+            warn!("Failed to flush recording: {}", e);
+        }
+    }
+}
+
+impl<S> Stream for Recorder<S>
+where
+    S: Stream<Item = WsStreamItem> + Unpin,
+{
+    type Item = WsStreamItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = ready!(Pin::new(&mut self.inner).poll_next(cx));
+        if let Some(Ok(Message::Text(text))) = &item {
+            self.record(text);
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl<S> Sink<Message> for Recorder<S>
+where
+    S: Sink<Message, Error = WsError> + Unpin,
+{
+    type Error = WsError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), WsError> {
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Pace at which a [`Replay`] emits its frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Keep the gaps between frames as they were recorded
+    Original,
+    /// Divide the recorded gaps by the given factor
+    Accelerated(f64),
+    /// Emit every frame as soon as possible
+    Instant,
+}
+
+impl ReplaySpeed {
+    fn delay(&self, gap_ms: u64) -> Option<Duration> {
+        match self {
+            ReplaySpeed::Original => Some(Duration::from_millis(gap_ms)),
+            ReplaySpeed::Accelerated(factor) if *factor > 0.0 => {
+                Some(Duration::from_secs_f64(gap_ms as f64 / 1000.0 / factor))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Messages the client sent through a [`Replay`] transport.
+pub type SentMessages = Arc<Mutex<Vec<Message>>>;
+
+/// Websocket transport that plays a recording back instead of talking to Blocknative.
+///
+/// Playback starts once the client sends its first text message (the handshake or
+/// the filter `put`), so subscriptions registered before that see every frame. The
+/// stream ends after the last frame, which the client treats as a closed connection.
+pub struct Replay {
+    frames: Option<Vec<RecordedFrame>>,
+    speed: ReplaySpeed,
+    incoming: mpsc::UnboundedReceiver<Message>,
+    incoming_tx: Option<mpsc::UnboundedSender<Message>>,
+    sent: SentMessages,
+}
+
+impl Replay {
+    pub fn new(frames: Vec<RecordedFrame>, speed: ReplaySpeed) -> Self {
+        let (incoming_tx, incoming) = mpsc::unbounded();
+        Self {
+            frames: Some(frames),
+            speed,
+            incoming,
+            incoming_tx: Some(incoming_tx),
+            sent: Default::default(),
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>, speed: ReplaySpeed) -> Result<Self, ClientError> {
+        Ok(Self::new(read_recording(path)?, speed))
+    }
+
+    /// Handle on everything the client sends, to assert on requests in tests.
+    pub fn sent_messages(&self) -> SentMessages {
+        self.sent.clone()
+    }
+
+    fn start(&mut self) {
+        let (frames, tx) = match (self.frames.take(), self.incoming_tx.take()) {
+            (Some(frames), Some(tx)) => (frames, tx),
+            _ => return,
+        };
+        let speed = self.speed;
+        tokio::spawn(async move {
+            let mut last_ms = frames.first().map(|f| f.elapsed_ms).unwrap_or_default();
+            for frame in frames {
+                if let Some(delay) = speed.delay(frame.elapsed_ms.saturating_sub(last_ms)) {
+                    tokio::time::sleep(delay).await;
+                }
+                last_ms = frame.elapsed_ms;
+                if tx.unbounded_send(Message::Text(frame.frame)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+impl Stream for Replay {
+    type Item = WsStreamItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.incoming)
+            .poll_next(cx)
+            .map(|msg| msg.map(Ok))
+    }
+}
+
+impl Sink<Message> for Replay {
+    type Error = WsError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), WsError> {
+        let is_text = matches!(item, Message::Text(_));
+        self.sent.lock().unwrap().push(item);
+        if is_text {
+            self.start();
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), WsError>> {
+        self.incoming.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use futures_util::StreamExt;
+
+    use crate::{
+        models::Blockchain,
+        ws::{models::WatchConfig, ws::Ws},
+    };
+
+    use super::*;
+
+    fn response(connection_id: &str) -> String {
+        format!(
+            r#"{{"version":0,"serverVersion":"0.127.0","timeStamp":"2022-02-05T05:47:08.506Z","connectionId":"{}","status":"ok"}}"#,
+            connection_id
+        )
+    }
+
+    #[tokio::test]
+    async fn test_recording_round_trip() {
+        // Unique per run, so concurrent test runs don't share the file
+        let path = std::env::temp_dir().join(format!(
+            "blocknative_recording_round_trip_{}_{}.jsonl",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let recorder = Recorder::create((), &path).unwrap();
+        recorder.record(&response("a"));
+        recorder.record(&response("b"));
+        recorder.finish().await.unwrap();
+
+        let frames = read_recording(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].frame, response("b"));
+        assert!(frames[0].elapsed_ms <= frames[1].elapsed_ms);
+    }
+
+    #[tokio::test]
+    async fn test_replay_drives_client() {
+        let frames = ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, id)| RecordedFrame {
+                elapsed_ms: i as u64 * 1000,
+                time_stamp: String::new(),
+                frame: response(id),
+            })
+            .collect();
+        let replay = Replay::new(frames, ReplaySpeed::Accelerated(1000.0));
+        let sent = replay.sent_messages();
+
+        let ws = Ws::new(replay, "", Blockchain::main());
+        let config = WatchConfig {
+            scope: "".to_string(),
+            filters: vec![HashMap::new()],
+            watch_address: true,
+        };
+        let stream = ws.listen(config).await.unwrap();
+
+        let ids: Vec<String> = stream.map(|resp| resp.connection_id).collect().await;
+        assert_eq!(ids, vec!["a", "b", "c"]);
+        assert!(sent
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|msg| matches!(msg, Message::Text(text) if text.contains("\"put\""))));
+    }
+}
@@ -1,6 +1,7 @@
 use std::{
     collections::{btree_map::Entry, BTreeMap},
     fmt::{self, Debug},
+    time::Duration,
 };
 
 use async_tungstenite;
@@ -17,6 +18,8 @@ use tracing::{debug, error, warn};
 
 use crate::models::Blockchain;
 
+use super::replay::Recorder;
+
 use super::models::{
     HelloMsg, JsonRpcError, Request, Response, TransactionSubscribe, WatchConfig, WatchRequest,
 };
@@ -26,8 +29,8 @@ use super::models::{
 type Pending = oneshot::Sender<Result<serde_json::Value, JsonRpcError>>;
 type Subscription = mpsc::UnboundedSender<Response>;
 
-type WsError = async_tungstenite::tungstenite::Error;
-type WsStreamItem = Result<Message, WsError>;
+pub(crate) type WsError = async_tungstenite::tungstenite::Error;
+pub(crate) type WsStreamItem = Result<Message, WsError>;
 
 /// Instructions for the `WsServer`.
 #[derive(Debug)]
@@ -81,9 +84,9 @@ impl Ws {
 
         let mut ping_sink = sink.clone();
         tokio::task::spawn(async move {
-            loop {
-                ping_sink.send(Instruction::Ping).await.unwrap();
-                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            // Stops once the server, and with it the connection, is gone
+            while ping_sink.send(Instruction::Ping).await.is_ok() {
+                tokio::time::sleep(Duration::from_secs(60)).await;
             }
         });
 
@@ -111,7 +114,21 @@ impl Ws {
         println!("Connecting to socket");
         let (ws, _) = async_tungstenite::async_std::connect_async(url).await?;
         let me = Self::new(ws, api_key, blockchain);
-        me.cast("initialize", "checkDappId", ()).await.unwrap();
+        me.cast("initialize", "checkDappId", ()).await?;
+        Ok(me)
+    }
+
+    /// Initializes a new WebSocket Client which records every incoming frame to `path`.
+    /// The recording can be played back with [`Replay`](super::replay::Replay).
+    pub async fn connect_recording(
+        url: impl async_tungstenite::tungstenite::client::IntoClientRequest + Unpin,
+        api_key: &str,
+        blockchain: Blockchain,
+        path: impl AsRef<std::path::Path>,
+    ) -> Result<Self, ClientError> {
+        let (ws, _) = async_tungstenite::async_std::connect_async(url).await?;
+        let me = Self::new(Recorder::create(ws, path)?, api_key, blockchain);
+        me.cast("initialize", "checkDappId", ()).await?;
         Ok(me)
     }
 
@@ -157,16 +174,52 @@ impl Ws {
         let req = WatchRequest { config };
         println!("{:?}", req.config);
 
-        self.cast("configs", "put", req).await.unwrap();
-
+        // Register the subscription before the filter is sent so that no event
+        // arriving right after the `put` is dropped.
         self.send(Instruction::Subscribe {
             id: 1u32.into(),
             sink,
         })?;
 
+        self.cast("configs", "put", req).await?;
+
         Ok(stream)
     }
 
+    /// Subscribes to `config` on `url` and subscribes again on a new connection,
+    /// `retry` after the previous one failed or closed. Stops reconnecting once the
+    /// returned stream is dropped.
+    pub fn listen_reconnecting(
+        url: String,
+        api_key: &str,
+        blockchain: Blockchain,
+        config: WatchConfig,
+        retry: Duration,
+    ) -> NotificationStream {
+        let (sink, stream) = mpsc::unbounded();
+        let api_key = api_key.to_string();
+        tokio::spawn(async move {
+            while !sink.is_closed() {
+                match Self::connect(url.as_str(), &api_key, blockchain.clone()).await {
+                    Ok(ws) => match ws.listen(config.clone()).await {
+                        Ok(mut responses) => {
+                            while let Some(response) = responses.next().await {
+                                if sink.unbounded_send(response).is_err() {
+                                    return;
+                                }
+                            }
+                            println!("Connection closed, reconnecting in {:?}", retry);
+                        }
+                        Err(e) => println!("Failed to subscribe: {}", e),
+                    },
+                    Err(e) => println!("Failed to connect: {}", e),
+                }
+                tokio::time::sleep(retry).await;
+            }
+        });
+        stream
+    }
+
     pub async fn unsubscribe<T: Into<u64>>(&self, id: T) -> Result<(), ClientError> {
         self.cast(
             "activeTransaction",
@@ -175,8 +228,7 @@ impl Ws {
                 "0x0b4c94c414f71ddd5e7a625fcaa83ff1f93e9a7ca37e0f577b488ac8fd786655".to_string(),
             ),
         )
-        .await
-        .unwrap();
+        .await?;
         self.send(Instruction::Unsubscribe { id: id.into() })
     }
 }
@@ -417,17 +469,19 @@ pub enum ClientError {
 
     #[error("Received an unexpected frame")]
     UnexpectedFrame,
+
+    /// Thrown if a recording can't be read or written
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
 }
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
-    use tokio::fs::read_to_string;
-
     use crate::{
         models::{Network, System},
-        ws::models::WatchConfig,
+        ws::{mock::MockServer, models::WatchConfig},
     };
 
     use super::*;
@@ -438,19 +492,15 @@ mod tests {
             system: System::Ethereum,
             network: Network::Polygon,
         };
-        let ws = Ws::connect("wss://api.blocknative.com/v0", "", bc)
-            .await
-            .unwrap();
-
-        let s = read_to_string("examples/quickswap.json").await.unwrap();
-        let abi = serde_json::from_str(&s).unwrap();
+        let server = MockServer::start().await.unwrap();
+        let ws = Ws::connect(server.url(), "", bc).await.unwrap();
 
         let mut filters = HashMap::new();
         filters.insert(
             "contractCall.params.path".to_string(),
-            "0xC250e9987A032ACAC293d838726C511E6E1C029d".to_string(),
+            "0xC250e9987A032ACAC293d838726C511E6E1C029d".into(),
         );
-      
+
         let config = WatchConfig {
             scope: "".to_string(),
             filters: vec![filters],
@@ -458,14 +508,16 @@ mod tests {
         };
 
         let mut stream = ws.listen(config).await.unwrap();
+        let put = server.wait_for("configs", "put").await.unwrap();
+        assert_eq!(
+            put["config"]["filters"][0]["contractCall.params.path"],
+            "0xC250e9987A032ACAC293d838726C511E6E1C029d"
+        );
 
-        while let Some(event) = stream.next().await {
-            println!("got event: {:?}", event);
-            let txn = event.event.unwrap().transaction.unwrap();
-            // let ether_tx: ethers::prelude::Transaction = txn.into();
+        server.push_event(serde_json::from_str(r#"{"timeStamp":"2021-12-07T10:20:25.212Z","categoryCode":"activeAddress","eventCode":"txPool","dappId":"","blockchain":{"system":"ethereum","network":"matic-main"},"transaction":{"status":"pending","monitorId":"Geth_137_C_PROD","monitorVersion":"0.102.0","pendingTimeStamp":"2021-12-07T10:20:22.066Z","pendingBlockNumber":22235980,"hash":"0xe0b1cf2bea578f49ba78cacd0d12d9c013f07cdd987936e71965edf6bd972b78","from":"0x21F3bB63e775ccDf0CC04559Be142971D241aB0E","to":"0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff","value":"0","gas":387473,"nonce":45,"v":"0x136","r":"0xb1fa90713d69a05869823607cc4bc67de6c7d4599b9fe8b00c54d8bc902739f9","s":"0x297a6aba5a47be29475d037b41619ad4003048e82305f20a3b18927cbfe2a343","input":"0x38ed1739","gasPrice":"113000000000","gasPriceGwei":113,"asset":""}}"#).unwrap());
 
-            // println("")
-            break;
-        }
+        let event = stream.next().await.unwrap();
+        let txn = event.event.unwrap().transaction.unwrap();
+        assert_eq!(txn.nonce, 45);
     }
 }
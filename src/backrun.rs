@@ -0,0 +1,215 @@
+//! Composition of backrun bundles: the victims' raw signed transactions from the
+//! mempool, followed by our arbitrage.
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use blocknative::ws::models::{ConversionError, Transaction as BlocknativeTransaction};
+use ethers::prelude::*;
+use ethers_flashbots::BundleRequest;
+use serde::{Deserialize, Serialize};
+
+/// A pending transaction we backrun, as signed by its sender.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Victim {
+    pub hash: H256,
+    /// Raw signed transaction, as it would be broadcast
+    pub raw: Bytes,
+    pub from: Address,
+    pub nonce: U256,
+    pub gas_price: Option<U256>,
+    pub max_fee_per_gas: Option<U256>,
+    pub max_priority_fee_per_gas: Option<U256>,
+    /// Pools the transaction trades on
+    pub pools: Vec<Address>,
+}
+
+impl Victim {
+    /// Builds a victim from a Blocknative mempool transaction. `chain_id` is used when
+    /// the transaction doesn't carry its own.
+    pub fn from_blocknative(
+        tx: &BlocknativeTransaction,
+        chain_id: Option<u64>,
+        pools: Vec<Address>,
+    ) -> Result<Self, ConversionError> {
+        let raw = tx.rlp_signed(chain_id)?;
+        let tx = tx.to_ethers(chain_id)?;
+        Ok(Self {
+            hash: tx.hash,
+            raw,
+            from: tx.from,
+            nonce: tx.nonce,
+            gas_price: tx.gas_price,
+            max_fee_per_gas: tx.max_fee_per_gas,
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas,
+            pools,
+        })
+    }
+
+    /// Tip per gas the block builder earns from the transaction at `base_fee`, or
+    /// `None` if it can't be included at that base fee.
+    pub fn effective_tip(&self, base_fee: U256) -> Option<U256> {
+        let max_fee = self.max_fee_per_gas.or(self.gas_price)?;
+        let headroom = max_fee.checked_sub(base_fee)?;
+        Some(match self.max_priority_fee_per_gas {
+            Some(priority_fee) => priority_fee.min(headroom),
+            None => headroom,
+        })
+    }
+
+    pub fn touches(&self, pools: &[Address]) -> bool {
+        self.pools.iter().any(|pool| pools.contains(pool))
+    }
+}
+
+/// Orders victims the way a builder would include them at `base_fee`: highest tip
+/// first, transactions of one sender by nonce. Victims which can't pay the base fee
+/// are dropped, with every later nonce of their sender.
+pub fn order_victims(victims: &[Victim], base_fee: U256) -> Vec<Victim> {
+    // Lowest nonce each sender can't get included
+    let mut blocked: HashMap<Address, U256> = HashMap::new();
+    for victim in victims {
+        if victim.effective_tip(base_fee).is_none() {
+            let nonce = blocked.entry(victim.from).or_insert(victim.nonce);
+            *nonce = (*nonce).min(victim.nonce);
+        }
+    }
+    let mut ordered: Vec<(U256, &Victim)> = victims
+        .iter()
+        .filter(|victim| match blocked.get(&victim.from) {
+            Some(nonce) => victim.nonce < *nonce,
+            None => true,
+        })
+        .filter_map(|victim| Some((victim.effective_tip(base_fee)?, victim)))
+        .collect();
+    ordered.sort_by(|(tip_a, a), (tip_b, b)| tip_b.cmp(tip_a).then(a.hash.cmp(&b.hash)));
+
+    // Keep the tip order between senders, but the nonce order within a sender
+    let mut by_sender: HashMap<Address, Vec<&Victim>> = HashMap::new();
+    for (_, victim) in &ordered {
+        by_sender.entry(victim.from).or_default().push(victim);
+    }
+    for txs in by_sender.values_mut() {
+        txs.sort_by_key(|tx| std::cmp::Reverse(tx.nonce));
+    }
+    ordered
+        .iter()
+        .map(|(_, victim)| {
+            by_sender
+                .get_mut(&victim.from)
+                .and_then(|txs| txs.pop())
+                .unwrap()
+                .clone()
+        })
+        .collect()
+}
+
+/// Bundle of the victims touching `pools`, in predicted inclusion order, followed by
+/// our signed transactions. Victims may revert without invalidating the bundle; our
+/// transactions may not.
+pub fn compose(
+    victims: &[Victim],
+    pools: &[Address],
+    ours: Vec<Bytes>,
+    base_fee: U256,
+) -> (BundleRequest, Vec<Victim>) {
+    let touching: Vec<Victim> = victims
+        .iter()
+        .filter(|victim| victim.touches(pools))
+        .cloned()
+        .collect();
+    let ordered = order_victims(&touching, base_fee);
+
+    let mut bundle = BundleRequest::new();
+    for victim in &ordered {
+        bundle = bundle.push_revertible_transaction(victim.raw.clone());
+    }
+    for tx in ours {
+        bundle = bundle.push_transaction(tx);
+    }
+    (bundle, ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::gwei;
+
+    fn victim(id: u8, from: u8, nonce: u64, priority_fee: u64) -> Victim {
+        Victim {
+            hash: H256::repeat_byte(id),
+            raw: Bytes::from(vec![id]),
+            from: Address::repeat_byte(from),
+            nonce: U256::from(nonce),
+            gas_price: None,
+            max_fee_per_gas: Some(gwei(100)),
+            max_priority_fee_per_gas: Some(gwei(priority_fee)),
+            pools: vec![Address::repeat_byte(0xaa)],
+        }
+    }
+
+    #[test]
+    fn test_effective_tip() {
+        let legacy = Victim {
+            gas_price: Some(gwei(40)),
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            ..victim(1, 1, 0, 0)
+        };
+        assert_eq!(legacy.effective_tip(gwei(30)), Some(gwei(10)));
+        assert_eq!(legacy.effective_tip(gwei(50)), None);
+        assert_eq!(victim(1, 1, 0, 2).effective_tip(gwei(30)), Some(gwei(2)));
+        assert_eq!(victim(1, 1, 0, 2).effective_tip(gwei(99)), Some(gwei(1)));
+    }
+
+    #[test]
+    fn test_order_victims() {
+        let victims = vec![
+            victim(1, 1, 5, 1),
+            victim(2, 2, 0, 3),
+            // Higher tip, but must follow nonce 5 of the same sender
+            victim(3, 1, 6, 9),
+        ];
+        let hashes: Vec<H256> = order_victims(&victims, gwei(30))
+            .iter()
+            .map(|v| v.hash)
+            .collect();
+        assert_eq!(
+            hashes,
+            vec![H256::repeat_byte(1), H256::repeat_byte(2), H256::repeat_byte(3)]
+        );
+    }
+
+    #[test]
+    fn test_order_victims_drops_later_nonces() {
+        let mut underpriced = victim(2, 1, 6, 1);
+        underpriced.max_fee_per_gas = Some(gwei(10));
+        let victims = vec![
+            victim(1, 1, 5, 1),
+            underpriced,
+            // Can't be included before nonce 6
+            victim(3, 1, 7, 9),
+            victim(4, 2, 0, 3),
+        ];
+        let hashes: Vec<H256> = order_victims(&victims, gwei(30))
+            .iter()
+            .map(|v| v.hash)
+            .collect();
+        assert_eq!(hashes, vec![H256::repeat_byte(4), H256::repeat_byte(1)]);
+    }
+
+    #[test]
+    fn test_compose_skips_unrelated_victims() {
+        let mut unrelated = victim(4, 4, 0, 5);
+        unrelated.pools = vec![Address::repeat_byte(0xbb)];
+        let victims = vec![victim(1, 1, 0, 1), unrelated];
+
+        let (bundle, ordered) = compose(
+            &victims,
+            &[Address::repeat_byte(0xaa)],
+            vec![Bytes::from(vec![9])],
+            gwei(30),
+        );
+        assert_eq!(ordered.len(), 1);
+        assert_eq!(bundle.transactions().len(), 2);
+    }
+}
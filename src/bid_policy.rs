@@ -0,0 +1,468 @@
+//! Pricing of flashbots bundles.
+//!
+//! A [`BidPolicy`] decides how much of a bundle's expected profit goes to the block
+//! builder, whether it is paid through the executor contract (`miner_tip`, a
+//! `block.coinbase` transfer) or through the priority fee, and fills the EIP-1559 fee
+//! fields and gas limits of our own transactions from a simulation.
+use std::env;
+
+use ethers::core::types::transaction::eip2718::TypedTransaction;
+use ethers::prelude::*;
+use num::rational::Ratio;
+
+use crate::fee_predictor::FeeEstimate;
+
+/// Env var overriding the share of the profit tipped, in basis points.
+pub const TIP_SHARE_ENV: &str = "BACKRUNNER_TIP_SHARE_BPS";
+/// Env var choosing how the tip is paid: `coinbase` or `priority`.
+pub const TIP_MODE_ENV: &str = "BACKRUNNER_TIP_MODE";
+/// Env var overriding the least tip worth sending, in wei.
+pub const MIN_TIP_ENV: &str = "BACKRUNNER_MIN_TIP_WEI";
+/// Env var overriding the gas limit used before any gas was measured.
+pub const FALLBACK_GAS_ENV: &str = "BACKRUNNER_FALLBACK_GAS";
+
+/// EIP-1559 `BASE_FEE_MAX_CHANGE_DENOMINATOR`: a full block raises the base fee by
+/// at most 1/8
+pub const BASE_FEE_MAX_CHANGE_DENOMINATOR: u64 = 8;
+/// EIP-1559 `ELASTICITY_MULTIPLIER`
+pub const ELASTICITY_MULTIPLIER: u64 = 2;
+
+/// How the builder tip reaches `block.coinbase`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TipMode {
+    /// The executor contract transfers `miner_tip` to `block.coinbase`, only if the
+    /// arbitrage succeeds. Transactions carry a zero priority fee.
+    CoinbaseTransfer,
+    /// The tip is spread over the gas used as priority fee. Paid even on revert.
+    PriorityFee,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BidPolicy {
+    /// Share of the expected profit paid to the builder
+    pub tip_share: Ratio<u64>,
+    pub tip_mode: TipMode,
+    /// Tip below which a bundle isn't worth sending
+    pub min_tip: U256,
+    /// Headroom on the next base fee for `max_fee_per_gas`, so the bundle stays valid
+    /// when targeting later blocks
+    pub base_fee_headroom: Ratio<u64>,
+    /// Headroom on the simulated gas used for the gas limit
+    pub gas_headroom: Ratio<u64>,
+    /// Gas limit used for the first simulation, before any gas was measured
+    pub fallback_gas: U256,
+}
+
+impl Default for BidPolicy {
+    fn default() -> Self {
+        Self {
+            tip_share: Ratio::new(80, 100),
+            tip_mode: TipMode::CoinbaseTransfer,
+            min_tip: U256::zero(),
+            base_fee_headroom: Ratio::new(2, 1),
+            gas_headroom: Ratio::new(12, 10),
+            fallback_gas: U256::from(400000),
+        }
+    }
+}
+
+/// Why `BidPolicy::bid_with` wouldn't price a bundle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BidRejection {
+    /// The tip on the profit is below `min_tip`
+    TipBelowMinimum { tip: U256, minimum: U256 },
+    /// The tip paid as priority fee over the gas used is below what recent blocks paid
+    PriorityFeeBelowMarket { required: U256, affordable: U256 },
+    /// The gas used costs more at the next base fee than the profit
+    GasAboveProfit { gas_cost: U256, profit: U256 },
+}
+
+/// Fees and gas of one of our bundle transactions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bid {
+    /// Amount to encode as `miner_tip`
+    pub coinbase_tip: U256,
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+    pub gas_limit: U256,
+}
+
+impl Bid {
+    /// Total paid to the builder if the transaction uses `gas_used`.
+    pub fn builder_payment(&self, gas_used: U256) -> U256 {
+        self.coinbase_tip + self.max_priority_fee_per_gas * gas_used
+    }
+}
+
+fn mul_ratio(amount: U256, ratio: &Ratio<u64>) -> U256 {
+    amount * U256::from(*ratio.numer()) / U256::from(*ratio.denom())
+}
+
+/// Base fee of the block following one with the given base fee and gas usage.
+pub fn next_base_fee(base_fee: U256, gas_used: U256, gas_limit: U256) -> U256 {
+    let gas_target = gas_limit / ELASTICITY_MULTIPLIER;
+    if gas_target.is_zero() || gas_used == gas_target {
+        return base_fee;
+    }
+    let denominator = gas_target * BASE_FEE_MAX_CHANGE_DENOMINATOR;
+    if gas_used > gas_target {
+        let delta = base_fee * (gas_used - gas_target) / denominator;
+        base_fee + delta.max(U256::one())
+    } else {
+        let delta = base_fee * (gas_target - gas_used) / denominator;
+        base_fee.saturating_sub(delta)
+    }
+}
+
+/// Base fee of the block following `block`, zero for pre-London blocks.
+pub fn next_base_fee_of<T>(block: &Block<T>) -> U256 {
+    match block.base_fee_per_gas {
+        Some(base_fee) => next_base_fee(base_fee, block.gas_used, block.gas_limit),
+        None => U256::zero(),
+    }
+}
+
+impl BidPolicy {
+    /// Reads `BACKRUNNER_TIP_SHARE_BPS`, `BACKRUNNER_TIP_MODE`, `BACKRUNNER_MIN_TIP_WEI`
+    /// and `BACKRUNNER_FALLBACK_GAS`, keeping the default for any that is unset.
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+        if let Some(bps) = env::var(TIP_SHARE_ENV)
+            .ok()
+            .and_then(|bps| bps.parse::<u64>().ok())
+        {
+            policy.tip_share = Ratio::new(bps.min(10_000), 10_000);
+        }
+        match env::var(TIP_MODE_ENV).as_deref() {
+            Ok("coinbase") => policy.tip_mode = TipMode::CoinbaseTransfer,
+            Ok("priority") => policy.tip_mode = TipMode::PriorityFee,
+            _ => {}
+        }
+        if let Some(min_tip) = env::var(MIN_TIP_ENV)
+            .ok()
+            .and_then(|wei| U256::from_dec_str(&wei).ok())
+        {
+            policy.min_tip = min_tip;
+        }
+        if let Some(gas) = env::var(FALLBACK_GAS_ENV)
+            .ok()
+            .and_then(|gas| U256::from_dec_str(&gas).ok())
+        {
+            policy.fallback_gas = gas;
+        }
+        policy
+    }
+
+    /// Total tip for a bundle expected to make `profit` wei.
+    pub fn tip(&self, profit: U256) -> U256 {
+        mul_ratio(profit, &self.tip_share)
+    }
+
+    /// Amount the executor contract should transfer to `block.coinbase`.
+    pub fn coinbase_tip(&self, profit: U256) -> U256 {
+        match self.tip_mode {
+            TipMode::CoinbaseTransfer => self.tip(profit),
+            TipMode::PriorityFee => U256::zero(),
+        }
+    }
+
+    /// Same as `coinbase_tip` for a profit made in `token`. The executor can only
+    /// unwrap a WETH profit, the tip on any other is left to the priority fee.
+    pub fn coinbase_tip_in(&self, token: Address, weth: Address, profit: U256) -> U256 {
+        if token == weth {
+            self.coinbase_tip(profit)
+        } else {
+            U256::zero()
+        }
+    }
+
+    /// Profit a coinbase transfer of `coinbase_tip` was sized on, `None` unless the
+    /// tip is paid that way.
+    pub fn tipped_profit(&self, coinbase_tip: U256) -> Option<U256> {
+        if self.tip_mode != TipMode::CoinbaseTransfer {
+            return None;
+        }
+        self.profit_of_tip(coinbase_tip)
+    }
+
+    /// Profit a tip of `tip`, however it is paid, was sized on. `None` for a zero
+    /// `tip_share`.
+    pub fn profit_of_tip(&self, tip: U256) -> Option<U256> {
+        if *self.tip_share.numer() == 0 {
+            return None;
+        }
+        Some(tip * U256::from(*self.tip_share.denom()) / U256::from(*self.tip_share.numer()))
+    }
+
+    /// Gas limit for a transaction which used `gas_used` in simulation.
+    pub fn gas_limit(&self, gas_used: U256) -> U256 {
+        if gas_used.is_zero() {
+            return self.fallback_gas;
+        }
+        mul_ratio(gas_used, &self.gas_headroom)
+    }
+
+    /// Prices a transaction expected to make `profit` wei while using `gas_used`,
+    /// or `None` if the tip falls below `min_tip`.
+    pub fn bid(&self, profit: U256, gas_used: U256, next_base_fee: U256) -> Option<Bid> {
+        self.bid_over(profit, self.coinbase_tip(profit), gas_used, next_base_fee)
+    }
+
+    /// Same as `bid`, for transactions transferring `coinbase_tip` to `block.coinbase`
+    /// whatever the tip mode. The rest of the tip is paid as priority fee.
+    pub fn bid_over(
+        &self,
+        profit: U256,
+        coinbase_tip: U256,
+        gas_used: U256,
+        next_base_fee: U256,
+    ) -> Option<Bid> {
+        let tip = self.tip(profit);
+        if tip < self.min_tip {
+            return None;
+        }
+
+        let gas_limit = self.gas_limit(gas_used);
+        // Without a measurement the transaction may burn up to its whole limit, and the
+        // tip is spread over that so the builder is never paid more than it
+        let tipped_gas = if gas_used.is_zero() {
+            gas_limit
+        } else {
+            gas_used
+        };
+        let priority_fee = tip.saturating_sub(coinbase_tip) / tipped_gas.max(U256::one());
+
+        Some(Bid {
+            coinbase_tip,
+            max_fee_per_gas: mul_ratio(next_base_fee, &self.base_fee_headroom) + priority_fee,
+            max_priority_fee_per_gas: priority_fee,
+            gas_limit,
+        })
+    }
+
+    /// Same as `bid_over`, at the predicted base fee. A tip paid only as priority fee
+    /// below what recent blocks paid wouldn't get the transaction ahead of the public
+    /// ones, and isn't worth sending.
+    pub fn bid_with(
+        &self,
+        profit: U256,
+        coinbase_tip: U256,
+        gas_used: U256,
+        fees: &FeeEstimate,
+    ) -> Result<Bid, BidRejection> {
+        let gas_cost = gas_used.saturating_mul(fees.next_base_fee);
+        if gas_cost > profit {
+            return Err(BidRejection::GasAboveProfit { gas_cost, profit });
+        }
+        let bid = self
+            .bid_over(profit, coinbase_tip, gas_used, fees.next_base_fee)
+            .ok_or(BidRejection::TipBelowMinimum {
+                tip: self.tip(profit),
+                minimum: self.min_tip,
+            })?;
+        if bid.coinbase_tip.is_zero() && bid.max_priority_fee_per_gas < fees.priority_fee {
+            return Err(BidRejection::PriorityFeeBelowMarket {
+                required: fees.priority_fee,
+                affordable: bid.max_priority_fee_per_gas,
+            });
+        }
+        Ok(bid)
+    }
+
+    /// Bid used for the first simulation, before gas was measured.
+    pub fn initial_bid(&self, profit: U256, next_base_fee: U256) -> Bid {
+        Bid {
+            coinbase_tip: self.coinbase_tip(profit),
+            max_fee_per_gas: mul_ratio(next_base_fee, &self.base_fee_headroom),
+            max_priority_fee_per_gas: U256::zero(),
+            gas_limit: self.fallback_gas,
+        }
+    }
+
+    /// Turns `tx` into an EIP-1559 transaction priced by `bid`. Nonce, chain id,
+    /// calldata and access list are kept.
+    pub fn apply(&self, tx: &TypedTransaction, bid: &Bid) -> TypedTransaction {
+        let mut request = Eip1559TransactionRequest::new()
+            .max_fee_per_gas(bid.max_fee_per_gas)
+            .max_priority_fee_per_gas(bid.max_priority_fee_per_gas)
+            .gas(bid.gas_limit)
+            .access_list(tx.access_list().cloned().unwrap_or_default());
+        request.from = tx.from().copied();
+        request.to = tx.to().cloned();
+        request.value = tx.value().copied();
+        request.data = tx.data().cloned();
+        request.nonce = tx.nonce().copied();
+        request.chain_id = tx.chain_id();
+        TypedTransaction::Eip1559(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::gwei;
+
+    #[test]
+    fn test_next_base_fee() {
+        let limit = U256::from(30_000_000);
+        assert_eq!(next_base_fee(gwei(100), limit / 2, limit), gwei(100));
+        assert_eq!(
+            next_base_fee(gwei(100), limit, limit),
+            gwei(112) + gwei(1) / 2
+        );
+        assert_eq!(
+            next_base_fee(gwei(100), U256::zero(), limit),
+            gwei(87) + gwei(1) / 2
+        );
+    }
+
+    #[test]
+    fn test_coinbase_bid() {
+        let policy = BidPolicy::default();
+        let profit = U256::exp10(18);
+        let bid = policy.bid(profit, U256::from(200000), gwei(30)).unwrap();
+
+        assert_eq!(bid.coinbase_tip, U256::exp10(17) * 8);
+        assert_eq!(bid.max_priority_fee_per_gas, U256::zero());
+        assert_eq!(bid.max_fee_per_gas, gwei(60));
+        assert_eq!(bid.gas_limit, U256::from(240000));
+    }
+
+    #[test]
+    fn test_priority_fee_bid() {
+        let policy = BidPolicy {
+            tip_share: Ratio::new(1, 2),
+            tip_mode: TipMode::PriorityFee,
+            ..Default::default()
+        };
+        let gas_used = U256::from(100000);
+        let bid = policy.bid(U256::exp10(16), gas_used, gwei(10)).unwrap();
+
+        assert_eq!(bid.coinbase_tip, U256::zero());
+        assert_eq!(bid.max_priority_fee_per_gas, gwei(50));
+        assert_eq!(bid.max_fee_per_gas, gwei(70));
+        assert_eq!(bid.builder_payment(gas_used), U256::exp10(16) / 2);
+    }
+
+    #[test]
+    fn test_priority_fee_bid_without_gas() {
+        let policy = BidPolicy {
+            tip_mode: TipMode::PriorityFee,
+            ..Default::default()
+        };
+        let profit = U256::exp10(16);
+        let bid = policy.bid(profit, U256::zero(), gwei(10)).unwrap();
+
+        assert_eq!(bid.gas_limit, policy.fallback_gas);
+        assert_eq!(
+            bid.max_priority_fee_per_gas,
+            policy.tip(profit) / policy.fallback_gas
+        );
+        assert!(bid.builder_payment(bid.gas_limit) <= policy.tip(profit));
+    }
+
+    #[test]
+    fn test_bid_with_market_priority_fee() {
+        let policy = BidPolicy {
+            tip_share: Ratio::new(1, 2),
+            tip_mode: TipMode::PriorityFee,
+            ..Default::default()
+        };
+        let mut fees = FeeEstimate {
+            block: U64::from(1),
+            next_base_fee: gwei(10),
+            priority_fee: gwei(2),
+        };
+        let gas_used = U256::from(100000);
+        let profit = U256::exp10(16);
+        let bid = policy
+            .bid_with(profit, U256::zero(), gas_used, &fees)
+            .unwrap();
+        assert_eq!(bid.max_fee_per_gas, gwei(70));
+
+        fees.priority_fee = gwei(60);
+        assert_eq!(
+            policy.bid_with(profit, U256::zero(), gas_used, &fees),
+            Err(BidRejection::PriorityFeeBelowMarket {
+                required: gwei(60),
+                affordable: gwei(50),
+            })
+        );
+        let coinbase = BidPolicy::default();
+        assert!(coinbase
+            .bid_with(profit, coinbase.coinbase_tip(profit), gas_used, &fees)
+            .is_ok());
+
+        // 100k gas at 200 gwei is twice the profit
+        fees.next_base_fee = gwei(200);
+        assert_eq!(
+            coinbase.bid_with(profit, coinbase.coinbase_tip(profit), gas_used, &fees),
+            Err(BidRejection::GasAboveProfit {
+                gas_cost: U256::exp10(16) * 2,
+                profit,
+            })
+        );
+    }
+
+    #[test]
+    fn test_priority_fee_fallback() {
+        let policy = BidPolicy::default();
+        let (weth, dai) = (Address::repeat_byte(0xee), Address::repeat_byte(1));
+        let profit = U256::exp10(16);
+        assert_eq!(
+            policy.coinbase_tip_in(weth, weth, profit),
+            policy.tip(profit)
+        );
+        assert_eq!(policy.coinbase_tip_in(dai, weth, profit), U256::zero());
+
+        // A cycle ending in DAI can't unwrap its tip, it goes to the priority fee
+        let gas_used = U256::from(100000);
+        let bid = policy
+            .bid_over(profit, U256::zero(), gas_used, gwei(10))
+            .unwrap();
+        assert_eq!(bid.coinbase_tip, U256::zero());
+        assert_eq!(bid.builder_payment(gas_used), policy.tip(profit));
+        assert_eq!(policy.tipped_profit(bid.coinbase_tip), Some(U256::zero()));
+    }
+
+    #[test]
+    fn test_min_tip() {
+        let policy = BidPolicy {
+            min_tip: gwei(1000),
+            ..Default::default()
+        };
+        assert!(policy
+            .bid(gwei(100), U256::from(100000), gwei(10))
+            .is_none());
+        let fees = FeeEstimate {
+            block: U64::from(1),
+            next_base_fee: U256::zero(),
+            priority_fee: U256::zero(),
+        };
+        assert_eq!(
+            policy.bid_with(gwei(100), U256::zero(), U256::from(100000), &fees),
+            Err(BidRejection::TipBelowMinimum {
+                tip: gwei(80),
+                minimum: gwei(1000),
+            })
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let policy = BidPolicy::default();
+        let legacy: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .data(vec![1, 2, 3])
+            .nonce(7)
+            .gas_price(gwei(300))
+            .into();
+        let bid = policy.initial_bid(U256::exp10(18), gwei(30));
+        let tx = policy.apply(&legacy, &bid);
+
+        assert!(matches!(tx, TypedTransaction::Eip1559(_)));
+        assert_eq!(tx.nonce(), Some(&U256::from(7)));
+        assert_eq!(tx.data(), legacy.data());
+        assert_eq!(tx.gas(), Some(&policy.fallback_gas));
+    }
+}
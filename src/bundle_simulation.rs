@@ -0,0 +1,387 @@
+//! Parsing of `eth_callBundle` results and the checks a bundle must pass before it is
+//! sent to a relay.
+use ethers::prelude::*;
+use ethers_flashbots::{SimulatedBundle, SimulatedTransaction};
+use num::rational::Ratio;
+
+use crate::bid_policy::{BidPolicy, BidRejection, TipMode};
+use crate::path_sequence::PathOutcome;
+
+/// Simulated execution of a single bundle transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxSimulation {
+    pub hash: H256,
+    pub gas_used: U256,
+    pub effective_gas_price: U256,
+    /// Total received by `block.coinbase`, gas fees included
+    pub coinbase_diff: U256,
+    /// Ether transferred directly to `block.coinbase`
+    pub coinbase_tip: U256,
+    /// Revert reason or execution error, if the transaction failed
+    pub revert: Option<String>,
+    /// Raw return data
+    pub output: Option<Bytes>,
+}
+
+impl From<&SimulatedTransaction> for TxSimulation {
+    fn from(tx: &SimulatedTransaction) -> Self {
+        Self {
+            hash: tx.hash,
+            gas_used: tx.gas_used,
+            effective_gas_price: tx.gas_price,
+            coinbase_diff: tx.coinbase_diff,
+            coinbase_tip: tx.coinbase_tip,
+            revert: tx.revert.clone().or_else(|| tx.error.clone()),
+            output: tx.value.clone(),
+        }
+    }
+}
+
+impl TxSimulation {
+    pub fn reverted(&self) -> bool {
+        self.revert.is_some()
+    }
+
+    /// Output decoded as a single `uint256`, which is what the executor's `execute`
+    /// returns: the profit, in its profit token. Empty for flash swaps and flash loans,
+    /// which enter the executor through a callback.
+    pub fn output_u256(&self) -> Option<U256> {
+        match &self.output {
+            Some(output) if output.len() == 32 => Some(U256::from_big_endian(output)),
+            _ => None,
+        }
+    }
+}
+
+/// Simulated execution of a whole bundle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleSimulation {
+    pub bundle_hash: H256,
+    pub simulation_block: U64,
+    pub gas_used: U256,
+    pub effective_gas_price: U256,
+    pub coinbase_diff: U256,
+    pub transactions: Vec<TxSimulation>,
+}
+
+impl From<&SimulatedBundle> for BundleSimulation {
+    fn from(bundle: &SimulatedBundle) -> Self {
+        Self {
+            bundle_hash: bundle.hash,
+            simulation_block: bundle.simulation_block,
+            gas_used: bundle.gas_used,
+            effective_gas_price: bundle.gas_price,
+            coinbase_diff: bundle.coinbase_diff,
+            transactions: bundle.transactions.iter().map(TxSimulation::from).collect(),
+        }
+    }
+}
+
+impl BundleSimulation {
+    /// First reverted transaction not in `allowed`, with its index in the bundle.
+    pub fn first_revert(&self, allowed: &[H256]) -> Option<(usize, &TxSimulation)> {
+        self.transactions
+            .iter()
+            .enumerate()
+            .find(|(_, tx)| tx.reverted() && !allowed.contains(&tx.hash))
+    }
+
+    /// Total paid to `block.coinbase` by the transactions at `indices`.
+    pub fn coinbase_payment(&self, indices: &[usize]) -> U256 {
+        indices
+            .iter()
+            .filter_map(|i| self.transactions.get(*i))
+            .fold(U256::zero(), |total, tx| total + tx.coinbase_diff)
+    }
+
+    /// Profit returned by the executor calls at `indices`, in units of their input
+    /// token. `None` unless every transaction called `execute` directly.
+    pub fn returned_profit(&self, indices: &[usize]) -> Option<U256> {
+        let returned: Option<Vec<U256>> = indices
+            .iter()
+            .filter_map(|i| self.transactions.get(*i))
+            .map(|tx| tx.output_u256())
+            .collect();
+        returned
+            .filter(|r| !r.is_empty())
+            .map(|r| r.into_iter().fold(U256::zero(), |a, b| a + b))
+    }
+}
+
+/// Outcome of gating a bundle on its simulation and, if it passed, of sending it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundleDecision {
+    /// Passed every check and can be sent
+    Accept(BundleSimulation),
+    /// Sent and included in `block`
+    Included {
+        bundle_hash: H256,
+        block: U64,
+        simulation: BundleSimulation,
+    },
+    /// Sent but not included in any block of the submission window
+    NotIncluded(BundleSimulation),
+    /// Sent, then cancelled because a traded pool changed or a newer submission
+    /// replaced it
+    Cancelled(BundleSimulation),
+    /// Sent as a private transaction, retried by the relay up to `max_block_number`
+    SentPrivately {
+        tx_hash: H256,
+        max_block_number: U64,
+        simulation: BundleSimulation,
+    },
+    /// A transaction of the bundle reverted
+    Reverted {
+        index: usize,
+        hash: H256,
+        reason: String,
+    },
+    /// Our transactions pay the builder less than the policy minimum
+    PaymentBelowMinimum { payment: U256, minimum: U256 },
+    /// The tip spread over the gas used makes a priority fee below the market's
+    PriorityFeeBelowMarket { required: U256, affordable: U256 },
+    /// The gas used costs more than the profit
+    GasAboveProfit { gas_cost: U256, profit: U256 },
+    /// The simulated profit is too far from the `PathOutcome` estimate
+    ProfitDeviation { expected: U256, realised: U256 },
+}
+
+impl BundleDecision {
+    pub fn is_accepted(&self) -> bool {
+        matches!(self, BundleDecision::Accept(_))
+    }
+}
+
+impl From<BidRejection> for BundleDecision {
+    fn from(rejection: BidRejection) -> Self {
+        match rejection {
+            BidRejection::TipBelowMinimum { tip, minimum } => BundleDecision::PaymentBelowMinimum {
+                payment: tip,
+                minimum,
+            },
+            BidRejection::PriorityFeeBelowMarket {
+                required,
+                affordable,
+            } => BundleDecision::PriorityFeeBelowMarket {
+                required,
+                affordable,
+            },
+            BidRejection::GasAboveProfit { gas_cost, profit } => {
+                BundleDecision::GasAboveProfit { gas_cost, profit }
+            }
+        }
+    }
+}
+
+/// Checks applied to a simulated bundle before it is sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationGate {
+    /// Largest accepted relative difference between estimated and simulated profit
+    pub max_profit_deviation: Ratio<u64>,
+}
+
+impl Default for SimulationGate {
+    fn default() -> Self {
+        Self {
+            max_profit_deviation: Ratio::new(10, 100),
+        }
+    }
+}
+
+impl SimulationGate {
+    /// Decides whether `simulation` may be sent. `own` are the indices of our
+    /// transactions in the bundle, `allowed_reverts` the hashes of victims which may
+    /// revert.
+    pub fn decide(
+        &self,
+        simulation: BundleSimulation,
+        own: &[usize],
+        allowed_reverts: &[H256],
+        outcome: &PathOutcome,
+        policy: &BidPolicy,
+    ) -> BundleDecision {
+        if let Some((index, tx)) = simulation.first_revert(allowed_reverts) {
+            return BundleDecision::Reverted {
+                index,
+                hash: tx.hash,
+                reason: tx.revert.clone().unwrap_or_default(),
+            };
+        }
+
+        let payment = simulation.coinbase_payment(own);
+        if payment < policy.min_tip {
+            return BundleDecision::PaymentBelowMinimum {
+                payment,
+                minimum: policy.min_tip,
+            };
+        }
+
+        // The executor returns the profit in the input token when called directly. A
+        // tip paid as priority fee is the whole payment, which leaves out the burnt
+        // base fee. A coinbase transfer is the amount in our calldata whatever the
+        // profit, cycles entering the executor through a callback and paying it are
+        // only held to the executor's `min_profit`.
+        let realised = match simulation.returned_profit(own) {
+            Some(returned) => Some((outcome.token_profit, returned)),
+            None if policy.tip_mode == TipMode::PriorityFee => policy
+                .profit_of_tip(payment)
+                .map(|realised| (outcome.profit, realised)),
+            None => None,
+        };
+        if let Some((expected, realised)) = realised {
+            let deviation = if realised > expected {
+                realised - expected
+            } else {
+                expected - realised
+            };
+            let tolerance = expected * U256::from(*self.max_profit_deviation.numer())
+                / U256::from(*self.max_profit_deviation.denom());
+            if deviation > tolerance {
+                return BundleDecision::ProfitDeviation { expected, realised };
+            }
+        }
+
+        BundleDecision::Accept(simulation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(coinbase_tip: u64, revert: Option<&str>, output: Option<U256>) -> TxSimulation {
+        TxSimulation {
+            hash: H256::repeat_byte(coinbase_tip as u8),
+            gas_used: U256::from(150000),
+            effective_gas_price: U256::zero(),
+            coinbase_diff: U256::from(coinbase_tip),
+            coinbase_tip: U256::from(coinbase_tip),
+            revert: revert.map(str::to_string),
+            output: output.map(|value| {
+                let mut bytes = [0u8; 32];
+                value.to_big_endian(&mut bytes);
+                Bytes::from(bytes.to_vec())
+            }),
+        }
+    }
+
+    fn simulation(transactions: Vec<TxSimulation>) -> BundleSimulation {
+        BundleSimulation {
+            bundle_hash: H256::zero(),
+            simulation_block: U64::from(1),
+            gas_used: U256::zero(),
+            effective_gas_price: U256::zero(),
+            coinbase_diff: U256::zero(),
+            transactions,
+        }
+    }
+
+    fn outcome(profit: u64) -> PathOutcome {
+        PathOutcome {
+            seq_id: 0,
+            path: "WETH-USDC-DAI".to_string(),
+            pools: vec![],
+            amount_in: U256::zero(),
+            amount_out: U256::zero(),
+            profit: U256::from(profit),
+            token_profit: U256::from(profit),
+            coinbase_tip: BidPolicy::default().coinbase_tip(U256::from(profit)),
+        }
+    }
+
+    #[test]
+    fn test_rejects_revert() {
+        let gate = SimulationGate::default();
+        let policy = BidPolicy::default();
+        let sim = simulation(vec![tx(0, None, None), tx(80, Some("K"), None)]);
+        let decision = gate.decide(sim, &[1], &[], &outcome(100), &policy);
+        assert!(matches!(
+            decision,
+            BundleDecision::Reverted { index: 1, ref reason, .. } if reason == "K"
+        ));
+
+        // A reverting victim is fine as long as it is allowed to revert
+        let sim = simulation(vec![tx(1, Some("STF"), None), tx(80, None, None)]);
+        let allowed = [sim.transactions[0].hash];
+        assert!(gate
+            .decide(sim.clone(), &[1], &allowed, &outcome(100), &policy)
+            .is_accepted());
+        assert!(!gate
+            .decide(sim, &[1], &[], &outcome(100), &policy)
+            .is_accepted());
+    }
+
+    #[test]
+    fn test_rejects_low_payment() {
+        let policy = BidPolicy {
+            min_tip: U256::from(100),
+            ..Default::default()
+        };
+        let sim = simulation(vec![tx(80, None, None)]);
+        assert_eq!(
+            SimulationGate::default().decide(sim, &[0], &[], &outcome(100), &policy),
+            BundleDecision::PaymentBelowMinimum {
+                payment: U256::from(80),
+                minimum: U256::from(100),
+            }
+        );
+    }
+
+    #[test]
+    fn test_priority_fee_profit() {
+        let gate = SimulationGate::default();
+        let policy = BidPolicy {
+            tip_mode: TipMode::PriorityFee,
+            ..Default::default()
+        };
+        // Nothing returned nor transferred, the builder only gets the priority fee
+        let paid = |payment: u64| {
+            let mut tx = tx(0, None, None);
+            tx.coinbase_diff = U256::from(payment);
+            simulation(vec![tx])
+        };
+        let in_priority_fee = PathOutcome {
+            coinbase_tip: U256::zero(),
+            ..outcome(100)
+        };
+        assert!(gate
+            .decide(paid(80), &[0], &[], &in_priority_fee, &policy)
+            .is_accepted());
+        assert_eq!(
+            gate.decide(paid(40), &[0], &[], &in_priority_fee, &policy),
+            BundleDecision::ProfitDeviation {
+                expected: U256::from(100),
+                realised: U256::from(50),
+            }
+        );
+    }
+
+    #[test]
+    fn test_profit_deviation() {
+        let gate = SimulationGate::default();
+        let policy = BidPolicy::default();
+
+        // Taken from the executor's return value, in the input token
+        let sim = simulation(vec![tx(80, None, Some(U256::from(290_000)))]);
+        let in_dai = PathOutcome {
+            token_profit: U256::from(300_000),
+            ..outcome(105)
+        };
+        assert!(gate.decide(sim, &[0], &[], &in_dai, &policy).is_accepted());
+
+        // A flash swap returns nothing, and its coinbase transfer tells nothing
+        let sim = simulation(vec![tx(40, None, None)]);
+        assert!(gate
+            .decide(sim, &[0], &[], &outcome(100), &policy)
+            .is_accepted());
+
+        let sim = simulation(vec![tx(80, None, Some(U256::from(50)))]);
+        assert_eq!(
+            gate.decide(sim, &[0], &[], &outcome(100), &policy),
+            BundleDecision::ProfitDeviation {
+                expected: U256::from(100),
+                realised: U256::from(50),
+            }
+        );
+    }
+}
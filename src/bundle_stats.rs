@@ -0,0 +1,188 @@
+//! Relay side statistics of the bundles we submit.
+//!
+//! After a submission ends, `flashbots_getBundleStats` tells how far the bundle got
+//! through the relay (simulated, sent to miners) and `flashbots_getUserStats` how the
+//! relay ranks us as a searcher. Both are kept next to the opportunity which produced
+//! the bundle, to tune the `BidPolicy`.
+use std::sync::{Arc, Mutex};
+
+use ethers::prelude::*;
+use ethers_flashbots::{BundleStats, FlashbotsMiddleware, UserStats};
+
+use crate::bundle_simulation::BundleDecision;
+use crate::path_sequence::PathOutcome;
+
+/// Why a bundle did or did not land, from the relay's point of view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InclusionExplanation {
+    Included,
+    /// The relay has no record of the bundle, it was probably rejected on receipt
+    NotReceived,
+    /// Received but never simulated, usually because it arrived too late or is invalid
+    NotSimulated,
+    /// Simulated but not forwarded: other bundles were considered more valuable
+    NotSentToMiners,
+    /// Forwarded to miners and builders, which picked other bundles over it
+    NotSelected,
+    /// Cancelled by us before its target block
+    Cancelled,
+}
+
+impl InclusionExplanation {
+    pub fn explain(stats: Option<&BundleStats>, included: bool) -> Self {
+        if included {
+            return InclusionExplanation::Included;
+        }
+        match stats {
+            None => InclusionExplanation::NotReceived,
+            Some(stats) if !stats.is_simulated => InclusionExplanation::NotSimulated,
+            Some(stats) if !stats.is_sent_to_miners => InclusionExplanation::NotSentToMiners,
+            Some(_) => InclusionExplanation::NotSelected,
+        }
+    }
+}
+
+/// Blocks a bundle simulated on `simulation_block` is sent for over a `window` of
+/// blocks, latest first.
+pub fn targeted_blocks(simulation_block: U64, window: u64) -> impl Iterator<Item = U64> {
+    (1..=window.max(1)).rev().map(move |offset| simulation_block + offset)
+}
+
+/// Relay statistics of one submitted bundle.
+#[derive(Debug)]
+pub struct SubmissionRecord {
+    pub outcome: PathOutcome,
+    pub bundle_hash: H256,
+    pub target_block: U64,
+    pub stats: Option<BundleStats>,
+    pub explanation: InclusionExplanation,
+}
+
+/// Log of every submitted bundle with its relay statistics.
+#[derive(Default)]
+pub struct BundleStatsLog {
+    records: Mutex<Vec<Arc<SubmissionRecord>>>,
+    user_stats: Mutex<Option<Arc<UserStats>>>,
+}
+
+impl BundleStatsLog {
+    /// Queries the stats of the bundle sent for `decision` and records them next to
+    /// `outcome`. Decisions which never reached a relay aren't recorded. A bundle
+    /// which didn't land is looked up for the last block of the `window` it was sent
+    /// for that the relay knows of, see `BundleSubmitter::window`.
+    pub async fn record<M: Middleware, S: Signer>(
+        &self,
+        relay: &FlashbotsMiddleware<M, S>,
+        outcome: &PathOutcome,
+        decision: &BundleDecision,
+        window: u64,
+    ) -> Result<Option<Arc<SubmissionRecord>>, anyhow::Error> {
+        // The relay doesn't know bundles it rejected, which isn't an error for us
+        let (bundle_hash, target_block, stats, included) = match decision {
+            BundleDecision::Included {
+                bundle_hash, block, ..
+            } => {
+                let stats = relay.get_bundle_stats(*bundle_hash, *block).await.ok();
+                (*bundle_hash, *block, stats, true)
+            }
+            BundleDecision::NotIncluded(simulation) | BundleDecision::Cancelled(simulation) => {
+                let bundle_hash = simulation.bundle_hash;
+                let mut last = (simulation.simulation_block + window.max(1), None);
+                for block in targeted_blocks(simulation.simulation_block, window) {
+                    if let Ok(stats) = relay.get_bundle_stats(bundle_hash, block).await {
+                        last = (block, Some(stats));
+                        break;
+                    }
+                }
+                (bundle_hash, last.0, last.1, false)
+            }
+            _ => return Ok(None),
+        };
+        let explanation = match decision {
+            BundleDecision::Cancelled(_) => InclusionExplanation::Cancelled,
+            _ => InclusionExplanation::explain(stats.as_ref(), included),
+        };
+
+        let record = Arc::new(SubmissionRecord {
+            outcome: outcome.clone(),
+            bundle_hash,
+            target_block,
+            stats,
+            explanation,
+        });
+        self.records.lock().unwrap().push(record.clone());
+
+        let user_stats = relay
+            .get_user_stats()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to get user stats: {}", e))?;
+        *self.user_stats.lock().unwrap() = Some(Arc::new(user_stats));
+
+        Ok(Some(record))
+    }
+
+    pub fn records(&self) -> Vec<Arc<SubmissionRecord>> {
+        self.records.lock().unwrap().clone()
+    }
+
+    /// Latest searcher statistics returned by the relay.
+    pub fn user_stats(&self) -> Option<Arc<UserStats>> {
+        self.user_stats.lock().unwrap().clone()
+    }
+
+    /// Number of recorded bundles for each explanation.
+    pub fn summary(&self) -> Vec<(InclusionExplanation, usize)> {
+        let mut summary: Vec<(InclusionExplanation, usize)> = Vec::new();
+        for record in self.records.lock().unwrap().iter() {
+            match summary.iter_mut().find(|(e, _)| *e == record.explanation) {
+                Some((_, count)) => *count += 1,
+                None => summary.push((record.explanation, 1)),
+            }
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(is_simulated: bool, is_sent_to_miners: bool) -> BundleStats {
+        BundleStats {
+            is_high_priority: false,
+            is_simulated,
+            is_sent_to_miners,
+            simulated_at: None,
+            submitted_at: None,
+            sent_to_miners_at: None,
+        }
+    }
+
+    #[test]
+    fn test_targeted_blocks() {
+        let blocks: Vec<U64> = targeted_blocks(U64::from(10), 3).collect();
+        assert_eq!(blocks, vec![U64::from(13), U64::from(12), U64::from(11)]);
+        let blocks: Vec<U64> = targeted_blocks(U64::from(10), 0).collect();
+        assert_eq!(blocks, vec![U64::from(11)]);
+    }
+
+    #[test]
+    fn test_explain() {
+        use InclusionExplanation::*;
+
+        assert_eq!(InclusionExplanation::explain(None, true), Included);
+        assert_eq!(InclusionExplanation::explain(None, false), NotReceived);
+        assert_eq!(
+            InclusionExplanation::explain(Some(&stats(false, false)), false),
+            NotSimulated
+        );
+        assert_eq!(
+            InclusionExplanation::explain(Some(&stats(true, false)), false),
+            NotSentToMiners
+        );
+        assert_eq!(
+            InclusionExplanation::explain(Some(&stats(true, true)), false),
+            NotSelected
+        );
+    }
+}
@@ -0,0 +1,476 @@
+//! Fan-out of signed bundles to several relays and builders over a window of blocks.
+//!
+//! The same `BundleRequest` is sent to every configured relay for the target block.
+//! If it isn't included, it is sent again for the next block, up to `window` blocks.
+//! Submissions touching a pool can be cancelled when that pool's reserves change
+//! before their first target block, so a stale bundle stops being resubmitted, and one
+//! can be aborted by id when a newer submission takes its nonces.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use ethers::prelude::*;
+use ethers_flashbots::{BundleRequest, FlashbotsMiddleware, PendingBundleError};
+use futures::future::{self, AbortHandle, AbortRegistration, Abortable};
+use url::Url;
+
+use crate::signers::SearcherSigner;
+
+/// Relays and builders accepting `eth_sendBundle` on mainnet, with the fee
+/// recipient of the blocks they build.
+pub const MAINNET_RELAYS: [(&str, &str, &str); 5] = [
+    (
+        "flashbots",
+        "https://relay.flashbots.net",
+        "0xDAFEA492D9c6733ae3d56b7Ed1ADB60692c98Bc5",
+    ),
+    (
+        "builder0x69",
+        "https://builder0x69.io",
+        "0x690B9A9E9aa1C9dB991C7721a92d351Db4FaC990",
+    ),
+    (
+        "beaverbuild",
+        "https://rpc.beaverbuild.org",
+        "0x95222290DD7278Aa3Ddd389Cc1E1d165CC4BAfe5",
+    ),
+    (
+        "rsync",
+        "https://rsync-builder.xyz",
+        "0x1f9090aaE28b8a3dCeaDf281B0F12828e676c326",
+    ),
+    (
+        "titan",
+        "https://rpc.titanbuilder.xyz",
+        "0x4838B106FCe9647Bdf1E7877BF73cE8B0BAD5f97",
+    ),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayEndpoint {
+    pub name: String,
+    pub url: Url,
+    /// Fee recipient of the blocks built behind this relay, if known
+    pub builder: Option<Address>,
+}
+
+impl RelayEndpoint {
+    pub fn new(name: &str, url: &str) -> Result<Self, url::ParseError> {
+        Ok(Self {
+            name: name.to_string(),
+            url: Url::parse(url)?,
+            builder: None,
+        })
+    }
+
+    pub fn with_builder(mut self, builder: Address) -> Self {
+        self.builder = Some(builder);
+        self
+    }
+
+    pub fn mainnet() -> Vec<Self> {
+        MAINNET_RELAYS
+            .iter()
+            .map(|(name, url, builder)| {
+                Self::new(name, url)
+                    .unwrap()
+                    .with_builder(builder.parse().unwrap())
+            })
+            .collect()
+    }
+}
+
+/// Submission statistics of a single relay.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelayStats {
+    /// Bundles sent, one per target block
+    pub submitted: u64,
+    /// Bundles the relay failed to accept
+    pub errors: u64,
+    /// Accepted bundles which landed in a block built behind this relay
+    pub included: u64,
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+}
+
+impl RelayStats {
+    fn record_send(&mut self, latency: Duration, accepted: bool) {
+        self.submitted += 1;
+        if !accepted {
+            self.errors += 1;
+        }
+        self.total_latency += latency;
+        self.max_latency = self.max_latency.max(latency);
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        if self.submitted == 0 {
+            return Duration::ZERO;
+        }
+        self.total_latency / self.submitted as u32
+    }
+
+    /// Share of accepted bundles which were included
+    pub fn inclusion_rate(&self) -> f64 {
+        let accepted = self.submitted - self.errors;
+        if accepted == 0 {
+            return 0.0;
+        }
+        self.included as f64 / accepted as f64
+    }
+}
+
+/// Final state of a submission.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inclusion {
+    Included {
+        bundle_hash: H256,
+        block: U64,
+        /// Fee recipient of the including block, to attribute it to a builder
+        builder: Option<Address>,
+        /// Relays which accepted the bundle for that block
+        relays: Vec<String>,
+        /// Relay whose builder built the including block, if it is known
+        landed_through: Option<String>,
+    },
+    /// Not included in any block of the window
+    NotIncluded,
+    /// Cancelled before it was included
+    Cancelled,
+}
+
+struct ActiveSubmission {
+    pools: Vec<Address>,
+    abort: AbortHandle,
+    /// Taken once the bundle is sent
+    registration: Option<AbortRegistration>,
+    /// First block the bundle is sent for, once it is
+    first_block: Option<U64>,
+    /// Transactions of the bundle, once it is sent
+    txs: Vec<H256>,
+}
+
+impl ActiveSubmission {
+    /// Whether a reserve change of one of its pools, by transaction `tx_hash` in
+    /// `block`, leaves the submission stale. Changes made by the bundle itself, or in
+    /// blocks it targets, where it is ordered first, don't.
+    fn stale_after(&self, block: Option<U64>, tx_hash: Option<H256>) -> bool {
+        if tx_hash.is_some_and(|hash| self.txs.contains(&hash)) {
+            return false;
+        }
+        match (self.first_block, block) {
+            (Some(first), Some(block)) => block < first,
+            _ => true,
+        }
+    }
+}
+
+type Relay = FlashbotsMiddleware<Provider<Http>, SearcherSigner>;
+
+/// Relay which gets the credit for an inclusion: the accepting relay whose
+/// builder is the fee recipient of the block.
+fn landing_relay<'a>(
+    accepted: impl IntoIterator<Item = (&'a str, Option<Address>)>,
+    author: Option<Address>,
+) -> Option<&'a str> {
+    let author = author?;
+    accepted
+        .into_iter()
+        .find(|(_, builder)| *builder == Some(author))
+        .map(|(name, _)| name)
+}
+
+pub struct BundleSubmitter {
+    provider: Provider<Http>,
+    relays: Vec<(String, Option<Address>, Relay)>,
+    window: u64,
+    stats: Arc<DashMap<String, RelayStats>>,
+    active: DashMap<u64, ActiveSubmission>,
+    next_id: AtomicU64,
+}
+
+impl BundleSubmitter {
+    /// `bundle_signer` is the searcher identity used to authenticate with every relay.
+    pub fn new(
+        provider: Provider<Http>,
+        bundle_signer: SearcherSigner,
+        relays: Vec<RelayEndpoint>,
+        window: u64,
+    ) -> Self {
+        let relays = relays
+            .into_iter()
+            .map(|relay| {
+                let middleware =
+                    FlashbotsMiddleware::new(provider.clone(), relay.url, bundle_signer.clone());
+                (relay.name, relay.builder, middleware)
+            })
+            .collect();
+        Self {
+            provider,
+            relays,
+            window: window.max(1),
+            stats: Default::default(),
+            active: Default::default(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of consecutive blocks a bundle is submitted for.
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    /// Snapshot of the statistics of every relay.
+    pub fn stats(&self) -> Vec<(String, RelayStats)> {
+        self.stats
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    /// Opens a submission trading on `pools` and returns its id. It can be aborted
+    /// from now on, and must be closed if it is never sent with `submit` or `replace`.
+    pub fn open(&self, pools: Vec<Address>) -> u64 {
+        let (abort, registration) = AbortHandle::new_pair();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.active.insert(
+            id,
+            ActiveSubmission {
+                pools,
+                abort,
+                registration: Some(registration),
+                first_block: None,
+                txs: Vec::new(),
+            },
+        );
+        id
+    }
+
+    /// Forgets submission `id`.
+    pub fn close(&self, id: u64) {
+        self.active.remove(&id);
+    }
+
+    /// Cancels submission `id`, whether it was sent yet or not.
+    pub fn abort(&self, id: u64) {
+        if let Some(submission) = self.active.get(&id) {
+            submission.abort.abort();
+        }
+    }
+
+    /// Sends `bundle` as submission `id` to every relay, starting at its target block,
+    /// until it is included, the window is over or it is cancelled.
+    pub async fn submit(&self, id: u64, bundle: BundleRequest) -> Result<Inclusion, anyhow::Error> {
+        let registration = self.active.get_mut(&id).and_then(|mut submission| {
+            submission.first_block = bundle.block();
+            submission.txs = bundle.transaction_hashes();
+            submission.registration.take()
+        });
+        let registration = match registration {
+            Some(registration) => registration,
+            None => return Ok(Inclusion::Cancelled),
+        };
+
+        let result = Abortable::new(self.run(bundle), registration).await;
+        self.close(id);
+        match result {
+            Ok(inclusion) => inclusion,
+            Err(_) => Ok(Inclusion::Cancelled),
+        }
+    }
+
+    /// Cancels every submission trading on `pool` left stale by its reserves changing
+    /// in transaction `tx_hash` of `block`, returning how many were cancelled.
+    pub fn cancel_touching(
+        &self,
+        pool: &Address,
+        block: Option<U64>,
+        tx_hash: Option<H256>,
+    ) -> usize {
+        let mut cancelled = 0;
+        for entry in self.active.iter() {
+            if entry.pools.contains(pool) && entry.stale_after(block, tx_hash) {
+                entry.abort.abort();
+                cancelled += 1;
+            }
+        }
+        cancelled
+    }
+
+    /// Cancels the other submissions sharing a pool with submission `id`, then sends
+    /// `bundle` as that one.
+    pub async fn replace(
+        &self,
+        id: u64,
+        bundle: BundleRequest,
+    ) -> Result<Inclusion, anyhow::Error> {
+        let pools = self
+            .active
+            .get(&id)
+            .map(|submission| submission.pools.clone())
+            .unwrap_or_default();
+        for entry in self.active.iter() {
+            if *entry.key() != id && entry.pools.iter().any(|pool| pools.contains(pool)) {
+                entry.abort.abort();
+            }
+        }
+        self.submit(id, bundle).await
+    }
+
+    async fn run(&self, bundle: BundleRequest) -> Result<Inclusion, anyhow::Error> {
+        let first = match bundle.block() {
+            Some(block) => block,
+            None => self.provider.get_block_number().await? + 1,
+        };
+
+        for block in (0..self.window).map(|offset| first + offset) {
+            let request = bundle.clone().set_block(block);
+            let sends = self.relays.iter().map(|(name, builder, relay)| {
+                let request = &request;
+                async move {
+                    let started = Instant::now();
+                    let result = relay.send_bundle(request).await;
+                    self.stats
+                        .entry(name.clone())
+                        .or_default()
+                        .record_send(started.elapsed(), result.is_ok());
+                    if let Err(e) = &result {
+                        println!("Relay {} rejected bundle for block {}: {}", name, block, e);
+                    }
+                    (name, *builder, result.ok())
+                }
+            });
+            let sent = future::join_all(sends).await;
+            let accepted: Vec<(&str, Option<Address>)> = sent
+                .iter()
+                .filter(|(_, _, pending)| pending.is_some())
+                .map(|(name, builder, _)| ((*name).as_str(), *builder))
+                .collect();
+            let relays: Vec<String> = accepted.iter().map(|(name, _)| name.to_string()).collect();
+
+            // Every pending bundle watches the same block, one is enough
+            let pending = match sent.into_iter().find_map(|(_, _, pending)| pending) {
+                Some(pending) => pending,
+                None => continue,
+            };
+            match pending.await {
+                Ok(bundle_hash) => {
+                    let builder = self
+                        .provider
+                        .get_block(block)
+                        .await?
+                        .and_then(|block| block.author);
+                    // Only the relay which delivered the block gets the inclusion
+                    let landed_through =
+                        landing_relay(accepted.iter().copied(), builder).map(str::to_string);
+                    if let Some(relay) = &landed_through {
+                        self.stats.entry(relay.clone()).or_default().included += 1;
+                    }
+                    return Ok(Inclusion::Included {
+                        bundle_hash,
+                        block,
+                        builder,
+                        relays,
+                        landed_through,
+                    });
+                }
+                Err(PendingBundleError::BundleNotIncluded) => {
+                    println!("Bundle was not included in block {}.", block);
+                }
+                Err(e) => return Err(anyhow::anyhow!("PendingBundleError occured: {:#}", e)),
+            }
+        }
+
+        Ok(Inclusion::NotIncluded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_stats() {
+        let mut stats = RelayStats::default();
+        stats.record_send(Duration::from_millis(100), true);
+        stats.record_send(Duration::from_millis(300), true);
+        stats.record_send(Duration::from_millis(200), false);
+        stats.included += 1;
+
+        assert_eq!(stats.mean_latency(), Duration::from_millis(200));
+        assert_eq!(stats.max_latency, Duration::from_millis(300));
+        assert_eq!(stats.inclusion_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_mainnet_relays() {
+        let relays = RelayEndpoint::mainnet();
+        assert_eq!(relays.len(), MAINNET_RELAYS.len());
+        assert_eq!(relays[0].url.host_str(), Some("relay.flashbots.net"));
+        assert!(relays.iter().all(|relay| relay.builder.is_some()));
+    }
+
+    #[tokio::test]
+    async fn test_aborted_before_sent() {
+        let provider = Provider::<Http>::try_from("http://localhost:8545").unwrap();
+        let signer = SearcherSigner::Local(LocalWallet::new(&mut rand::thread_rng()));
+        let submitter = BundleSubmitter::new(provider, signer, vec![], 1);
+
+        // Replaced while it was still being simulated
+        let replaced = submitter.open(vec![Address::repeat_byte(1)]);
+        submitter.abort(replaced);
+        let inclusion = submitter
+            .submit(replaced, BundleRequest::new())
+            .await
+            .unwrap();
+        assert_eq!(inclusion, Inclusion::Cancelled);
+
+        // Never sent once closed
+        let closed = submitter.open(vec![Address::repeat_byte(2)]);
+        submitter.close(closed);
+        let inclusion = submitter
+            .submit(closed, BundleRequest::new())
+            .await
+            .unwrap();
+        assert_eq!(inclusion, Inclusion::Cancelled);
+    }
+
+    #[test]
+    fn test_stale_after() {
+        let (abort, registration) = AbortHandle::new_pair();
+        let mut submission = ActiveSubmission {
+            pools: vec![Address::repeat_byte(1)],
+            abort,
+            registration: Some(registration),
+            first_block: None,
+            txs: Vec::new(),
+        };
+        // Still simulating, any change is stale
+        assert!(submission.stale_after(Some(U64::from(12)), None));
+
+        let own = H256::repeat_byte(1);
+        submission.first_block = Some(U64::from(11));
+        submission.txs = vec![own];
+        assert!(submission.stale_after(Some(U64::from(10)), Some(H256::repeat_byte(2))));
+        // Our own bundle landing, or others in the blocks it targets
+        assert!(!submission.stale_after(Some(U64::from(10)), Some(own)));
+        assert!(!submission.stale_after(Some(U64::from(11)), Some(H256::repeat_byte(2))));
+        assert!(!submission.stale_after(Some(U64::from(12)), Some(H256::repeat_byte(2))));
+    }
+
+    #[test]
+    fn test_landing_relay() {
+        let titan = Address::repeat_byte(0x01);
+        let beaver = Address::repeat_byte(0x02);
+        let accepted = [
+            ("titan", Some(titan)),
+            ("beaverbuild", Some(beaver)),
+            ("other", None),
+        ];
+
+        assert_eq!(landing_relay(accepted, Some(beaver)), Some("beaverbuild"));
+        assert_eq!(landing_relay(accepted, Some(Address::zero())), None);
+        assert_eq!(landing_relay(accepted, None), None);
+    }
+}
@@ -161,7 +161,18 @@ pub async fn monitor_tx(pair_map: &mut HashMap<Address, Arc<CryptoPair>>) {
             continue;
         }
         let pair = pair_pre.unwrap();
+
         if method.eq("Sync") {
+            // Bundles priced on the old reserves of this pair are stale now
+            let cancelled =
+                mainnet::bundle_submitter.cancel_touching(&address, log.block_number, tx_hash);
+            if cancelled > 0 {
+                println!(
+                    "[Reserves Event] - Cancelled {} bundle(s) on {}",
+                    cancelled,
+                    pair.pair_symbol()
+                );
+            }
             for param in decoded_event.params.iter() {
                 let log_param: ethabi::LogParam = (*param).clone();
                 if log_param.name.eq("reserve_0") {
@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.17;
+
+interface IERC20 {
+    function balanceOf(address owner) external view returns (uint256);
+}
+
+/// @notice Runs the cycles `executor_call.rs` encodes, either called directly or from
+/// the callback of a Uniswap V2 flash swap, a Balancer Vault or an Aave flash loan.
+/// Each step is a call with ether to an arbitrary target; a swap step reverts early
+/// unless its recipient gains at least `minOut` of the token it trades into. The whole
+/// call reverts unless the steps leave at least `minProfit` more of `profitToken` than
+/// the executor held before any of it was lent, then `minerTip` is paid to
+/// `block.coinbase`. Called directly, `execute` returns the profit.
+/// Only transactions sent by the deployer run anything.
+contract Executor {
+    struct Step {
+        address target;
+        uint256 value;
+        bytes data;
+        /// Token the step trades into, zero for steps trading nothing
+        address token;
+        /// Receiver of the step's output
+        address recipient;
+        uint256 minOut;
+    }
+
+    address private constant BALANCER_VAULT = 0xBA12222222228d8Ba445958a75a0704d566BF2C8;
+
+    address public immutable owner;
+
+    constructor() {
+        owner = msg.sender;
+    }
+
+    /// WETH unwrapped for the tip lands here
+    receive() external payable {}
+
+    modifier onlyOwner() {
+        require(tx.origin == owner, "Executor: not the owner");
+        _;
+    }
+
+    function execute(
+        Step[] calldata steps,
+        address profitToken,
+        uint256 minProfit,
+        uint256 minerTip
+    ) external payable onlyOwner returns (uint256 profit) {
+        return _run(steps, profitToken, minProfit, minerTip, 0, 0);
+    }
+
+    /// Flash swap: the pair lent its output, a step of `data` repays it.
+    function uniswapV2Call(
+        address sender,
+        uint256 amount0,
+        uint256 amount1,
+        bytes calldata data
+    ) external onlyOwner {
+        require(sender == owner, "Executor: not the owner");
+        _callback(data, address(0), 0, 0);
+    }
+
+    /// Balancer Vault flash loan, repaid by a step of `userData`.
+    function receiveFlashLoan(
+        address[] calldata tokens,
+        uint256[] calldata amounts,
+        uint256[] calldata feeAmounts,
+        bytes calldata userData
+    ) external onlyOwner {
+        require(msg.sender == BALANCER_VAULT, "Executor: not the vault");
+        _callback(userData, tokens[0], amounts[0], 0);
+    }
+
+    /// Aave v2 flash loan, pulled back with its premium once this returns.
+    function executeOperation(
+        address[] calldata assets,
+        uint256[] calldata amounts,
+        uint256[] calldata premiums,
+        address initiator,
+        bytes calldata params
+    ) external onlyOwner returns (bool) {
+        require(initiator == owner, "Executor: not the owner");
+        _callback(params, assets[0], amounts[0], amounts[0] + premiums[0]);
+        return true;
+    }
+
+    /// Aave v3 `flashLoanSimple`, pulled back with its premium once this returns.
+    function executeOperation(
+        address asset,
+        uint256 amount,
+        uint256 premium,
+        address initiator,
+        bytes calldata params
+    ) external onlyOwner returns (bool) {
+        require(initiator == owner, "Executor: not the owner");
+        _callback(params, asset, amount, amount + premium);
+        return true;
+    }
+
+    /// Runs the `execute` calldata a callback was handed. `lent` of `token` arrived
+    /// before the callback and `owed` of it is pulled back after, neither is profit.
+    function _callback(
+        bytes calldata data,
+        address token,
+        uint256 lent,
+        uint256 owed
+    ) private {
+        require(bytes4(data[:4]) == this.execute.selector, "Executor: not an execute call");
+        (Step[] memory steps, address profitToken, uint256 minProfit, uint256 minerTip) = abi
+            .decode(data[4:], (Step[], address, uint256, uint256));
+        if (token != profitToken) {
+            (lent, owed) = (0, 0);
+        }
+        _run(steps, profitToken, minProfit, minerTip, lent, owed);
+    }
+
+    function _run(
+        Step[] memory steps,
+        address profitToken,
+        uint256 minProfit,
+        uint256 minerTip,
+        uint256 lent,
+        uint256 owed
+    ) private returns (uint256 profit) {
+        uint256 start = IERC20(profitToken).balanceOf(address(this)) - lent;
+        for (uint256 i = 0; i < steps.length; i++) {
+            _step(steps[i]);
+        }
+        uint256 end = IERC20(profitToken).balanceOf(address(this));
+        require(end >= start + owed + minProfit, "Executor: below the least profit");
+
+        if (minerTip > 0) {
+            (bool paid, ) = block.coinbase.call{value: minerTip}("");
+            require(paid, "Executor: tip not paid");
+        }
+        return end - start - owed;
+    }
+
+    function _step(Step memory step) private {
+        uint256 held = step.token == address(0)
+            ? 0
+            : IERC20(step.token).balanceOf(step.recipient);
+        (bool success, bytes memory result) = step.target.call{value: step.value}(step.data);
+        if (!success) {
+            assembly {
+                revert(add(result, 32), mload(result))
+            }
+        }
+        if (step.token != address(0)) {
+            require(
+                IERC20(step.token).balanceOf(step.recipient) >= held + step.minOut,
+                "Executor: step below its least output"
+            );
+        }
+    }
+}
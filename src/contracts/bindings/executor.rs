@@ -0,0 +1,360 @@
+pub use executor_mod::*;
+
+#[allow(clippy::too_many_arguments)]
+mod executor_mod {
+    #![allow(clippy::enum_variant_names)]
+    #![allow(dead_code)]
+    #![allow(clippy::type_complexity)]
+    #![allow(unused_imports)]
+
+    use ethers::contract::{
+        builders::{ContractCall, Event},
+        Contract, Lazy,
+    };
+    use ethers::core::{
+        abi::{Abi, Detokenize, InvalidOutputType, Token, Tokenizable},
+        types::*,
+    };
+    use ethers::providers::Middleware;
+    #[doc = "Executor was auto-generated with ethers-rs Abigen. More information at: https://github.com/gakonst/ethers-rs"]
+    use std::sync::Arc;
+
+    pub static EXECUTOR_ABI: ethers::contract::Lazy<ethers::core::abi::Abi> =
+        ethers::contract::Lazy::new(|| {
+            serde_json :: from_str ("[{\"type\":\"constructor\",\"inputs\":[]},{\"type\":\"function\",\"name\":\"execute\",\"inputs\":[{\"internalType\":\"struct Executor.Step[]\",\"name\":\"steps\",\"type\":\"tuple[]\",\"components\":[{\"internalType\":\"address\",\"name\":\"target\",\"type\":\"address\"},{\"internalType\":\"uint256\",\"name\":\"value\",\"type\":\"uint256\"},{\"internalType\":\"bytes\",\"name\":\"data\",\"type\":\"bytes\"},{\"internalType\":\"address\",\"name\":\"token\",\"type\":\"address\"},{\"internalType\":\"address\",\"name\":\"recipient\",\"type\":\"address\"},{\"internalType\":\"uint256\",\"name\":\"minOut\",\"type\":\"uint256\"}]},{\"internalType\":\"address\",\"name\":\"profitToken\",\"type\":\"address\"},{\"internalType\":\"uint256\",\"name\":\"minProfit\",\"type\":\"uint256\"},{\"internalType\":\"uint256\",\"name\":\"minerTip\",\"type\":\"uint256\"}],\"outputs\":[{\"internalType\":\"uint256\",\"name\":\"profit\",\"type\":\"uint256\"}],\"constant\":false,\"stateMutability\":\"payable\"},{\"type\":\"function\",\"name\":\"executeOperation\",\"inputs\":[{\"internalType\":\"address[]\",\"name\":\"assets\",\"type\":\"address[]\"},{\"internalType\":\"uint256[]\",\"name\":\"amounts\",\"type\":\"uint256[]\"},{\"internalType\":\"uint256[]\",\"name\":\"premiums\",\"type\":\"uint256[]\"},{\"internalType\":\"address\",\"name\":\"initiator\",\"type\":\"address\"},{\"internalType\":\"bytes\",\"name\":\"params\",\"type\":\"bytes\"}],\"outputs\":[{\"internalType\":\"bool\",\"name\":\"\",\"type\":\"bool\"}],\"constant\":false,\"stateMutability\":\"nonpayable\"},{\"type\":\"function\",\"name\":\"executeOperation\",\"inputs\":[{\"internalType\":\"address\",\"name\":\"asset\",\"type\":\"address\"},{\"internalType\":\"uint256\",\"name\":\"amount\",\"type\":\"uint256\"},{\"internalType\":\"uint256\",\"name\":\"premium\",\"type\":\"uint256\"},{\"internalType\":\"address\",\"name\":\"initiator\",\"type\":\"address\"},{\"internalType\":\"bytes\",\"name\":\"params\",\"type\":\"bytes\"}],\"outputs\":[{\"internalType\":\"bool\",\"name\":\"\",\"type\":\"bool\"}],\"constant\":false,\"stateMutability\":\"nonpayable\"},{\"type\":\"function\",\"name\":\"owner\",\"inputs\":[],\"outputs\":[{\"internalType\":\"address\",\"name\":\"\",\"type\":\"address\"}],\"constant\":true,\"stateMutability\":\"view\"},{\"type\":\"function\",\"name\":\"receiveFlashLoan\",\"inputs\":[{\"internalType\":\"address[]\",\"name\":\"tokens\",\"type\":\"address[]\"},{\"internalType\":\"uint256[]\",\"name\":\"amounts\",\"type\":\"uint256[]\"},{\"internalType\":\"uint256[]\",\"name\":\"feeAmounts\",\"type\":\"uint256[]\"},{\"internalType\":\"bytes\",\"name\":\"userData\",\"type\":\"bytes\"}],\"outputs\":[],\"constant\":false,\"stateMutability\":\"nonpayable\"},{\"type\":\"function\",\"name\":\"uniswapV2Call\",\"inputs\":[{\"internalType\":\"address\",\"name\":\"sender\",\"type\":\"address\"},{\"internalType\":\"uint256\",\"name\":\"amount0\",\"type\":\"uint256\"},{\"internalType\":\"uint256\",\"name\":\"amount1\",\"type\":\"uint256\"},{\"internalType\":\"bytes\",\"name\":\"data\",\"type\":\"bytes\"}],\"outputs\":[],\"constant\":false,\"stateMutability\":\"nonpayable\"},{\"type\":\"receive\"}]") . expect ("invalid abi")
+        });
+    #[derive(Clone)]
+    pub struct Executor<M>(ethers::contract::Contract<M>);
+    impl<M> std::ops::Deref for Executor<M> {
+        type Target = ethers::contract::Contract<M>;
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+    impl<M: ethers::providers::Middleware> std::fmt::Debug for Executor<M> {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_tuple(stringify!(Executor))
+                .field(&self.address())
+                .finish()
+        }
+    }
+    impl<'a, M: ethers::providers::Middleware> Executor<M> {
+        #[doc = r" Creates a new contract instance with the specified `ethers`"]
+        #[doc = r" client at the given `Address`. The contract derefs to a `ethers::Contract`"]
+        #[doc = r" object"]
+        pub fn new<T: Into<ethers::core::types::Address>>(
+            address: T,
+            client: ::std::sync::Arc<M>,
+        ) -> Self {
+            let contract =
+                ethers::contract::Contract::new(address.into(), EXECUTOR_ABI.clone(), client);
+            Self(contract)
+        }
+        #[doc = "Calls the contract's `execute` (0x446f30ae) function"]
+        pub fn execute(
+            &self,
+            steps: ::std::vec::Vec<Step>,
+            profit_token: ethers::core::types::Address,
+            min_profit: ethers::core::types::U256,
+            miner_tip: ethers::core::types::U256,
+        ) -> ethers::contract::builders::ContractCall<M, ethers::core::types::U256> {
+            self.0
+                .method_hash(
+                    [68, 111, 48, 174],
+                    (steps, profit_token, min_profit, miner_tip),
+                )
+                .expect("method not found (this should never happen)")
+        }
+        #[doc = "Calls the contract's `executeOperation` (0x920f5c84) function"]
+        pub fn execute_operation(
+            &self,
+            assets: ::std::vec::Vec<ethers::core::types::Address>,
+            amounts: ::std::vec::Vec<ethers::core::types::U256>,
+            premiums: ::std::vec::Vec<ethers::core::types::U256>,
+            initiator: ethers::core::types::Address,
+            params: ethers::core::types::Bytes,
+        ) -> ethers::contract::builders::ContractCall<M, bool> {
+            self.0
+                .method_hash(
+                    [146, 15, 92, 132],
+                    (assets, amounts, premiums, initiator, params),
+                )
+                .expect("method not found (this should never happen)")
+        }
+        #[doc = "Calls the contract's `executeOperation` (0x1b11d0ff) function"]
+        pub fn execute_operation_with_asset_and_amount_and_premium(
+            &self,
+            asset: ethers::core::types::Address,
+            amount: ethers::core::types::U256,
+            premium: ethers::core::types::U256,
+            initiator: ethers::core::types::Address,
+            params: ethers::core::types::Bytes,
+        ) -> ethers::contract::builders::ContractCall<M, bool> {
+            self.0
+                .method_hash(
+                    [27, 17, 208, 255],
+                    (asset, amount, premium, initiator, params),
+                )
+                .expect("method not found (this should never happen)")
+        }
+        #[doc = "Calls the contract's `owner` (0x8da5cb5b) function"]
+        pub fn owner(
+            &self,
+        ) -> ethers::contract::builders::ContractCall<M, ethers::core::types::Address> {
+            self.0
+                .method_hash([141, 165, 203, 91], ())
+                .expect("method not found (this should never happen)")
+        }
+        #[doc = "Calls the contract's `receiveFlashLoan` (0xf04f2707) function"]
+        pub fn receive_flash_loan(
+            &self,
+            tokens: ::std::vec::Vec<ethers::core::types::Address>,
+            amounts: ::std::vec::Vec<ethers::core::types::U256>,
+            fee_amounts: ::std::vec::Vec<ethers::core::types::U256>,
+            user_data: ethers::core::types::Bytes,
+        ) -> ethers::contract::builders::ContractCall<M, ()> {
+            self.0
+                .method_hash([240, 79, 39, 7], (tokens, amounts, fee_amounts, user_data))
+                .expect("method not found (this should never happen)")
+        }
+        #[doc = "Calls the contract's `uniswapV2Call` (0x10d1e85c) function"]
+        pub fn uniswap_v2_call(
+            &self,
+            sender: ethers::core::types::Address,
+            amount_0: ethers::core::types::U256,
+            amount_1: ethers::core::types::U256,
+            data: ethers::core::types::Bytes,
+        ) -> ethers::contract::builders::ContractCall<M, ()> {
+            self.0
+                .method_hash([16, 209, 232, 92], (sender, amount_0, amount_1, data))
+                .expect("method not found (this should never happen)")
+        }
+    }
+    #[doc = "Container type for all input parameters for the `execute`function with signature `execute((address,uint256,bytes,address,address,uint256)[],address,uint256,uint256)` and selector `[68, 111, 48, 174]`"]
+    #[derive(
+        Clone,
+        Debug,
+        Default,
+        Eq,
+        PartialEq,
+        ethers :: contract :: EthCall,
+        ethers :: contract :: EthDisplay,
+    )]
+    #[ethcall(
+        name = "execute",
+        abi = "execute((address,uint256,bytes,address,address,uint256)[],address,uint256,uint256)"
+    )]
+    pub struct ExecuteCall {
+        pub steps: ::std::vec::Vec<Step>,
+        pub profit_token: ethers::core::types::Address,
+        pub min_profit: ethers::core::types::U256,
+        pub miner_tip: ethers::core::types::U256,
+    }
+    #[doc = "Container type for all input parameters for the `execute_operation`function with signature `executeOperation(address[],uint256[],uint256[],address,bytes)` and selector `[146, 15, 92, 132]`"]
+    #[derive(
+        Clone,
+        Debug,
+        Default,
+        Eq,
+        PartialEq,
+        ethers :: contract :: EthCall,
+        ethers :: contract :: EthDisplay,
+    )]
+    #[ethcall(
+        name = "executeOperation",
+        abi = "executeOperation(address[],uint256[],uint256[],address,bytes)"
+    )]
+    pub struct ExecuteOperationCall {
+        pub assets: ::std::vec::Vec<ethers::core::types::Address>,
+        pub amounts: ::std::vec::Vec<ethers::core::types::U256>,
+        pub premiums: ::std::vec::Vec<ethers::core::types::U256>,
+        pub initiator: ethers::core::types::Address,
+        pub params: ethers::core::types::Bytes,
+    }
+    #[doc = "Container type for all input parameters for the `execute_operation_with_asset_and_amount_and_premium`function with signature `executeOperation(address,uint256,uint256,address,bytes)` and selector `[27, 17, 208, 255]`"]
+    #[derive(
+        Clone,
+        Debug,
+        Default,
+        Eq,
+        PartialEq,
+        ethers :: contract :: EthCall,
+        ethers :: contract :: EthDisplay,
+    )]
+    #[ethcall(
+        name = "executeOperation",
+        abi = "executeOperation(address,uint256,uint256,address,bytes)"
+    )]
+    pub struct ExecuteOperationWithAssetAndAmountAndPremiumCall {
+        pub asset: ethers::core::types::Address,
+        pub amount: ethers::core::types::U256,
+        pub premium: ethers::core::types::U256,
+        pub initiator: ethers::core::types::Address,
+        pub params: ethers::core::types::Bytes,
+    }
+    #[doc = "Container type for all input parameters for the `owner`function with signature `owner()` and selector `[141, 165, 203, 91]`"]
+    #[derive(
+        Clone,
+        Debug,
+        Default,
+        Eq,
+        PartialEq,
+        ethers :: contract :: EthCall,
+        ethers :: contract :: EthDisplay,
+    )]
+    #[ethcall(name = "owner", abi = "owner()")]
+    pub struct OwnerCall;
+    #[doc = "Container type for all input parameters for the `receive_flash_loan`function with signature `receiveFlashLoan(address[],uint256[],uint256[],bytes)` and selector `[240, 79, 39, 7]`"]
+    #[derive(
+        Clone,
+        Debug,
+        Default,
+        Eq,
+        PartialEq,
+        ethers :: contract :: EthCall,
+        ethers :: contract :: EthDisplay,
+    )]
+    #[ethcall(
+        name = "receiveFlashLoan",
+        abi = "receiveFlashLoan(address[],uint256[],uint256[],bytes)"
+    )]
+    pub struct ReceiveFlashLoanCall {
+        pub tokens: ::std::vec::Vec<ethers::core::types::Address>,
+        pub amounts: ::std::vec::Vec<ethers::core::types::U256>,
+        pub fee_amounts: ::std::vec::Vec<ethers::core::types::U256>,
+        pub user_data: ethers::core::types::Bytes,
+    }
+    #[doc = "Container type for all input parameters for the `uniswap_v2_call`function with signature `uniswapV2Call(address,uint256,uint256,bytes)` and selector `[16, 209, 232, 92]`"]
+    #[derive(
+        Clone,
+        Debug,
+        Default,
+        Eq,
+        PartialEq,
+        ethers :: contract :: EthCall,
+        ethers :: contract :: EthDisplay,
+    )]
+    #[ethcall(
+        name = "uniswapV2Call",
+        abi = "uniswapV2Call(address,uint256,uint256,bytes)"
+    )]
+    pub struct UniswapV2CallCall {
+        pub sender: ethers::core::types::Address,
+        pub amount_0: ethers::core::types::U256,
+        pub amount_1: ethers::core::types::U256,
+        pub data: ethers::core::types::Bytes,
+    }
+    #[derive(Debug, Clone, PartialEq, Eq, ethers :: contract :: EthAbiType)]
+    pub enum ExecutorCalls {
+        Execute(ExecuteCall),
+        ExecuteOperation(ExecuteOperationCall),
+        ExecuteOperationWithAssetAndAmountAndPremium(
+            ExecuteOperationWithAssetAndAmountAndPremiumCall,
+        ),
+        Owner(OwnerCall),
+        ReceiveFlashLoan(ReceiveFlashLoanCall),
+        UniswapV2Call(UniswapV2CallCall),
+    }
+    impl ethers::core::abi::AbiDecode for ExecutorCalls {
+        fn decode(data: impl AsRef<[u8]>) -> Result<Self, ethers::core::abi::AbiError> {
+            if let Ok(decoded) =
+                <ExecuteCall as ethers::core::abi::AbiDecode>::decode(data.as_ref())
+            {
+                return Ok(ExecutorCalls::Execute(decoded));
+            }
+            if let Ok(decoded) =
+                <ExecuteOperationCall as ethers::core::abi::AbiDecode>::decode(data.as_ref())
+            {
+                return Ok(ExecutorCalls::ExecuteOperation(decoded));
+            }
+            if let Ok(decoded) =
+                <ExecuteOperationWithAssetAndAmountAndPremiumCall as ethers::core::abi::AbiDecode>::decode(data.as_ref())
+            {
+                return Ok(ExecutorCalls::ExecuteOperationWithAssetAndAmountAndPremium(decoded));
+            }
+            if let Ok(decoded) = <OwnerCall as ethers::core::abi::AbiDecode>::decode(data.as_ref())
+            {
+                return Ok(ExecutorCalls::Owner(decoded));
+            }
+            if let Ok(decoded) =
+                <ReceiveFlashLoanCall as ethers::core::abi::AbiDecode>::decode(data.as_ref())
+            {
+                return Ok(ExecutorCalls::ReceiveFlashLoan(decoded));
+            }
+            if let Ok(decoded) =
+                <UniswapV2CallCall as ethers::core::abi::AbiDecode>::decode(data.as_ref())
+            {
+                return Ok(ExecutorCalls::UniswapV2Call(decoded));
+            }
+            Err(ethers::core::abi::Error::InvalidData.into())
+        }
+    }
+    impl ethers::core::abi::AbiEncode for ExecutorCalls {
+        fn encode(self) -> Vec<u8> {
+            match self {
+                ExecutorCalls::Execute(element) => element.encode(),
+                ExecutorCalls::ExecuteOperation(element) => element.encode(),
+                ExecutorCalls::ExecuteOperationWithAssetAndAmountAndPremium(element) => {
+                    element.encode()
+                }
+                ExecutorCalls::Owner(element) => element.encode(),
+                ExecutorCalls::ReceiveFlashLoan(element) => element.encode(),
+                ExecutorCalls::UniswapV2Call(element) => element.encode(),
+            }
+        }
+    }
+    impl ::std::fmt::Display for ExecutorCalls {
+        fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+            match self {
+                ExecutorCalls::Execute(element) => element.fmt(f),
+                ExecutorCalls::ExecuteOperation(element) => element.fmt(f),
+                ExecutorCalls::ExecuteOperationWithAssetAndAmountAndPremium(element) => {
+                    element.fmt(f)
+                }
+                ExecutorCalls::Owner(element) => element.fmt(f),
+                ExecutorCalls::ReceiveFlashLoan(element) => element.fmt(f),
+                ExecutorCalls::UniswapV2Call(element) => element.fmt(f),
+            }
+        }
+    }
+    impl ::std::convert::From<ExecuteCall> for ExecutorCalls {
+        fn from(var: ExecuteCall) -> Self {
+            ExecutorCalls::Execute(var)
+        }
+    }
+    impl ::std::convert::From<ExecuteOperationCall> for ExecutorCalls {
+        fn from(var: ExecuteOperationCall) -> Self {
+            ExecutorCalls::ExecuteOperation(var)
+        }
+    }
+    impl ::std::convert::From<ExecuteOperationWithAssetAndAmountAndPremiumCall> for ExecutorCalls {
+        fn from(var: ExecuteOperationWithAssetAndAmountAndPremiumCall) -> Self {
+            ExecutorCalls::ExecuteOperationWithAssetAndAmountAndPremium(var)
+        }
+    }
+    impl ::std::convert::From<OwnerCall> for ExecutorCalls {
+        fn from(var: OwnerCall) -> Self {
+            ExecutorCalls::Owner(var)
+        }
+    }
+    impl ::std::convert::From<ReceiveFlashLoanCall> for ExecutorCalls {
+        fn from(var: ReceiveFlashLoanCall) -> Self {
+            ExecutorCalls::ReceiveFlashLoan(var)
+        }
+    }
+    impl ::std::convert::From<UniswapV2CallCall> for ExecutorCalls {
+        fn from(var: UniswapV2CallCall) -> Self {
+            ExecutorCalls::UniswapV2Call(var)
+        }
+    }
+    #[doc = "`Step(address,uint256,bytes,address,address,uint256)`"]
+    #[derive(Clone, Debug, Default, Eq, PartialEq, ethers :: contract :: EthAbiType)]
+    pub struct Step {
+        pub target: ethers::core::types::Address,
+        pub value: ethers::core::types::U256,
+        pub data: ethers::core::types::Bytes,
+        pub token: ethers::core::types::Address,
+        pub recipient: ethers::core::types::Address,
+        pub min_out: ethers::core::types::U256,
+    }
+}
@@ -1,4 +1,7 @@
+pub mod arbitrage_swap;
+pub mod executor;
 pub mod ierc20;
+pub mod iweth9;
 /// This module contains all the autogenerated abigen! contract bindings
 pub mod uniswap_v2_factory;
 pub mod uniswap_v2_pair;
@@ -0,0 +1,321 @@
+//! Conversion legs closing cycles that end in another token.
+//!
+//! Paths found with `SCENARIO::DIFFERENT_COIN` don't end in the token they start
+//! with, so the flash-borrowed amount can't be repaid from their output. A last leg
+//! converts the output back: straight through a pool of the two tokens, or through one
+//! of the hub tokens, whichever returns the most. A cycle is only worth sending if it
+//! is still profitable once that leg has traded.
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+
+use ethers::prelude::*;
+use once_cell::sync::Lazy;
+
+use crate::crypto_pair::CryptoPair;
+use crate::opportunity_aggregator::{Hop, ReserveBook};
+use crate::uniswap_providers::mainnet;
+use crate::weth;
+
+/// Tokens with deep pools against most others, tried as the middle of two-hop
+/// conversions: WETH, USDC, USDT and DAI.
+pub static HUB_TOKENS: Lazy<Vec<Address>> = Lazy::new(|| {
+    [
+        "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+        "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+        "0xdAC17F958D2ee523a2206206994597C13D831ec7",
+        "0x6B175474E89094C44Da98b954EedA27d02cC65a1",
+    ]
+    .iter()
+    .map(|token| token.parse().unwrap())
+    .collect()
+});
+
+/// Pairs conversions may trade through, registered once the pair cache is loaded.
+pub static CONVERSION_PAIRS: Lazy<RwLock<Vec<Arc<CryptoPair>>>> =
+    Lazy::new(|| RwLock::new(Vec::new()));
+
+pub fn register_pairs<'a>(pairs: impl IntoIterator<Item = &'a Arc<CryptoPair>>) {
+    CONVERSION_PAIRS.write().unwrap().extend(pairs.into_iter().cloned());
+}
+
+/// Snapshot of the pending reserves of the registered pairs.
+pub fn reserve_book() -> ReserveBook {
+    ReserveBook::from_pairs(CONVERSION_PAIRS.read().unwrap().iter())
+}
+
+/// A value built at most once per block, shared by everything priced on that block.
+pub struct BlockCache<T> {
+    latest: RwLock<Option<(U64, Arc<T>)>>,
+}
+
+impl<T> Default for BlockCache<T> {
+    fn default() -> Self {
+        Self {
+            latest: RwLock::new(None),
+        }
+    }
+}
+
+impl<T> BlockCache<T> {
+    /// The value of `block`, built by `build` unless already cached.
+    pub fn at(&self, block: U64, build: impl FnOnce() -> T) -> Arc<T> {
+        if let Some((cached, value)) = &*self.latest.read().unwrap() {
+            if *cached == block {
+                return value.clone();
+            }
+        }
+        let value = Arc::new(build());
+        let mut latest = self.latest.write().unwrap();
+        match &*latest {
+            Some((cached, _)) if *cached > block => {}
+            _ => *latest = Some((block, value.clone())),
+        }
+        value
+    }
+
+    /// The value of the latest block cached, built by `build` if there is none yet.
+    pub fn latest(&self, build: impl FnOnce() -> T) -> Arc<T> {
+        if let Some((_, value)) = &*self.latest.read().unwrap() {
+            return value.clone();
+        }
+        self.at(U64::zero(), build)
+    }
+}
+
+static RESERVE_BOOKS: Lazy<BlockCache<ReserveBook>> = Lazy::new(Default::default);
+
+/// `reserve_book` as of `block`, built once per block.
+pub fn reserve_book_at(block: U64) -> Arc<ReserveBook> {
+    RESERVE_BOOKS.at(block, reserve_book)
+}
+
+/// A registered pair.
+pub fn pair_of(pool: Address) -> Option<Arc<CryptoPair>> {
+    CONVERSION_PAIRS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|pair| *pair.pair_id() == pool)
+        .cloned()
+}
+
+/// Router of a registered pair.
+pub fn router_of(pool: Address) -> Option<Address> {
+    pair_of(pool).map(|pair| pair.router())
+}
+
+/// Hops converting `amount_in` of one token into another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conversion {
+    pub hops: Vec<Hop>,
+    pub amount_in: U256,
+    /// Output of each hop
+    pub amounts_out: Vec<U256>,
+}
+
+impl Conversion {
+    pub fn amount_out(&self) -> U256 {
+        self.amounts_out.last().copied().unwrap_or(self.amount_in)
+    }
+}
+
+fn simulate(book: &ReserveBook, hops: Vec<Hop>, amount_in: U256) -> Option<Conversion> {
+    let mut book = book.clone();
+    let mut amounts_out = Vec::with_capacity(hops.len());
+    let mut amount = amount_in;
+    for hop in &hops {
+        amount = book.execute(std::slice::from_ref(hop), amount)?;
+        amounts_out.push(amount);
+    }
+    Some(Conversion {
+        hops,
+        amount_in,
+        amounts_out,
+    })
+}
+
+/// The conversion of `amount_in` returning the most `token_out`, direct or through one
+/// of `hubs`, never trading on the `excluded` pools.
+pub fn best_conversion(
+    book: &ReserveBook,
+    token_in: Address,
+    token_out: Address,
+    amount_in: U256,
+    hubs: &[Address],
+    excluded: &HashSet<Address>,
+) -> Option<Conversion> {
+    let hop = |pool, token_in, token_out| Hop {
+        pool,
+        token_in,
+        token_out,
+    };
+    let pools = |a, b| {
+        book.pools_between(a, b)
+            .into_iter()
+            .filter(|pool| !excluded.contains(pool))
+            .collect::<Vec<_>>()
+    };
+
+    let mut routes: Vec<Vec<Hop>> = pools(token_in, token_out)
+        .into_iter()
+        .map(|pool| vec![hop(pool, token_in, token_out)])
+        .collect();
+    for hub in hubs.iter().filter(|hub| ![token_in, token_out].contains(hub)) {
+        for first in pools(token_in, *hub) {
+            for second in pools(*hub, token_out) {
+                routes.push(vec![hop(first, token_in, *hub), hop(second, *hub, token_out)]);
+            }
+        }
+    }
+
+    routes
+        .into_iter()
+        .filter_map(|hops| simulate(book, hops, amount_in))
+        .max_by_key(|conversion| conversion.amount_out())
+}
+
+/// A cycle traded into `target`, with the conversion leg it needed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClosedCycle {
+    pub cycle: Vec<Hop>,
+    pub conversion: Option<Conversion>,
+    pub target: Address,
+    pub amount_in: U256,
+    /// `target` received once every leg has traded
+    pub output: U256,
+    /// What `amount_in` is worth in `target`, to be covered by `output`
+    pub cost: U256,
+}
+
+impl ClosedCycle {
+    pub fn profit(&self) -> Option<U256> {
+        self.output.checked_sub(self.cost)
+    }
+
+    /// The cycle's hops followed by the conversion's.
+    pub fn hops(&self) -> Vec<Hop> {
+        let conversion = self.conversion.iter().flat_map(|c| c.hops.iter());
+        self.cycle.iter().chain(conversion).cloned().collect()
+    }
+}
+
+/// Trades `amount_in` along `hops` and converts the output into `target` if the
+/// cycle ends elsewhere. `target` is the flash-borrowed token, or WETH when profit is
+/// kept in WETH: the input is then valued at the best `target` quote before the
+/// cycle trades, `weth::NATIVE_ETH` standing for WETH. The first pool is left out of
+/// conversions, as a flash-swapped pair is locked until repaid. `None` if the cycle
+/// or the conversion can't execute.
+pub fn close_cycle(
+    book: &ReserveBook,
+    hops: &[Hop],
+    amount_in: U256,
+    target: Address,
+    hubs: &[Address],
+) -> Option<ClosedCycle> {
+    let (first, last) = (hops.first()?, hops.last()?);
+    let target = weth::pool_token(*mainnet::weth, target);
+    let mut traded = book.clone();
+    let cycle_output = traded.execute(hops, amount_in)?;
+
+    let conversion = if last.token_out == target {
+        None
+    } else {
+        let excluded = HashSet::from([first.pool]);
+        Some(best_conversion(
+            &traded,
+            last.token_out,
+            target,
+            cycle_output,
+            hubs,
+            &excluded,
+        )?)
+    };
+    let cost = if first.token_in == target {
+        amount_in
+    } else {
+        best_conversion(book, first.token_in, target, amount_in, hubs, &HashSet::new())?
+            .amount_out()
+    };
+
+    Some(ClosedCycle {
+        cycle: hops.to_vec(),
+        output: conversion
+            .as_ref()
+            .map(Conversion::amount_out)
+            .unwrap_or(cycle_output),
+        conversion,
+        target,
+        amount_in,
+        cost,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{eth, hop, pool, token};
+
+    /// 1 -> 2 -> 3 -> 4 ends in token 4; 4 converts back to 1 directly on pool 4 or
+    /// through hub token 5 on pools 5 and 6.
+    fn book(direct_depth: u64) -> ReserveBook {
+        let mut book = ReserveBook::default();
+        book.insert(pool(1), token(1), token(2), eth(1000), eth(2000));
+        book.insert(pool(2), token(2), token(3), eth(2000), eth(3000));
+        book.insert(pool(3), token(3), token(4), eth(2500), eth(1000));
+        book.insert(pool(4), token(4), token(1), eth(direct_depth), eth(direct_depth));
+        book.insert(pool(5), token(4), token(5), eth(5000), eth(5000));
+        book.insert(pool(6), token(5), token(1), eth(5000), eth(5000));
+        book
+    }
+
+    #[test]
+    fn test_block_cache() {
+        let cache = BlockCache::default();
+        assert_eq!(*cache.latest(|| 0), 0);
+        assert_eq!(*cache.at(U64::from(5), || 1), 1);
+        // Built once for the block, whoever asks
+        assert_eq!(*cache.at(U64::from(5), || 2), 1);
+        assert_eq!(*cache.latest(|| 3), 1);
+        // An older block doesn't replace the newer one
+        assert_eq!(*cache.at(U64::from(4), || 4), 4);
+        assert_eq!(*cache.at(U64::from(5), || 5), 1);
+    }
+
+    #[test]
+    fn test_best_conversion() {
+        let hubs = [Address::repeat_byte(5)];
+        let (from, to) = (Address::repeat_byte(4), Address::repeat_byte(1));
+        let none = HashSet::new();
+
+        let direct = best_conversion(&book(100_000), from, to, eth(10), &hubs, &none).unwrap();
+        assert_eq!(direct.hops, vec![hop(4, 4, 1)]);
+
+        let via_hub = best_conversion(&book(20), from, to, eth(10), &hubs, &none).unwrap();
+        assert_eq!(via_hub.hops, vec![hop(5, 4, 5), hop(6, 5, 1)]);
+        assert_eq!(via_hub.amounts_out.len(), 2);
+
+        let excluded = HashSet::from([Address::repeat_byte(0xa5)]);
+        let only_direct = best_conversion(&book(20), from, to, eth(10), &hubs, &excluded).unwrap();
+        assert_eq!(only_direct.hops, vec![hop(4, 4, 1)]);
+    }
+
+    #[test]
+    fn test_close_cycle_nets_the_conversion() {
+        let hubs = [Address::repeat_byte(5)];
+        let hops = vec![hop(1, 1, 2), hop(2, 2, 3), hop(3, 3, 4)];
+        let book = book(100_000);
+
+        let closed = close_cycle(&book, &hops, eth(5), Address::repeat_byte(1), &hubs).unwrap();
+        let conversion = closed.conversion.clone().unwrap();
+        assert_eq!(closed.hops().len(), 4);
+        assert_eq!(closed.cost, eth(5));
+        assert_eq!(closed.output, conversion.amount_out());
+        // The conversion costs its pool fee: less comes back than the cycle returned
+        assert!(closed.output < conversion.amount_in);
+        assert!(closed.profit().unwrap() > U256::zero());
+
+        let closed_cycle = vec![hop(1, 1, 2), hop(2, 2, 3), hop(3, 3, 4), hop(4, 4, 1)];
+        let closed = close_cycle(&book, &closed_cycle, eth(5), Address::repeat_byte(1), &hubs);
+        assert!(closed.unwrap().conversion.is_none());
+    }
+}
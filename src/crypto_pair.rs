@@ -7,21 +7,20 @@ use std::ops::Sub;
 use bigdecimal::BigDecimal;
 use bigdecimal::FromPrimitive;
 use ethers::core::types::transaction::eip2718::TypedTransaction;
-use ethers::prelude::{Address, U256};
+use ethers::prelude::{Address, H256, U256};
 use futures_signals::signal::{Mutable, MutableSignal};
 use num_bigint::BigInt;
 use num_rational::{BigRational, Ratio};
 use serde::{Deserialize, Serialize};
 
-use crate::dex_pool::DexPool;
+use crate::backrun::Victim;
+use crate::dex_pool::{DexPool, PoolKind};
 use crate::utils::common::DIRECTION;
 use crate::utils::conversions::U256Ext;
 use crate::utils::conversions::*;
 use crate::utils::ratio_as_decimal::*;
 use crate::utils::u256_decimal::*;
 
-const POOL_SWAP_GAS_COST: usize = 60_000;
-
 lazy_static::lazy_static! {
     static ref POOL_MAX_RESERVES: U256 = U256::from((1u128 << 112) - 1);
 }
@@ -45,7 +44,7 @@ pub struct CryptoPair {
     pub(crate) pending_right_reserves: Mutable<ethers::prelude::U256>,
     pub(crate) confirmed_left_reserves: Mutable<ethers::prelude::U256>,
     pub(crate) confirmed_right_reserves: Mutable<ethers::prelude::U256>,
-    pub(crate) pending_txs: Mutable<Vec<TypedTransaction>>,
+    pub(crate) pending_txs: Mutable<Vec<Victim>>,
     pub(crate) fee: Ratio<u32>,
 }
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -139,6 +138,10 @@ impl CryptoPair {
         self.pair.router
     }
 
+    pub fn kind(&self) -> PoolKind {
+        self.pair.kind()
+    }
+
     pub fn left_id(&self) -> &Address {
         &self.pair.token0.id
     }
@@ -236,10 +239,26 @@ impl CryptoPair {
         }
     }
 
-    pub fn pending_txs(&self) -> Vec<TypedTransaction> {
+    /// Pending mempool transactions trading on this pair, to backrun
+    pub fn pending_txs(&self) -> Vec<Victim> {
         self.pending_txs.get_cloned()
     }
 
+    /// Adds a pending transaction, replacing any other with the same sender and nonce
+    pub fn add_pending_tx(&self, victim: Victim) {
+        let mut pending = self.pending_txs.lock_mut();
+        if pending.iter().any(|tx| tx.hash == victim.hash) {
+            return;
+        }
+        pending.retain(|tx| tx.from != victim.from || tx.nonce != victim.nonce);
+        pending.push(victim);
+    }
+
+    /// Drops a pending transaction once it was included or replaced
+    pub fn remove_pending_tx(&self, hash: &H256) {
+        self.pending_txs.lock_mut().retain(|tx| &tx.hash != hash);
+    }
+
     /* Reasoning in terms of price exclusively uses reserves */
     pub fn rational_price(&self, direction: DIRECTION) -> Result<Ratio<BigInt>, anyhow::Error> {
         if direction == DIRECTION::Left {
@@ -1,4 +1,4 @@
-use ethers::prelude::{Address, U256};
+use ethers::prelude::{Address, H256, U256};
 use serde::{Deserialize, Serialize};
 
 use uniswapv2_pairs::uniswap_pairs::UniswapPairsPairsTokens;
@@ -17,3 +17,28 @@ pub struct DexPool {
     pub token0: UniswapPairsPairsTokens,
     pub token1: UniswapPairsPairsTokens,
 }
+
+/// How swaps against a pool are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolKind {
+    /// Uniswap V2 style pair, swapped directly or through its DEX's router
+    V2,
+    /// Uniswap V3 pool, swapped through `exactInputSingle`
+    V3 { fee: u32 },
+    /// Balancer pool, swapped through the Vault
+    Balancer { pool_id: H256 },
+}
+
+impl DexPool {
+    /// Kind of the pool from its source. Balancer pools need their pool id and are
+    /// never loaded as `DexPool`s.
+    pub fn kind(&self) -> PoolKind {
+        if self.dex.contains("v3") {
+            PoolKind::V3 {
+                fee: self.fee_tier.max(0) as u32,
+            }
+        } else {
+            PoolKind::V2
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Typed calls to our executor contract, `contracts/Executor.sol`.
+//!
+//! The executor runs a list of steps, each a call with ether to an arbitrary target:
+//! transfers into pairs, swaps, approvals, repayments of flash liquidity. A swap step
+//! reverts early unless its recipient gains at least the step's `min_out`. The call
+//! reverts unless the steps leave it at least `min_profit` more of `profit_token` than
+//! it held before anything was lent to it, then pays `miner_tip` to `block.coinbase`.
+//! Flash swap and flash loan callbacks hand it the same `execute` calldata, so every
+//! cycle is encoded here.
+use std::fmt;
+
+use ethers::abi::{AbiDecode, AbiEncode};
+use ethers::contract::EthCall;
+use ethers::core::types::transaction::eip2718::TypedTransaction;
+use ethers::prelude::*;
+
+pub use crate::contracts::bindings::executor::{ExecuteCall, Step};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutorCallError {
+    NoLegs,
+    /// No leg trades back into the token owed to the lender
+    BrokenCycle,
+    Unprofitable {
+        expected_output: U256,
+        repayment: U256,
+    },
+    Decode(String),
+    /// A coinbase tip paid in ether out of profit in another token than WETH
+    TipNotInWeth {
+        profit_token: Address,
+    },
+}
+
+impl fmt::Display for ExecutorCallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecutorCallError::NoLegs => write!(f, "the call has no legs"),
+            ExecutorCallError::BrokenCycle => write!(f, "legs don't form a cycle"),
+            ExecutorCallError::Unprofitable {
+                expected_output,
+                repayment,
+            } => write!(
+                f,
+                "expected output {} doesn't cover the repayment of {}",
+                expected_output, repayment
+            ),
+            ExecutorCallError::Decode(e) => write!(f, "invalid executor calldata: {}", e),
+            ExecutorCallError::TipNotInWeth { profit_token } => write!(
+                f,
+                "can't unwrap a coinbase tip out of {:?} profit",
+                profit_token
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExecutorCallError {}
+
+/// One call the executor makes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutorLeg {
+    pub target: Address,
+    /// Ether sent with the call
+    pub value: U256,
+    pub calldata: Bytes,
+    /// Token the leg trades into, zero for transfers and approvals
+    pub token_out: Address,
+    /// Amount of `token_out` the leg is simulated to return
+    pub expected_output: U256,
+    /// Receiver of the output, checked for `min_output`
+    pub recipient: Address,
+    /// Least output the executor accepts, zero for no check
+    pub min_output: U256,
+}
+
+impl ExecutorLeg {
+    /// A call which trades nothing, such as a transfer or an approval.
+    pub fn call(target: Address, calldata: Bytes) -> Self {
+        Self {
+            target,
+            value: U256::zero(),
+            calldata,
+            token_out: Address::zero(),
+            expected_output: U256::zero(),
+            recipient: Address::zero(),
+            min_output: U256::zero(),
+        }
+    }
+
+    /// A swap expected to return `expected_output` of `token_out`.
+    pub fn swap(
+        target: Address,
+        calldata: Bytes,
+        token_out: Address,
+        expected_output: U256,
+    ) -> Self {
+        Self {
+            token_out,
+            expected_output,
+            ..Self::call(target, calldata)
+        }
+    }
+
+    pub fn with_value(mut self, value: U256) -> Self {
+        self.value = value;
+        self
+    }
+
+    /// Has the executor revert unless `recipient` gains at least `min_output` of
+    /// `token_out` from the leg, see `SlippagePolicy::min_out`.
+    pub fn with_min_output(mut self, recipient: Address, min_output: U256) -> Self {
+        self.recipient = recipient;
+        self.min_output = min_output;
+        self
+    }
+
+    fn step(&self) -> Step {
+        let checked = !self.min_output.is_zero();
+        Step {
+            target: self.target,
+            value: self.value,
+            data: self.calldata.clone(),
+            token: if checked { self.token_out } else { Address::zero() },
+            recipient: self.recipient,
+            min_out: self.min_output,
+        }
+    }
+}
+
+/// What the executor owes for the liquidity the cycle started with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repayment {
+    pub pool: Address,
+    pub token: Address,
+    pub amount: U256,
+}
+
+/// A call to the executor at `target`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutorCall {
+    pub target: Address,
+    /// Ether sent with the call
+    pub value: U256,
+    pub legs: Vec<ExecutorLeg>,
+    pub repayment: Repayment,
+    /// Least profit, in the repayment token, left once every leg ran and before the
+    /// tip is unwrapped from it
+    pub min_profit: U256,
+    /// Ether paid to `block.coinbase` once every leg ran
+    pub miner_tip: U256,
+}
+
+impl ExecutorCall {
+    pub fn new(target: Address, repayment: Repayment) -> Self {
+        Self {
+            target,
+            value: U256::zero(),
+            legs: Vec::new(),
+            repayment,
+            min_profit: U256::zero(),
+            miner_tip: U256::zero(),
+        }
+    }
+
+    pub fn push(&mut self, leg: ExecutorLeg) {
+        self.legs.push(leg);
+    }
+
+    pub fn with_legs(mut self, legs: impl IntoIterator<Item = ExecutorLeg>) -> Self {
+        self.legs.extend(legs);
+        self
+    }
+
+    pub fn with_min_profit(mut self, min_profit: U256) -> Self {
+        self.min_profit = min_profit;
+        self
+    }
+
+    pub fn with_miner_tip(mut self, miner_tip: U256) -> Self {
+        self.miner_tip = miner_tip;
+        self
+    }
+
+    /// Amount of the repayment token the last leg trading into it returns.
+    pub fn expected_output(&self) -> U256 {
+        self.legs
+            .iter()
+            .rev()
+            .find(|leg| leg.token_out == self.repayment.token)
+            .map(|leg| leg.expected_output)
+            .unwrap_or_default()
+    }
+
+    /// What is left once the liquidity is repaid.
+    pub fn profit(&self) -> Option<U256> {
+        self.expected_output().checked_sub(self.repayment.amount)
+    }
+
+    /// Arguments of the executor's `execute`, after checking the legs trade back into
+    /// the repayment token and cover the repayment. The tip is unwrapped out of the
+    /// profit, see `weth::tip_steps`, so the executor checks for what `min_profit`
+    /// leaves once it is paid.
+    pub fn execute_call(&self) -> Result<ExecuteCall, ExecutorCallError> {
+        if self.legs.is_empty() {
+            return Err(ExecutorCallError::NoLegs);
+        }
+        if !self
+            .legs
+            .iter()
+            .any(|leg| leg.token_out == self.repayment.token)
+        {
+            return Err(ExecutorCallError::BrokenCycle);
+        }
+        self.profit().ok_or(ExecutorCallError::Unprofitable {
+            expected_output: self.expected_output(),
+            repayment: self.repayment.amount,
+        })?;
+
+        Ok(ExecuteCall {
+            steps: self.legs.iter().map(ExecutorLeg::step).collect(),
+            profit_token: self.repayment.token,
+            min_profit: self.min_profit.saturating_sub(self.miner_tip),
+            miner_tip: self.miner_tip,
+        })
+    }
+
+    /// Calldata of the call, selector included. Also the data flash swap and flash
+    /// loan callbacks are handed.
+    pub fn encode(&self) -> Result<Bytes, ExecutorCallError> {
+        Ok(Bytes::from(self.execute_call()?.encode()))
+    }
+
+    /// Decodes calldata sent to the executor, such as the input of one of our
+    /// transactions.
+    pub fn decode(data: &[u8]) -> Result<ExecuteCall, ExecutorCallError> {
+        if data.len() < 4 || data[..4] != ExecuteCall::selector() {
+            return Err(ExecutorCallError::Decode("unknown selector".to_string()));
+        }
+        ExecuteCall::decode(data).map_err(|e| ExecutorCallError::Decode(e.to_string()))
+    }
+
+    /// Unsigned transaction running the call, to be priced and signed.
+    pub fn to_transaction(&self) -> Result<TypedTransaction, ExecutorCallError> {
+        Ok(Eip1559TransactionRequest::new()
+            .to(self.target)
+            .value(self.value)
+            .data(self.encode()?)
+            .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Borrows `repayment` of WETH, swaps it to DAI and back through two pools.
+    fn call(repayment: u64, intermediate: u64, output: u64) -> ExecutorCall {
+        let weth = Address::repeat_byte(1);
+        let dai = Address::repeat_byte(2);
+        ExecutorCall::new(
+            Address::repeat_byte(0xee),
+            Repayment {
+                pool: Address::repeat_byte(0xa1),
+                token: weth,
+                amount: U256::from(repayment),
+            },
+        )
+        .with_legs([
+            ExecutorLeg::call(weth, Bytes::from(vec![1])),
+            ExecutorLeg::swap(
+                Address::repeat_byte(0xa1),
+                Bytes::from(vec![2]),
+                dai,
+                U256::from(intermediate),
+            ),
+            ExecutorLeg::swap(
+                Address::repeat_byte(0xa2),
+                Bytes::from(vec![3]),
+                weth,
+                U256::from(output),
+            )
+            .with_min_output(Address::repeat_byte(0xee), U256::from(output - 3)),
+            ExecutorLeg::call(weth, Bytes::from(vec![4])),
+        ])
+        .with_min_profit(U256::from(50))
+        .with_miner_tip(U256::from(7))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let call = call(1000, 2_000_000, 1050);
+        assert_eq!(call.profit(), Some(U256::from(50)));
+        let data = call.encode().unwrap();
+        assert_eq!(data[..4], ExecuteCall::selector());
+
+        let decoded = ExecutorCall::decode(&data).unwrap();
+        assert_eq!(decoded, call.execute_call().unwrap());
+        assert_eq!(decoded.steps.len(), 4);
+        assert_eq!(decoded.steps[1].target, Address::repeat_byte(0xa1));
+        // Only the guarded swap is checked, on its recipient's balance
+        assert_eq!(decoded.steps[1].token, Address::zero());
+        assert_eq!(decoded.steps[2].token, Address::repeat_byte(1));
+        assert_eq!(decoded.steps[2].recipient, Address::repeat_byte(0xee));
+        assert_eq!(decoded.steps[2].min_out, U256::from(1047));
+        assert_eq!(decoded.profit_token, Address::repeat_byte(1));
+        assert_eq!(decoded.miner_tip, U256::from(7));
+        // What is left of the profit once the tip is unwrapped from it
+        assert_eq!(decoded.min_profit, U256::from(43));
+    }
+
+    #[test]
+    fn test_rejects_invalid_calls() {
+        assert_eq!(
+            call(1000, 2_000_000, 990).encode(),
+            Err(ExecutorCallError::Unprofitable {
+                expected_output: U256::from(990),
+                repayment: U256::from(1000),
+            })
+        );
+
+        let mut broken = call(1000, 2_000_000, 1050);
+        broken.legs[2].token_out = Address::repeat_byte(3);
+        assert_eq!(broken.encode(), Err(ExecutorCallError::BrokenCycle));
+
+        broken.legs.clear();
+        assert_eq!(broken.encode(), Err(ExecutorCallError::NoLegs));
+
+        assert!(ExecutorCall::decode(&[0, 1, 2, 3, 4]).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Base fee and priority fee of the next block.
+//!
+//! A watcher follows new blocks and records their `baseFeePerGas`, how full they
+//! were against the EIP-1559 gas target, and the priority fees their transactions
+//! paid. The next base fee follows from the latest block by the EIP-1559 formula; the
+//! priority fee is a percentile of what recent blocks included. Bids and profit
+//! estimates read `mainnet::fee_predictor` instead of querying a block each time.
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+
+use ethers::prelude::*;
+
+use crate::bid_policy::{next_base_fee, BASE_FEE_MAX_CHANGE_DENOMINATOR, ELASTICITY_MULTIPLIER};
+
+/// Blocks whose priority fees make up the distribution
+const DEFAULT_WINDOW: usize = 20;
+
+/// Fees of one block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockFees {
+    pub number: U64,
+    pub base_fee: U256,
+    pub gas_used: U256,
+    pub gas_limit: U256,
+    /// Priority fee per gas each transaction paid, ascending
+    pub priority_fees: Vec<U256>,
+}
+
+/// Priority fee per gas `tx` pays in a block with `base_fee`.
+pub fn priority_fee(tx: &Transaction, base_fee: U256) -> U256 {
+    match (tx.max_fee_per_gas, tx.max_priority_fee_per_gas) {
+        (Some(max_fee), Some(max_priority_fee)) => {
+            max_priority_fee.min(max_fee.saturating_sub(base_fee))
+        }
+        _ => tx.gas_price.unwrap_or_default().saturating_sub(base_fee),
+    }
+}
+
+impl BlockFees {
+    /// `None` for pending or pre-London blocks.
+    pub fn from_block(block: &Block<Transaction>) -> Option<Self> {
+        let base_fee = block.base_fee_per_gas?;
+        let mut priority_fees: Vec<U256> = block
+            .transactions
+            .iter()
+            .map(|tx| priority_fee(tx, base_fee))
+            .collect();
+        priority_fees.sort();
+        Some(Self {
+            number: block.number?,
+            base_fee,
+            gas_used: block.gas_used,
+            gas_limit: block.gas_limit,
+            priority_fees,
+        })
+    }
+
+    pub fn gas_target(&self) -> U256 {
+        self.gas_limit / ELASTICITY_MULTIPLIER
+    }
+
+    /// Gas used over target: 1 keeps the base fee, 2 is a full block.
+    pub fn fullness(&self) -> f64 {
+        if self.gas_target().is_zero() {
+            return 0.0;
+        }
+        self.gas_used.as_u128() as f64 / self.gas_target().as_u128() as f64
+    }
+
+    pub fn next_base_fee(&self) -> U256 {
+        next_base_fee(self.base_fee, self.gas_used, self.gas_limit)
+    }
+}
+
+/// Fees expected for the next block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeEstimate {
+    /// Block the estimate follows
+    pub block: U64,
+    pub next_base_fee: U256,
+    /// Priority fee at the predictor's percentile of recent blocks
+    pub priority_fee: U256,
+}
+
+/// Recent block fees and predictions drawn from them.
+#[derive(Debug, Clone)]
+pub struct FeePredictor {
+    history: VecDeque<BlockFees>,
+    window: usize,
+    /// Percentile of recent priority fees used as the estimate, in `0..=100`
+    pub percentile: u8,
+}
+
+impl Default for FeePredictor {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW, 50)
+    }
+}
+
+impl FeePredictor {
+    pub fn new(window: usize, percentile: u8) -> Self {
+        Self {
+            history: VecDeque::with_capacity(window),
+            window: window.max(1),
+            percentile: percentile.min(100),
+        }
+    }
+
+    /// Records `fees`, dropping blocks beyond the window. Reorged blocks replace the
+    /// ones they orphan.
+    pub fn observe(&mut self, fees: BlockFees) {
+        while matches!(self.history.back(), Some(last) if last.number >= fees.number) {
+            self.history.pop_back();
+        }
+        self.history.push_back(fees);
+        while self.history.len() > self.window {
+            self.history.pop_front();
+        }
+    }
+
+    pub fn latest(&self) -> Option<&BlockFees> {
+        self.history.back()
+    }
+
+    pub fn next_base_fee(&self) -> Option<U256> {
+        self.latest().map(BlockFees::next_base_fee)
+    }
+
+    /// Highest base fee possible `blocks` after the next one, every block full.
+    /// Bundles targeting several blocks must stay valid up to their last.
+    pub fn max_base_fee_in(&self, blocks: u64) -> Option<U256> {
+        let mut base_fee = self.next_base_fee()?;
+        for _ in 0..blocks {
+            base_fee = base_fee + base_fee / BASE_FEE_MAX_CHANGE_DENOMINATOR;
+        }
+        Some(base_fee)
+    }
+
+    /// `percentile` of the priority fees paid over the window, zero without data.
+    pub fn priority_fee_at(&self, percentile: u8) -> U256 {
+        let mut fees: Vec<U256> = self
+            .history
+            .iter()
+            .flat_map(|block| block.priority_fees.iter().copied())
+            .collect();
+        if fees.is_empty() {
+            return U256::zero();
+        }
+        fees.sort();
+        let index = (fees.len() - 1) * usize::from(percentile.min(100)) / 100;
+        fees[index]
+    }
+
+    pub fn estimate(&self) -> Option<FeeEstimate> {
+        let latest = self.latest()?;
+        Some(FeeEstimate {
+            block: latest.number,
+            next_base_fee: latest.next_base_fee(),
+            priority_fee: self.priority_fee_at(self.percentile),
+        })
+    }
+
+    /// Estimate for the block after `block`: the watcher's if it has seen `block`,
+    /// otherwise from `block` alone, without priority fees.
+    pub fn estimate_after<T>(&self, block: &Block<T>) -> FeeEstimate {
+        match self.estimate() {
+            Some(estimate) if Some(estimate.block) == block.number => estimate,
+            _ => FeeEstimate {
+                block: block.number.unwrap_or_default(),
+                next_base_fee: crate::bid_policy::next_base_fee_of(block),
+                priority_fee: U256::zero(),
+            },
+        }
+    }
+}
+
+/// Follows new blocks of `client` into `predictor` until the block stream ends.
+pub async fn watch<M: Middleware>(client: Arc<M>, predictor: &RwLock<FeePredictor>) {
+    let mut blocks = match client.watch_blocks().await {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            println!("Fee predictor failed to watch blocks: {}", e);
+            return;
+        }
+    };
+    while let Some(hash) = blocks.next().await {
+        match client.get_block_with_txs(hash).await {
+            Ok(Some(block)) => {
+                if let Some(fees) = BlockFees::from_block(&block) {
+                    println!(
+                        "Block {} base fee: {} fullness: {:.2} next base fee: {}",
+                        fees.number,
+                        fees.base_fee,
+                        fees.fullness(),
+                        fees.next_base_fee()
+                    );
+                    predictor.write().unwrap().observe(fees);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => println!("Fee predictor failed to get block {:?}: {}", hash, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::gwei;
+
+    fn fees(number: u64, gas_used: u64, priority_fees: &[u64]) -> BlockFees {
+        BlockFees {
+            number: U64::from(number),
+            base_fee: gwei(100),
+            gas_used: U256::from(gas_used),
+            gas_limit: U256::from(30_000_000),
+            priority_fees: priority_fees.iter().map(|fee| gwei(*fee)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_priority_fee() {
+        let dynamic = Transaction {
+            max_fee_per_gas: Some(gwei(105)),
+            max_priority_fee_per_gas: Some(gwei(10)),
+            ..Transaction::default()
+        };
+        assert_eq!(priority_fee(&dynamic, gwei(100)), gwei(5));
+        assert_eq!(priority_fee(&dynamic, gwei(90)), gwei(10));
+
+        let legacy = Transaction {
+            gas_price: Some(gwei(103)),
+            ..Transaction::default()
+        };
+        assert_eq!(priority_fee(&legacy, gwei(100)), gwei(3));
+    }
+
+    #[test]
+    fn test_predicts_next_block() {
+        let mut predictor = FeePredictor::new(2, 50);
+        assert!(predictor.estimate().is_none());
+
+        predictor.observe(fees(1, 15_000_000, &[1, 2, 3]));
+        predictor.observe(fees(2, 30_000_000, &[4, 5]));
+        let estimate = predictor.estimate().unwrap();
+        assert_eq!(estimate.block, U64::from(2));
+        // A full block raises the base fee by an eighth
+        assert_eq!(estimate.next_base_fee, gwei(112_500_000) / 1_000_000);
+        assert_eq!(estimate.priority_fee, gwei(3));
+        assert_eq!(predictor.max_base_fee_in(1), Some(U256::from(126_562_500_000_u64)));
+
+        // The window drops block 1, and a reorged block 3 replaces the first one
+        predictor.observe(fees(3, 0, &[7]));
+        predictor.observe(fees(3, 15_000_000, &[9]));
+        assert_eq!(predictor.latest().unwrap().priority_fees, vec![gwei(9)]);
+        assert_eq!(predictor.priority_fee_at(0), gwei(4));
+        assert_eq!(predictor.next_base_fee(), Some(gwei(100)));
+    }
+}
@@ -0,0 +1,650 @@
+//! Flash liquidity other than Uniswap V2 flash swaps.
+//!
+//! A flash swap makes the first hop the lending pair. Borrowing the cycle's input from
+//! a lender instead lets every hop be a plain `pair.swap`, at the lender's fee: none
+//! for the Balancer Vault, a premium on Aave. `plan_cycle` compares every source with
+//! the flash swap and keeps the most profitable plan. What each lender can lend is
+//! read from its balances by `load_sources`, refreshed every block by `watch_liquidity`.
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use ethers::abi::AbiEncode;
+use ethers::contract::EthCall;
+use ethers::core::types::transaction::eip2718::TypedTransaction;
+use ethers::prelude::*;
+use once_cell::sync::Lazy;
+
+use crate::contracts::bindings::ierc20::{ApproveCall, IERC20};
+use crate::executor_call::{ExecutorCall, ExecutorCallError, ExecutorLeg, Repayment};
+use crate::flash_swap::{
+    chain_legs, plan_for_input, transfer, FlashSwapError, FlashSwapPlan, PairSwap,
+};
+use crate::opportunity_aggregator::{Hop, ReserveBook};
+use crate::slippage::SlippagePolicy;
+use crate::uniswap_providers::mainnet;
+use crate::weth::tip_steps;
+
+pub static BALANCER_VAULT: Lazy<Address> = Lazy::new(|| {
+    "0xBA12222222228d8Ba445958a75a0704d566BF2C8"
+        .parse()
+        .unwrap()
+});
+pub static AAVE_V2_LENDING_POOL: Lazy<Address> = Lazy::new(|| {
+    "0x7d2768dE32b0b80b7a3454c06BdAc94A69DDc7A9"
+        .parse()
+        .unwrap()
+});
+pub static AAVE_V3_POOL: Lazy<Address> = Lazy::new(|| {
+    "0x87870Bca3F3fD6335C3F4ce8392D69350B4fA4E2"
+        .parse()
+        .unwrap()
+});
+
+/// `Vault.flashLoan`, calling `receiveFlashLoan` on the recipient
+#[derive(Clone, Debug, Default, Eq, PartialEq, EthCall)]
+#[ethcall(name = "flashLoan", abi = "flashLoan(address,address[],uint256[],bytes)")]
+pub struct BalancerFlashLoanCall {
+    pub recipient: Address,
+    pub tokens: Vec<Address>,
+    pub amounts: Vec<U256>,
+    pub user_data: Bytes,
+}
+
+/// Aave v2 `LendingPool.flashLoan`, calling `executeOperation` on the receiver
+#[derive(Clone, Debug, Default, Eq, PartialEq, EthCall)]
+#[ethcall(
+    name = "flashLoan",
+    abi = "flashLoan(address,address[],uint256[],uint256[],address,bytes,uint16)"
+)]
+pub struct AaveFlashLoanCall {
+    pub receiver_address: Address,
+    pub assets: Vec<Address>,
+    pub amounts: Vec<U256>,
+    /// 0 for every asset: repaid within the transaction, no debt opened
+    pub modes: Vec<U256>,
+    pub on_behalf_of: Address,
+    pub params: Bytes,
+    pub referral_code: u16,
+}
+
+/// Aave v3 `Pool.flashLoanSimple`, calling `executeOperation` on the receiver
+#[derive(Clone, Debug, Default, Eq, PartialEq, EthCall)]
+#[ethcall(
+    name = "flashLoanSimple",
+    abi = "flashLoanSimple(address,address,uint256,bytes,uint16)"
+)]
+pub struct AaveFlashLoanSimpleCall {
+    pub receiver_address: Address,
+    pub asset: Address,
+    pub amount: U256,
+    pub params: Bytes,
+    pub referral_code: u16,
+}
+
+/// A lender of flash loans.
+pub trait FlashSource: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Contract the loan is requested from
+    fn lender(&self) -> Address;
+
+    /// Fee owed on top of `amount`, `None` if the source can't lend it.
+    fn fee(&self, token: Address, amount: U256) -> Option<U256>;
+
+    /// Calldata requesting `amount` of `token` for `receiver`, handing `data` back to
+    /// it in the callback.
+    fn loan_calldata(&self, token: Address, amount: U256, receiver: Address, data: Bytes)
+        -> Bytes;
+
+    /// Last step of the callback, settling `owed` of `token` with the lender.
+    fn repayment_step(&self, token: Address, owed: U256) -> ExecutorLeg;
+}
+
+/// `getReserveData` of an Aave pool, whose result holds the asset's aToken
+#[derive(Clone, Debug, Default, Eq, PartialEq, EthCall)]
+#[ethcall(name = "getReserveData", abi = "getReserveData(address)")]
+pub struct AaveGetReserveDataCall {
+    pub asset: Address,
+}
+
+/// Tokens and amounts a source can lend, from the lender's balances.
+pub type Liquidity = HashMap<Address, U256>;
+
+/// Every source loaded, swapped whole when reloaded.
+pub type FlashSources = Arc<Vec<Box<dyn FlashSource>>>;
+
+fn available(liquidity: &Liquidity, token: Address, amount: U256) -> bool {
+    liquidity.get(&token).is_some_and(|available| *available >= amount)
+}
+
+/// Balancer V2 Vault flash loans, free of charge. The loan is repaid by
+/// transferring it back to the Vault.
+#[derive(Debug, Clone)]
+pub struct BalancerVaultSource {
+    pub vault: Address,
+    pub liquidity: Liquidity,
+}
+
+impl BalancerVaultSource {
+    pub fn mainnet(liquidity: Liquidity) -> Self {
+        Self {
+            vault: *BALANCER_VAULT,
+            liquidity,
+        }
+    }
+}
+
+impl FlashSource for BalancerVaultSource {
+    fn name(&self) -> &str {
+        "balancer"
+    }
+
+    fn lender(&self) -> Address {
+        self.vault
+    }
+
+    fn fee(&self, token: Address, amount: U256) -> Option<U256> {
+        available(&self.liquidity, token, amount).then(U256::zero)
+    }
+
+    fn loan_calldata(&self, token: Address, amount: U256, receiver: Address, data: Bytes) -> Bytes {
+        Bytes::from(
+            BalancerFlashLoanCall {
+                recipient: receiver,
+                tokens: vec![token],
+                amounts: vec![amount],
+                user_data: data,
+            }
+            .encode(),
+        )
+    }
+
+    fn repayment_step(&self, token: Address, owed: U256) -> ExecutorLeg {
+        transfer(token, self.vault, owed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AaveVersion {
+    V2,
+    V3,
+}
+
+impl AaveVersion {
+    /// aToken of a `getReserveData` result: every field is a static word, the aToken
+    /// follows the rates and timestamp, and v3's reserve id.
+    pub fn a_token(&self, reserve_data: &[u8]) -> Option<Address> {
+        let word = match self {
+            AaveVersion::V2 => 7,
+            AaveVersion::V3 => 8,
+        };
+        let word = reserve_data.get(word * 32..(word + 1) * 32)?;
+        Some(Address::from_slice(&word[12..]))
+    }
+}
+
+/// Aave lending pool flash loans. The pool pulls the loan and its premium back
+/// after the callback, so the executor only approves it.
+#[derive(Debug, Clone)]
+pub struct AaveSource {
+    pub pool: Address,
+    pub version: AaveVersion,
+    /// Premium in basis points of the amount
+    pub premium_bps: u64,
+    pub liquidity: Liquidity,
+}
+
+impl AaveSource {
+    pub fn mainnet_v2(liquidity: Liquidity) -> Self {
+        Self {
+            pool: *AAVE_V2_LENDING_POOL,
+            version: AaveVersion::V2,
+            premium_bps: 9,
+            liquidity,
+        }
+    }
+
+    pub fn mainnet_v3(liquidity: Liquidity) -> Self {
+        Self {
+            pool: *AAVE_V3_POOL,
+            version: AaveVersion::V3,
+            premium_bps: 5,
+            liquidity,
+        }
+    }
+}
+
+impl FlashSource for AaveSource {
+    fn name(&self) -> &str {
+        match self.version {
+            AaveVersion::V2 => "aave-v2",
+            AaveVersion::V3 => "aave-v3",
+        }
+    }
+
+    fn lender(&self) -> Address {
+        self.pool
+    }
+
+    fn fee(&self, token: Address, amount: U256) -> Option<U256> {
+        if !available(&self.liquidity, token, amount) {
+            return None;
+        }
+        let premium = amount.checked_mul(U256::from(self.premium_bps))?;
+        Some(match self.version {
+            // `amount.mul(premium).div(10000)`
+            AaveVersion::V2 => premium / 10_000,
+            // `amount.percentMul(premium)`, rounding half up
+            AaveVersion::V3 => (premium + 5_000) / 10_000,
+        })
+    }
+
+    fn loan_calldata(&self, token: Address, amount: U256, receiver: Address, data: Bytes) -> Bytes {
+        let calldata = match self.version {
+            AaveVersion::V2 => AaveFlashLoanCall {
+                receiver_address: receiver,
+                assets: vec![token],
+                amounts: vec![amount],
+                modes: vec![U256::zero()],
+                on_behalf_of: receiver,
+                params: data,
+                referral_code: 0,
+            }
+            .encode(),
+            AaveVersion::V3 => AaveFlashLoanSimpleCall {
+                receiver_address: receiver,
+                asset: token,
+                amount,
+                params: data,
+                referral_code: 0,
+            }
+            .encode(),
+        };
+        Bytes::from(calldata)
+    }
+
+    fn repayment_step(&self, token: Address, owed: U256) -> ExecutorLeg {
+        ExecutorLeg::call(
+            token,
+            Bytes::from(
+                ApproveCall {
+                    spender: self.pool,
+                    amount: owed,
+                }
+                .encode(),
+            ),
+        )
+    }
+}
+
+/// Balances `holder` has of each of `tokens`. Tokens whose balance can't be read
+/// are left out.
+pub async fn balances_of<M: Middleware + 'static>(
+    client: Arc<M>,
+    holder: Address,
+    tokens: &[Address],
+) -> Liquidity {
+    let mut liquidity = Liquidity::new();
+    for token in tokens {
+        match IERC20::new(*token, client.clone()).balance_of(holder).call().await {
+            Ok(balance) => {
+                liquidity.insert(*token, balance);
+            }
+            Err(e) => println!("Failed to read the balance of {:?} in {:?}: {}", holder, token, e),
+        }
+    }
+    liquidity
+}
+
+/// What an Aave pool can lend of each of `tokens`: the balance its aToken holds.
+/// Tokens the pool doesn't list are left out.
+pub async fn aave_liquidity<M: Middleware + 'static>(
+    client: Arc<M>,
+    pool: Address,
+    version: AaveVersion,
+    tokens: &[Address],
+) -> Liquidity {
+    let mut liquidity = Liquidity::new();
+    for token in tokens {
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .to(pool)
+            .data(AaveGetReserveDataCall { asset: *token }.encode())
+            .into();
+        let a_token = match client.call(&tx, None).await {
+            Ok(data) => match version.a_token(&data) {
+                Some(a_token) if !a_token.is_zero() => a_token,
+                _ => continue,
+            },
+            Err(e) => {
+                println!("Failed to read the {:?} reserve of {:?}: {}", token, pool, e);
+                continue;
+            }
+        };
+        liquidity.extend(balances_of(client.clone(), a_token, &[*token]).await);
+    }
+    liquidity
+}
+
+/// The Balancer Vault and both Aave pools, with what they can lend of `tokens`.
+pub async fn load_sources<M: Middleware + 'static>(
+    client: Arc<M>,
+    tokens: &[Address],
+) -> Vec<Box<dyn FlashSource>> {
+    let balancer = balances_of(client.clone(), *BALANCER_VAULT, tokens).await;
+    let aave_v2 = aave_liquidity(client.clone(), *AAVE_V2_LENDING_POOL, AaveVersion::V2, tokens).await;
+    let aave_v3 = aave_liquidity(client, *AAVE_V3_POOL, AaveVersion::V3, tokens).await;
+    vec![
+        Box::new(BalancerVaultSource::mainnet(balancer)),
+        Box::new(AaveSource::mainnet_v2(aave_v2)),
+        Box::new(AaveSource::mainnet_v3(aave_v3)),
+    ]
+}
+
+/// Reloads `sources` with the lenders' liquidity in the hub tokens on every block.
+pub async fn watch_liquidity<M: Middleware + 'static>(
+    client: Arc<M>,
+    sources: &RwLock<FlashSources>,
+) {
+    let mut blocks = match client.watch_blocks().await {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            println!("Flash sources failed to watch blocks: {}", e);
+            return;
+        }
+    };
+    loop {
+        let loaded = load_sources(client.clone(), &crate::conversion::HUB_TOKENS).await;
+        *sources.write().unwrap() = Arc::new(loaded);
+        if blocks.next().await.is_none() {
+            return;
+        }
+    }
+}
+
+/// Source lending `amount` of `token` at the lowest fee, with that fee.
+pub fn cheapest_source(
+    sources: &[Box<dyn FlashSource>],
+    token: Address,
+    amount: U256,
+) -> Option<(&dyn FlashSource, U256)> {
+    sources
+        .iter()
+        .filter_map(|source| Some((source.as_ref(), source.fee(token, amount)?)))
+        .min_by_key(|(_, fee)| *fee)
+}
+
+/// A cycle funded by a flash loan of its input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashLoanPlan {
+    pub source: String,
+    pub lender: Address,
+    pub executor: Address,
+    pub token: Address,
+    pub amount: U256,
+    pub fee: U256,
+    pub legs: Vec<PairSwap>,
+    /// Step settling the loan, see `FlashSource::repayment_step`
+    pub repayment: ExecutorLeg,
+}
+
+impl FlashLoanPlan {
+    /// Plans borrowing `amount_in` of the first hop's input from `source` and
+    /// trading it along every hop, each accepting `slippage`.
+    pub fn build(
+        source: &dyn FlashSource,
+        hops: &[Hop],
+        book: &ReserveBook,
+        amount_in: U256,
+        executor: Address,
+        slippage: &SlippagePolicy,
+    ) -> Result<Option<Self>, FlashSwapError> {
+        let first = hops.first().ok_or(FlashSwapError::TooFewHops)?;
+        let fee = match source.fee(first.token_in, amount_in) {
+            Some(fee) => fee,
+            None => return Ok(None),
+        };
+        let legs = chain_legs(hops, &mut book.clone(), amount_in, executor, slippage)?;
+        if legs.last().map(|leg| leg.token_out) != Some(first.token_in) {
+            return Err(FlashSwapError::BrokenCycle);
+        }
+        Ok(Some(Self {
+            source: source.name().to_string(),
+            lender: source.lender(),
+            executor,
+            token: first.token_in,
+            amount: amount_in,
+            fee,
+            legs,
+            repayment: source.repayment_step(first.token_in, amount_in + fee),
+        }))
+    }
+
+    pub fn output(&self) -> U256 {
+        self.legs
+            .last()
+            .map(|leg| leg.amount_out)
+            .unwrap_or_default()
+    }
+
+    pub fn profit(&self) -> Option<U256> {
+        self.output().checked_sub(self.amount + self.fee)
+    }
+
+    /// Calls the executor makes from the lender's callback: send the loan to the
+    /// first pair, swap along every pair, settle with the lender.
+    pub fn steps(&self) -> Vec<ExecutorLeg> {
+        let mut steps = Vec::new();
+        if let Some(first) = self.legs.first() {
+            steps.push(transfer(self.token, first.pool, self.amount));
+        }
+        steps.extend(self.legs.iter().map(PairSwap::leg));
+        steps.push(self.repayment.clone());
+        steps
+    }
+
+    /// Executor call run from the lender's callback, reverting below `min_profit`. A
+    /// tip paid out of WETH profit is unwrapped after the lender is settled, a tip out
+    /// of any other token refused.
+    pub fn executor_call(
+        &self,
+        min_profit: U256,
+        miner_tip: U256,
+    ) -> Result<ExecutorCall, ExecutorCallError> {
+        let repayment = Repayment {
+            pool: self.lender,
+            token: self.token,
+            amount: self.amount + self.fee,
+        };
+        Ok(ExecutorCall::new(self.executor, repayment)
+            .with_legs(self.steps())
+            .with_legs(tip_steps(*mainnet::weth, self.token, miner_tip)?)
+            .with_min_profit(min_profit)
+            .with_miner_tip(miner_tip))
+    }
+
+    /// Loan request to `source`, the plan's lender, running the whole cycle from
+    /// its callback.
+    pub fn to_transaction(
+        &self,
+        source: &dyn FlashSource,
+        min_profit: U256,
+        miner_tip: U256,
+    ) -> Result<TypedTransaction, ExecutorCallError> {
+        let data = self.executor_call(min_profit, miner_tip)?.encode()?;
+        Ok(Eip1559TransactionRequest::new()
+            .to(self.lender)
+            .data(source.loan_calldata(self.token, self.amount, self.executor, data))
+            .into())
+    }
+}
+
+/// How a cycle gets its input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FundingPlan {
+    FlashSwap(FlashSwapPlan),
+    FlashLoan(FlashLoanPlan),
+}
+
+impl FundingPlan {
+    pub fn profit(&self) -> Option<U256> {
+        match self {
+            FundingPlan::FlashSwap(plan) => plan.profit(),
+            FundingPlan::FlashLoan(plan) => plan.profit(),
+        }
+    }
+
+    /// Transaction running the plan, the loan requested from the source of `sources`
+    /// it was planned with.
+    pub fn to_transaction(
+        &self,
+        sources: &[Box<dyn FlashSource>],
+        min_profit: U256,
+        miner_tip: U256,
+    ) -> anyhow::Result<TypedTransaction> {
+        Ok(match self {
+            FundingPlan::FlashSwap(plan) => plan.to_transaction(min_profit, miner_tip)?,
+            FundingPlan::FlashLoan(plan) => {
+                let source = sources
+                    .iter()
+                    .find(|source| source.name() == plan.source)
+                    .ok_or_else(|| anyhow::anyhow!("unknown flash source {}", plan.source))?;
+                plan.to_transaction(source.as_ref(), min_profit, miner_tip)?
+            }
+        })
+    }
+}
+
+/// Most profitable way to fund `amount_in` into `hops`: a flash swap on the first
+/// pair, or a loan from the cheapest source lending the input token. The flash swap
+/// wins ties, it needs no call to a lender. Every hop after the flash swap's borrow
+/// accepts `slippage`.
+pub fn plan_cycle(
+    sources: &[Box<dyn FlashSource>],
+    hops: &[Hop],
+    book: &ReserveBook,
+    amount_in: U256,
+    executor: Address,
+    slippage: &SlippagePolicy,
+) -> Result<FundingPlan, FlashSwapError> {
+    let swap =
+        plan_for_input(hops, book, amount_in, executor, slippage).map(FundingPlan::FlashSwap);
+    let first = hops.first().ok_or(FlashSwapError::TooFewHops)?;
+    let loan = match cheapest_source(sources, first.token_in, amount_in) {
+        Some((source, _)) => {
+            FlashLoanPlan::build(source, hops, book, amount_in, executor, slippage)?
+                .map(FundingPlan::FlashLoan)
+        }
+        None => None,
+    };
+
+    match (swap, loan) {
+        (Ok(swap), Some(loan)) if loan.profit() > swap.profit() => Ok(loan),
+        (Ok(swap), _) => Ok(swap),
+        (Err(_), Some(loan)) => Ok(loan),
+        (Err(e), None) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{cycle_book, eth, hop, token};
+
+    fn liquidity(amount: U256) -> Liquidity {
+        vec![(token(1), amount)].into_iter().collect()
+    }
+
+    #[test]
+    fn test_aave_a_token() {
+        let a_token = Address::repeat_byte(0xac);
+        let words = |index: usize| {
+            let mut data = vec![0u8; 12 * 32];
+            data[index * 32 + 12..(index + 1) * 32].copy_from_slice(a_token.as_bytes());
+            data
+        };
+        assert_eq!(AaveVersion::V2.a_token(&words(7)), Some(a_token));
+        assert_eq!(AaveVersion::V3.a_token(&words(8)), Some(a_token));
+        assert_eq!(AaveVersion::V3.a_token(&words(8)[..8 * 32]), None);
+    }
+
+    #[test]
+    fn test_cheapest_source() {
+        let sources: Vec<Box<dyn FlashSource>> = vec![
+            Box::new(AaveSource::mainnet_v2(liquidity(eth(1000)))),
+            Box::new(AaveSource::mainnet_v3(liquidity(eth(1000)))),
+            Box::new(BalancerVaultSource::mainnet(liquidity(eth(5)))),
+        ];
+
+        let (source, fee) = cheapest_source(&sources, token(1), eth(1)).unwrap();
+        assert_eq!((source.name(), fee), ("balancer", U256::zero()));
+
+        // More than the Vault holds
+        let (source, fee) = cheapest_source(&sources, token(1), eth(10)).unwrap();
+        assert_eq!(source.name(), "aave-v3");
+        assert_eq!(fee, eth(10) * 5 / 10_000);
+
+        assert!(cheapest_source(&sources, token(2), eth(1)).is_none());
+    }
+
+    #[test]
+    fn test_flash_loan_plan() {
+        let hops = vec![hop(1, 1, 2), hop(2, 2, 3), hop(3, 3, 1)];
+        let executor = Address::repeat_byte(0xee);
+        let source = AaveSource::mainnet_v3(liquidity(eth(100)));
+
+        let no_slippage = SlippagePolicy::new(0);
+        let loan = FlashLoanPlan::build(
+            &source,
+            &hops,
+            &cycle_book(),
+            eth(5),
+            executor,
+            &no_slippage,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(loan.lender, *AAVE_V3_POOL);
+        assert_eq!(loan.fee, eth(5) * 5 / 10_000);
+        assert_eq!(loan.legs.len(), 3);
+        assert_eq!(loan.legs[2].to, executor);
+        assert_eq!(loan.steps().len(), 5);
+        assert_eq!(loan.profit(), loan.output().checked_sub(eth(5) + loan.fee));
+        let tx = loan.to_transaction(&source, U256::zero(), U256::zero()).unwrap();
+        assert_eq!(tx.data().unwrap()[..4], AaveFlashLoanSimpleCall::selector());
+
+        assert!(FlashLoanPlan::build(
+            &source,
+            &hops,
+            &cycle_book(),
+            eth(500),
+            executor,
+            &no_slippage
+        )
+        .unwrap()
+        .is_none());
+    }
+
+    #[test]
+    fn test_plan_cycle_picks_most_profitable() {
+        let hops = vec![hop(1, 1, 2), hop(2, 2, 3), hop(3, 3, 1)];
+        let executor = Address::repeat_byte(0xee);
+
+        // Repaying the first pair costs no more than the trade itself, so only a free
+        // loan could match the flash swap and the flash swap wins the tie
+        let sources: Vec<Box<dyn FlashSource>> = vec![
+            Box::new(AaveSource::mainnet_v2(liquidity(eth(100)))),
+            Box::new(BalancerVaultSource::mainnet(liquidity(eth(100)))),
+        ];
+        let plan = |sources: &[Box<dyn FlashSource>], hops: &[Hop]| {
+            let no_slippage = SlippagePolicy::new(0);
+            plan_cycle(sources, hops, &cycle_book(), eth(5), executor, &no_slippage)
+        };
+        assert!(matches!(
+            plan(&sources, &hops),
+            Ok(FundingPlan::FlashSwap(_))
+        ));
+
+        // Without lenders the flash swap is the only option, and it needs a cycle
+        assert!(plan(&[], &hops[..1]).is_err());
+    }
+}
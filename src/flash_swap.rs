@@ -0,0 +1,405 @@
+//! Uniswap V2 flash swaps repaid from the pair callback.
+//!
+//! The first pair of a cycle lends its output before being paid: `pair.swap` sends it
+//! to the executor and calls `uniswapV2Call` on it with our data. From the callback
+//! the executor forwards the loan to the second pair, every pair's `swap` sends its
+//! output straight to the next pair and the last one back to the executor, which
+//! then repays the first pair, 0.3% fee included. The whole cycle is one transaction.
+use std::fmt;
+
+use ethers::abi::AbiEncode;
+use ethers::core::types::transaction::eip2718::TypedTransaction;
+use ethers::prelude::*;
+
+use crate::contracts::bindings::ierc20::TransferCall;
+use crate::contracts::bindings::uniswap_v2_pair::SwapCall;
+use crate::crypto_pair::CryptoPair;
+use crate::executor_call::{ExecutorCall, ExecutorCallError, ExecutorLeg, Repayment};
+use crate::opportunity_aggregator::{Hop, ReserveBook};
+use crate::slippage::SlippagePolicy;
+use crate::uniswap_providers::mainnet;
+use crate::weth::tip_steps;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlashSwapError {
+    TooFewHops,
+    /// Consecutive hops don't share a token, or the last doesn't return the first's
+    BrokenCycle,
+    UnknownPool(Address),
+    /// A pool can't return the amount asked from it
+    InsufficientLiquidity(Address),
+}
+
+impl fmt::Display for FlashSwapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlashSwapError::TooFewHops => write!(f, "a flash swap cycle needs at least 2 hops"),
+            FlashSwapError::BrokenCycle => write!(f, "hops don't form a cycle"),
+            FlashSwapError::UnknownPool(pool) => write!(f, "no reserves for pool {:?}", pool),
+            FlashSwapError::InsufficientLiquidity(pool) => {
+                write!(f, "insufficient liquidity in pool {:?}", pool)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FlashSwapError {}
+
+/// Input a V2 pair needs to send `amount_out`, its 0.3% fee included. Rounds up like
+/// `UniswapV2Library.getAmountIn`, so the pair's invariant check passes.
+pub fn amount_in_for(amount_out: U256, reserve_in: U256, reserve_out: U256) -> Option<U256> {
+    if amount_out >= reserve_out {
+        return None;
+    }
+    let numerator = reserve_in
+        .checked_mul(amount_out)?
+        .checked_mul(U256::from(1000))?;
+    let denominator = (reserve_out - amount_out).checked_mul(U256::from(997))?;
+    numerator.checked_div(denominator)?.checked_add(U256::one())
+}
+
+/// `(amount0Out, amount1Out)` for receiving `amount` of `token_out` from a pair
+/// trading it against `token_in`. Pairs sort their tokens by address.
+pub fn amounts_out(token_in: Address, token_out: Address, amount: U256) -> (U256, U256) {
+    if token_out < token_in {
+        (amount, U256::zero())
+    } else {
+        (U256::zero(), amount)
+    }
+}
+
+/// One `pair.swap` of the cycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PairSwap {
+    pub pool: Address,
+    pub token_in: Address,
+    pub token_out: Address,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub amount0_out: U256,
+    pub amount1_out: U256,
+    /// Receiver of the output: the next pair, or the executor
+    pub to: Address,
+    /// Least output the executor accepts, `amount_out` unless set by `chain_legs`
+    pub amount_out_min: U256,
+}
+
+impl PairSwap {
+    fn new(hop: &Hop, amount_in: U256, amount_out: U256, to: Address) -> Self {
+        let (amount0_out, amount1_out) = amounts_out(hop.token_in, hop.token_out, amount_out);
+        Self {
+            pool: hop.pool,
+            token_in: hop.token_in,
+            token_out: hop.token_out,
+            amount_in,
+            amount_out,
+            amount0_out,
+            amount1_out,
+            to,
+            amount_out_min: amount_out,
+        }
+    }
+
+    /// Calldata of `pair.swap`. A non-empty `data` makes it a flash swap.
+    pub fn calldata(&self, data: Bytes) -> Bytes {
+        Bytes::from(
+            SwapCall {
+                amount_0_out: self.amount0_out,
+                amount_1_out: self.amount1_out,
+                to: self.to,
+                data,
+            }
+            .encode(),
+        )
+    }
+
+    /// The swap as an executor leg, the input already in the pair, checked for
+    /// `amount_out_min` at the receiver.
+    pub fn leg(&self) -> ExecutorLeg {
+        ExecutorLeg::swap(
+            self.pool,
+            self.calldata(Bytes::default()),
+            self.token_out,
+            self.amount_out,
+        )
+        .with_min_output(self.to, self.amount_out_min)
+    }
+}
+
+/// Direct `pair.swap` calls trading `amount_in` along `hops`, each sending its output
+/// to the next pair and the last one to `executor`. Every pair is asked for its quoted
+/// output: it pays exactly what it is asked or reverts on its invariant, so asking for
+/// less would only leave the difference to its LPs. The least output `slippage`
+/// accepts goes to the executor's check of each step instead. Updates `book` as it
+/// goes; the first pair must already hold `amount_in` when the chain starts.
+pub fn chain_legs(
+    hops: &[Hop],
+    book: &mut ReserveBook,
+    amount_in: U256,
+    executor: Address,
+    slippage: &SlippagePolicy,
+) -> Result<Vec<PairSwap>, FlashSwapError> {
+    let mut legs = Vec::new();
+    let mut amount = amount_in;
+    for (i, hop) in hops.iter().enumerate() {
+        let (reserve_in, reserve_out) = book
+            .reserves(hop)
+            .ok_or(FlashSwapError::UnknownPool(hop.pool))?;
+        let amount_out =
+            CryptoPair::get_amount_out(amount, reserve_in, reserve_out).unwrap_or_default();
+        if amount_out.is_zero() {
+            return Err(FlashSwapError::InsufficientLiquidity(hop.pool));
+        }
+        book.apply(hop, amount, amount_out)
+            .ok_or(FlashSwapError::InsufficientLiquidity(hop.pool))?;
+        let to = hops.get(i + 1).map(|next| next.pool).unwrap_or(executor);
+        let mut leg = PairSwap::new(hop, amount, amount_out, to);
+        leg.amount_out_min = slippage.min_out(amount_out);
+        legs.push(leg);
+        amount = amount_out;
+    }
+    Ok(legs)
+}
+
+/// `token.transfer(recipient, amount)` as an executor step.
+pub fn transfer(token: Address, recipient: Address, amount: U256) -> ExecutorLeg {
+    ExecutorLeg::call(
+        token,
+        Bytes::from(TransferCall { recipient, amount }.encode()),
+    )
+}
+
+/// A cycle opened by a flash swap and closed by repaying it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashSwapPlan {
+    pub executor: Address,
+    /// The flash swap, lending the first pair's output to the executor
+    pub borrow: PairSwap,
+    /// Remaining hops, run from the callback
+    pub legs: Vec<PairSwap>,
+    pub repayment: Repayment,
+}
+
+impl FlashSwapPlan {
+    /// Plans borrowing `borrow` of the first hop's output and trading it along the
+    /// other hops, with reserves from `book`. Every amount is exact, the executor
+    /// checks the other hops' outputs against `slippage`.
+    pub fn build(
+        hops: &[Hop],
+        book: &ReserveBook,
+        borrow: U256,
+        executor: Address,
+        slippage: &SlippagePolicy,
+    ) -> Result<Self, FlashSwapError> {
+        let (first, rest) = match hops {
+            [first, rest @ ..] if !rest.is_empty() => (first, rest),
+            _ => return Err(FlashSwapError::TooFewHops),
+        };
+        let linked = hops.windows(2).all(|w| w[0].token_out == w[1].token_in);
+        if !linked || hops[hops.len() - 1].token_out != first.token_in {
+            return Err(FlashSwapError::BrokenCycle);
+        }
+
+        let mut book = book.clone();
+        let (reserve_in, reserve_out) = book
+            .reserves(first)
+            .ok_or(FlashSwapError::UnknownPool(first.pool))?;
+        let owed = amount_in_for(borrow, reserve_in, reserve_out)
+            .ok_or(FlashSwapError::InsufficientLiquidity(first.pool))?;
+        book.execute(std::slice::from_ref(first), owed)
+            .ok_or(FlashSwapError::InsufficientLiquidity(first.pool))?;
+
+        Ok(Self {
+            executor,
+            borrow: PairSwap::new(first, owed, borrow, executor),
+            legs: chain_legs(rest, &mut book, borrow, executor, slippage)?,
+            repayment: Repayment {
+                pool: first.pool,
+                token: first.token_in,
+                amount: owed,
+            },
+        })
+    }
+
+    /// Amount of the repayment token the last hop returns.
+    pub fn output(&self) -> U256 {
+        self.legs
+            .last()
+            .map(|leg| leg.amount_out)
+            .unwrap_or_default()
+    }
+
+    pub fn profit(&self) -> Option<U256> {
+        self.output().checked_sub(self.repayment.amount)
+    }
+
+    /// Calls the executor makes from the callback, in order: forward the loan,
+    /// swap along the remaining pairs, repay the first pair.
+    pub fn steps(&self) -> Vec<ExecutorLeg> {
+        let mut steps = Vec::new();
+        if let Some(second) = self.legs.first() {
+            steps.push(transfer(self.borrow.token_out, second.pool, self.borrow.amount_out));
+        }
+        steps.extend(self.legs.iter().map(PairSwap::leg));
+        steps.push(transfer(
+            self.repayment.token,
+            self.repayment.pool,
+            self.repayment.amount,
+        ));
+        steps
+    }
+
+    /// Executor call run from the callback, reverting below `min_profit`. `miner_tip`
+    /// is transferred to `block.coinbase` last, unwrapped from the profit when the
+    /// cycle is repaid in WETH, refused otherwise.
+    pub fn executor_call(
+        &self,
+        min_profit: U256,
+        miner_tip: U256,
+    ) -> Result<ExecutorCall, ExecutorCallError> {
+        Ok(ExecutorCall::new(self.executor, self.repayment.clone())
+            .with_legs(self.steps())
+            .with_legs(tip_steps(*mainnet::weth, self.repayment.token, miner_tip)?)
+            .with_min_profit(min_profit)
+            .with_miner_tip(miner_tip))
+    }
+
+    /// Data passed to `uniswapV2Call`, see `ExecutorCall::encode`.
+    pub fn callback_data(
+        &self,
+        min_profit: U256,
+        miner_tip: U256,
+    ) -> Result<Bytes, ExecutorCallError> {
+        self.executor_call(min_profit, miner_tip)?.encode()
+    }
+
+    /// The flash swap on the first pair, running the whole cycle.
+    pub fn to_transaction(
+        &self,
+        min_profit: U256,
+        miner_tip: U256,
+    ) -> Result<TypedTransaction, ExecutorCallError> {
+        Ok(Eip1559TransactionRequest::new()
+            .to(self.borrow.pool)
+            .data(self.borrow.calldata(self.callback_data(min_profit, miner_tip)?))
+            .into())
+    }
+}
+
+/// Same as `FlashSwapPlan::build`, borrowing what the first hop returns for `amount_in`.
+pub fn plan_for_input(
+    hops: &[Hop],
+    book: &ReserveBook,
+    amount_in: U256,
+    executor: Address,
+    slippage: &SlippagePolicy,
+) -> Result<FlashSwapPlan, FlashSwapError> {
+    let first = hops.first().ok_or(FlashSwapError::TooFewHops)?;
+    let (reserve_in, reserve_out) = book
+        .reserves(first)
+        .ok_or(FlashSwapError::UnknownPool(first.pool))?;
+    let borrow = CryptoPair::get_amount_out(amount_in, reserve_in, reserve_out)
+        .ok_or(FlashSwapError::InsufficientLiquidity(first.pool))?;
+    FlashSwapPlan::build(hops, book, borrow, executor, slippage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{cycle_book, eth, hop};
+    use ethers::abi::AbiDecode;
+
+    #[test]
+    fn test_exact_repayment() {
+        let (reserve_in, reserve_out) = (eth(1000), eth(2000));
+        let owed = amount_in_for(eth(10), reserve_in, reserve_out).unwrap();
+        assert!(CryptoPair::get_amount_out(owed, reserve_in, reserve_out).unwrap() >= eth(10));
+        assert!(CryptoPair::get_amount_out(owed - 1, reserve_in, reserve_out).unwrap() < eth(10));
+        assert!(amount_in_for(reserve_out, reserve_in, reserve_out).is_none());
+    }
+
+    #[test]
+    fn test_plan_chains_pairs() {
+        let hops = vec![hop(1, 1, 2), hop(2, 2, 3), hop(3, 3, 1)];
+        let executor = Address::repeat_byte(0xee);
+        let plan = FlashSwapPlan::build(
+            &hops,
+            &cycle_book(),
+            eth(10),
+            executor,
+            &SlippagePolicy::new(0),
+        )
+        .unwrap();
+
+        // Token 2 sorts after token 1, so it is token1 of the first pair
+        assert_eq!(plan.borrow.amount0_out, U256::zero());
+        assert_eq!(plan.borrow.amount1_out, eth(10));
+        assert_eq!(plan.borrow.to, executor);
+        assert_eq!(plan.legs[0].to, Address::repeat_byte(0xa3));
+        assert_eq!(plan.legs[1].to, executor);
+        // Token 1 sorts before token 3 on the last pair
+        assert_eq!(plan.legs[1].amount0_out, plan.output());
+        assert_eq!(plan.repayment.token, Address::repeat_byte(1));
+        assert!(plan.profit().unwrap() > U256::zero());
+
+        let steps = plan.steps();
+        assert_eq!(steps.len(), 4);
+        assert_eq!(steps[0].target, Address::repeat_byte(2));
+        assert_eq!(steps[3].target, Address::repeat_byte(1));
+
+        let tx = plan.to_transaction(plan.profit().unwrap(), U256::zero()).unwrap();
+        assert_eq!(tx.to(), Some(&NameOrAddress::Address(Address::repeat_byte(0xa1))));
+        let data = SwapCall::decode(tx.data().unwrap()).unwrap().data;
+        let call = ExecutorCall::decode(&data).unwrap();
+        assert_eq!(call.steps.len(), 4);
+        assert_eq!(call.profit_token, Address::repeat_byte(1));
+        assert_eq!(call.min_profit, plan.profit().unwrap());
+    }
+
+    #[test]
+    fn test_legs_ask_for_quoted_output() {
+        let hops = vec![hop(1, 1, 2), hop(2, 2, 3), hop(3, 3, 1)];
+        let executor = Address::repeat_byte(0xee);
+        let plan = |bps| {
+            FlashSwapPlan::build(&hops, &cycle_book(), eth(10), executor, &SlippagePolicy::new(bps))
+                .unwrap()
+        };
+        let (exact, tolerant) = (plan(0), plan(100));
+
+        // The tolerance gives nothing away to the pairs, it only loosens the checks
+        assert_eq!(tolerant.borrow, exact.borrow);
+        assert_eq!(tolerant.repayment, exact.repayment);
+        assert_eq!(tolerant.output(), exact.output());
+        assert_eq!(tolerant.legs[0].amount_out, exact.legs[0].amount_out);
+        assert_eq!(tolerant.legs[0].amount_out_min, exact.legs[0].amount_out * 99 / 100);
+
+        let step = &tolerant.steps()[1];
+        assert_eq!(step.recipient, tolerant.legs[1].pool);
+        assert_eq!(step.min_output, tolerant.legs[0].amount_out_min);
+    }
+
+    #[test]
+    fn test_rejects_broken_cycle() {
+        let no_slippage = SlippagePolicy::new(0);
+        assert_eq!(
+            FlashSwapPlan::build(
+                &[hop(1, 1, 2)],
+                &cycle_book(),
+                eth(1),
+                Address::zero(),
+                &no_slippage
+            ),
+            Err(FlashSwapError::TooFewHops)
+        );
+        assert_eq!(
+            FlashSwapPlan::build(
+                &[hop(1, 1, 2), hop(2, 2, 3)],
+                &cycle_book(),
+                eth(1),
+                Address::zero(),
+                &no_slippage
+            ),
+            Err(FlashSwapError::BrokenCycle)
+        );
+    }
+}
@@ -1,4 +1,14 @@
 use super::uniswap_providers::*;
+use crate::backrun::{self, Victim};
+use crate::bid_policy::{Bid, BidPolicy};
+use crate::bundle_simulation::{BundleDecision, BundleSimulation, SimulationGate};
+use crate::bundle_submitter::Inclusion;
+use crate::conversion;
+use crate::fee_predictor::FeeEstimate;
+use crate::gas_model::{self, GasModel, GasSource};
+use crate::nonce_manager::NonceReservation;
+use crate::path_sequence::PathOutcome;
+use crate::private_tx::SubmissionMode;
 use anyhow;
 use anyhow::Result;
 use ethers::core::k256::ecdsa::SigningKey;
@@ -16,19 +26,24 @@ use std::str::FromStr;
 use url::Url;
 pub mod utils {
     use super::*;
-    pub static timestamp_seed: u128 = 30000_u128;
+    /// Seconds a swap stays valid
+    pub static timestamp_seed: u64 = 30;
 
     pub fn valid_timestamp() -> U256 {
         let start = SystemTime::now();
         let since_epoch = start.duration_since(UNIX_EPOCH).unwrap();
-        let time_millis = since_epoch.as_millis().checked_add(timestamp_seed).unwrap();
-        U256::from(time_millis)
+        let time_secs = since_epoch.as_secs().checked_add(timestamp_seed).unwrap();
+        U256::from(time_secs)
     }
 
     /// Return a new flashbots bundle request for this block
     pub async fn new_bundle_request() -> Result<BundleRequest> {
+        for_next_block(BundleRequest::new()).await
+    }
+
+    /// Targets `bundle` at the next block, simulated on top of the current one
+    pub async fn for_next_block(mut bundle: BundleRequest) -> Result<BundleRequest> {
         let block = mainnet::flashbots_client.get_block_number().await?;
-        let mut bundle = BundleRequest::new();
         bundle = bundle.set_simulation_block(block);
         bundle = bundle.set_block(block + 1);
         let now = SystemTime::now();
@@ -36,71 +51,330 @@ pub mod utils {
         Ok(bundle)
     }
 
-    pub async fn send_flashswap_bundle(txs: Vec<TypedTransaction>) -> Result<H256, anyhow::Error> {
+    /// Signs `txs` and places them right after the victims touching `pools`. Returns
+    /// the bundle for the next block and the victims in bundle order.
+    async fn sign_bundle(
+        victims: &[Victim],
+        pools: &[Address],
+        txs: &[TypedTransaction],
+        base_fee: U256,
+    ) -> Result<(BundleRequest, Vec<Victim>)> {
+        let mut ours = Vec::new();
+        for tx in txs {
+            let signature = mainnet::flashbots_client
+                .signer()
+                .sign_transaction(tx)
+                .await?;
+            ours.push(tx.rlp_signed(&signature));
+        }
+        let (bundle, ordered) = backrun::compose(victims, pools, ours, base_fee);
+        Ok((for_next_block(bundle).await?, ordered))
+    }
 
-        let nonce = mainnet::flashbots_client
-            .get_transaction_count(
-                mainnet::wallet.address(),
-                Some(BlockId::from(BlockNumber::Latest)),
-            )
-            .await?;
+    /// Prices our transactions with `policy`, simulates them behind the victims they
+    /// backrun and sends the bundle if it passes `gate`.
+    ///
+    /// Our transactions are simulated once with the policy's fallback gas to measure
+    /// their gas, then re-priced from the measured gas and the next block's base fee
+    /// and simulated again. Any `miner_tip` in their calldata is expected to add up to
+    /// `outcome.coinbase_tip`, the rest of the policy's tip on `outcome.profit` is paid
+    /// as priority fee. Victims may revert without sinking the bundle, the profit
+    /// check catches a backrun left unprofitable by it.
+    ///
+    /// A single transaction of ours backrunning nothing is sent with
+    /// `eth_sendPrivateTransaction` instead of as a bundle, see `SubmissionMode::choose`.
+    ///
+    /// Nonces come from `mainnet::nonce_manager`, reserved for every block the
+    /// submission targets. A submission still in flight with the same nonces is
+    /// replaced: its bundle stops being sent and its private transaction is cancelled,
+    /// or never sent if it was replaced first.
+    pub async fn send_flashswap_bundle(
+        victims: &[Victim],
+        mut txs: Vec<TypedTransaction>,
+        outcome: &PathOutcome,
+        policy: &BidPolicy,
+        gate: &SimulationGate,
+    ) -> Result<BundleDecision, anyhow::Error> {
+        let victims: Vec<Victim> = victims
+            .iter()
+            .filter(|victim| victim.touches(&outcome.pools))
+            .cloned()
+            .collect();
+        let mode = SubmissionMode::choose(&victims, &txs);
+
+        let latest = mainnet::flashbots_client
+            .get_block(BlockNumber::Latest)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Latest block not found"))?;
+        let block = latest
+            .number
+            .ok_or_else(|| anyhow::anyhow!("Latest block has no number"))?;
+        let fees = mainnet::fee_predictor.read().unwrap().estimate_after(&latest);
+        println!(
+            "Block Number: {:?}, next base fee: {}, priority fee: {}",
+            block, fees.next_base_fee, fees.priority_fee
+        );
 
-        let block_number = mainnet::flashbots_client
-            .inner()
-            .inner()
-            .get_block_number()
-            .await
-            .unwrap();
-        println!("Block Number: {}", block_number);
+        // Reserve nonces for every block the submission may land in
+        let nonces = &mainnet::nonce_manager;
+        nonces.sync(&**mainnet::flashbots_client).await?;
+        nonces.expire(block + 1);
+        let until_block = match mode {
+            SubmissionMode::Bundle => block + mainnet::bundle_submitter.window(),
+            SubmissionMode::PrivateTransaction => block + mainnet::private_tx_client.max_blocks,
+        };
+        let missing = txs.iter().filter(|tx| tx.nonce().is_none()).count();
+        let submission = mainnet::bundle_submitter.open(outcome.pools.clone());
+        let reservation = match nonces.reserve(missing, until_block, submission) {
+            Ok(reservation) => reservation,
+            Err(e) => {
+                mainnet::bundle_submitter.close(submission);
+                return Err(e.into());
+            }
+        };
+        for id in &reservation.aborts {
+            mainnet::bundle_submitter.abort(*id);
+        }
+        for tx_hash in &reservation.replaces {
+            if let Err(e) = mainnet::private_tx_client
+                .cancel_private_transaction(*tx_hash)
+                .await
+            {
+                println!("Failed to cancel private transaction {:?}: {}", tx_hash, e);
+            }
+        }
+        let chain_id = mainnet::flashbots_client.signer().chain_id();
+        let mut reserved = reservation.nonces.iter();
+        for tx in txs.iter_mut() {
+            if tx.nonce().is_none() {
+                tx.set_nonce(*reserved.next().unwrap());
+            }
+            tx.set_chain_id(chain_id);
+        }
 
-        let mut bundle = new_bundle_request().await?;
+        let decision = price_and_send(
+            &victims,
+            mode,
+            submission,
+            &reservation,
+            txs,
+            outcome,
+            fees,
+            policy,
+            gate,
+        )
+        .await;
+        mainnet::bundle_submitter.close(submission);
+        match &decision {
+            Ok(BundleDecision::Included { .. }) => nonces.commit(&reservation),
+            // Held until it lands, which the next sync notices, or its last block passes
+            Ok(BundleDecision::SentPrivately { .. }) => {}
+            _ => nonces.release(&reservation),
+        }
+        decision
+    }
 
-        for mut tx in txs {
-            if tx.nonce().is_none() {
-                tx.set_nonce(nonce);
-                tx.set_gas_price(U256::from(300000000000u64));
-                tx.set_gas(U256::from(400000));
+    /// Feeds the gas `tx` used running `outcome` to `mainnet::gas_model`, saving the
+    /// coefficients after every receipt. Only plain flash swaps are fed, see
+    /// `gas_model::calibrates_from`.
+    fn calibrate_gas(
+        tx: &TypedTransaction,
+        outcome: &PathOutcome,
+        gas_used: U256,
+        source: GasSource,
+    ) {
+        if !gas_model::calibrates_from(tx, &outcome.pools, outcome.coinbase_tip) {
+            return;
+        }
+        let pairs = conversion::CONVERSION_PAIRS.read().unwrap().clone();
+        let legs = match gas_model::legs_of(&pairs, &outcome.pools) {
+            Some(legs) => legs,
+            None => return,
+        };
+        let mut model = mainnet::gas_model.write().unwrap();
+        let calldata = tx.data().map(|data| data.as_ref()).unwrap_or_default();
+        model.observe(&legs, calldata, gas_used, source);
+        if source == GasSource::Receipt {
+            if let Err(e) = model.save(&GasModel::path()) {
+                println!("Failed to save the gas model: {}", e);
             }
+        }
+    }
 
-            let signature = mainnet::flashbots_client
-                .signer()
-                .sign_transaction(&tx)
-                .await?;
-                
-            tx.set_chain_id(mainnet::flashbots_client.signer().chain_id());
-            bundle = bundle.push_transaction(
-                tx.rlp_signed(&signature),
-            );
-             
+    #[allow(clippy::too_many_arguments)]
+    async fn price_and_send(
+        victims: &[Victim],
+        mode: SubmissionMode,
+        submission: u64,
+        reservation: &NonceReservation,
+        mut txs: Vec<TypedTransaction>,
+        outcome: &PathOutcome,
+        fees: FeeEstimate,
+        policy: &BidPolicy,
+        gate: &SimulationGate,
+    ) -> Result<BundleDecision, anyhow::Error> {
+        let profit = outcome.profit;
+        let base_fee = fees.next_base_fee;
+        let initial_bid = policy.initial_bid(profit, base_fee);
+        for tx in txs.iter_mut() {
+            *tx = policy.apply(tx, &initial_bid);
         }
 
-        // Simulate it
+        // Simulate it to measure gas
+        let (bundle, ordered) = sign_bundle(victims, &outcome.pools, &txs, base_fee).await?;
+        let allowed_reverts: Vec<H256> = ordered.iter().map(|victim| victim.hash).collect();
+        let own: Vec<usize> = (ordered.len()..ordered.len() + txs.len()).collect();
         let simulated_bundle = mainnet::flashbots_client
             .inner()
             .simulate_bundle(&bundle)
             .await?;
-        println!("Simulated bundle: {:?}", simulated_bundle);
+        let measured = BundleSimulation::from(&simulated_bundle);
+        if let Some((index, tx)) = measured.first_revert(&allowed_reverts) {
+            return Ok(BundleDecision::Reverted {
+                index,
+                hash: tx.hash,
+                reason: tx.revert.clone().unwrap_or_default(),
+            });
+        }
 
-        // Send it
-        let pending_bundle = mainnet::flashbots_client
+        if let [i] = own.as_slice() {
+            if let Some(tx) = measured.transactions.get(*i) {
+                calibrate_gas(&txs[0], outcome, tx.gas_used, GasSource::Simulation);
+            }
+        }
+
+        let own_gas = |i: usize| {
+            measured
+                .transactions
+                .get(i)
+                .map(|tx| tx.gas_used)
+                .unwrap_or_default()
+        };
+        let total_gas = own
+            .iter()
+            .fold(U256::zero(), |total, i| total + own_gas(*i));
+        let bid = match policy.bid_with(profit, outcome.coinbase_tip, total_gas, &fees) {
+            Ok(bid) => bid,
+            Err(rejection) => return Ok(rejection.into()),
+        };
+
+        for (tx, i) in txs.iter_mut().zip(own.iter().copied()) {
+            let tx_bid = Bid {
+                gas_limit: policy.gas_limit(own_gas(i)),
+                ..bid.clone()
+            };
+            *tx = policy.apply(tx, &tx_bid);
+        }
+        let (bundle, _) = sign_bundle(&ordered, &outcome.pools, &txs, base_fee).await?;
+
+        // Simulate the priced bundle and decide whether to send it
+        let simulated_bundle = mainnet::flashbots_client
             .inner()
-            .send_bundle(&bundle)
+            .simulate_bundle(&bundle)
             .await?;
+        let simulation = match gate.decide(
+            BundleSimulation::from(&simulated_bundle),
+            &own,
+            &allowed_reverts,
+            outcome,
+            policy,
+        ) {
+            BundleDecision::Accept(simulation) => simulation,
+            rejected => {
+                println!("Bundle rejected: {:?}", rejected);
+                return Ok(rejected);
+            }
+        };
 
-        match pending_bundle.await {
-            Ok(bundle_hash) => {
+        if mode == SubmissionMode::PrivateTransaction {
+            // Replaced while simulating, by a submission with no transaction to cancel
+            if !mainnet::nonce_manager.is_live(reservation) {
+                return Ok(BundleDecision::Cancelled(simulation));
+            }
+            let tx = &txs[0];
+            let signature = mainnet::flashbots_client
+                .signer()
+                .sign_transaction(tx)
+                .await?;
+            let max_block_number =
+                simulation.simulation_block + mainnet::private_tx_client.max_blocks;
+            let tx_hash = mainnet::private_tx_client
+                .send_private_transaction(tx.rlp_signed(&signature), max_block_number)
+                .await?;
+            println!(
+                "Private transaction {:?} sent until block {}",
+                tx_hash, max_block_number
+            );
+            if !mainnet::nonce_manager.sent_privately(reservation, tx_hash) {
+                println!("Private transaction {:?} was replaced, cancelling it", tx_hash);
+                if let Err(e) = mainnet::private_tx_client
+                    .cancel_private_transaction(tx_hash)
+                    .await
+                {
+                    println!("Failed to cancel private transaction {:?}: {}", tx_hash, e);
+                }
+                return Ok(BundleDecision::Cancelled(simulation));
+            }
+            return Ok(BundleDecision::SentPrivately {
+                tx_hash,
+                max_block_number,
+                simulation,
+            });
+        }
+
+        // Send it to every relay, superseding older bundles on the same pools
+        let decision = match mainnet::bundle_submitter
+            .replace(submission, bundle)
+            .await?
+        {
+            Inclusion::Included {
+                bundle_hash,
+                block,
+                builder,
+                relays,
+                landed_through,
+            } => {
                 println!(
-                    "Bundle with hash {:?} was included in target block",
-                    bundle_hash
+                    "Bundle with hash {:?} was included in block {} by {:?} through {:?} (sent to {:?})",
+                    bundle_hash, block, builder, landed_through, relays
                 );
-                Ok(bundle_hash)
+                if let [i] = own.as_slice() {
+                    let hash = simulation.transactions[*i].hash;
+                    match mainnet::flashbots_client.get_transaction_receipt(hash).await {
+                        Ok(Some(TransactionReceipt {
+                            gas_used: Some(gas_used),
+                            ..
+                        })) => calibrate_gas(&txs[0], outcome, gas_used, GasSource::Receipt),
+                        _ => println!("No receipt for {:?} to calibrate gas with", hash),
+                    }
+                }
+                BundleDecision::Included {
+                    bundle_hash,
+                    block,
+                    simulation,
+                }
             }
-            Err(PendingBundleError::BundleNotIncluded) => {
-                println!("Bundle was not included in target block.");
-                Err(anyhow::anyhow!("Bundle not included"))
+            Inclusion::NotIncluded => {
+                println!("Bundle was not included in the target blocks.");
+                BundleDecision::NotIncluded(simulation)
             }
-            Err(e) => Err(anyhow::anyhow!("PendingBundleError occured: {:#}", e)),
+            Inclusion::Cancelled => BundleDecision::Cancelled(simulation),
+        };
+
+        match mainnet::bundle_stats
+            .record(
+                mainnet::flashbots_client.inner(),
+                outcome,
+                &decision,
+                mainnet::bundle_submitter.window(),
+            )
+            .await
+        {
+            Ok(Some(record)) => println!("Bundle stats: {:?}", record.explanation),
+            Ok(None) => {}
+            Err(e) => println!("Bundle stats could not be recorded.  Reason: {:#}", e),
         }
+        Ok(decision)
     }
 }
 
@@ -119,13 +393,9 @@ pub mod utils {
         let provider =
             Provider::<Http>::try_from("https://goerli.infura.io/v3/0ab0b9c9d5bf44818399aea45b5ade51")?;
 
-        let private_key = "7005b56052be4776bffe00ff781879c65aa87ac3d5f8945c0452f27e11fa9236";
-        let bundle_signer = private_key.parse::<LocalWallet>()?;
-        let wallet = private_key.parse::<LocalWallet>()?;
-
-        // Set chainId for goerli testnet
-        let wallet = wallet.with_chain_id(1u64);
-        let bundle_signer = bundle_signer.with_chain_id(1u64);
+        // Keys from the environment, see `signers`
+        let bundle_signer = SearcherSigner::from_env(FLASHBOTS_ENV, Some(SignerConfig::Random), 1)?;
+        let wallet = SearcherSigner::from_env(WALLET_ENV, None, 1)?;
 
         let client = SignerMiddleware::new(
             FlashbotsMiddleware::new(
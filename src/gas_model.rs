@@ -0,0 +1,347 @@
+//! Gas cost of our cycles, calibrated per DEX.
+//!
+//! A cycle costs the transaction's fixed overhead, its calldata and one swap per leg.
+//! Calldata is priced by the protocol, per zero and non-zero byte. What a swap
+//! costs depends on the DEX, and on whether the pool and tokens it touches are
+//! already warm in the transaction (EIP-2929): the first access to an account or
+//! storage slot costs more than later ones. Each DEX keeps a warm swap cost, fitted
+//! from the gas our single-transaction bundles used in simulation and on chain; the
+//! cold surcharges are fixed by the protocol. Only plain flash swaps are fitted from,
+//! see `calibrates_from`. Coefficients are saved as JSON and reloaded on start.
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::sync::Arc;
+
+use ethers::core::types::transaction::eip2718::TypedTransaction;
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto_pair::CryptoPair;
+use crate::net_profit::{EXECUTOR_OVERHEAD_GAS, TX_BASE_GAS};
+
+/// Env var overriding where the coefficients are persisted.
+pub const GAS_MODEL_ENV: &str = "BACKRUNNER_GAS_MODEL";
+pub const DEFAULT_GAS_MODEL_PATH: &str = "gas_model.json";
+
+/// Calldata gas per zero byte
+pub const ZERO_BYTE_GAS: u64 = 4;
+/// Calldata gas per non-zero byte (EIP-2028)
+pub const NON_ZERO_BYTE_GAS: u64 = 16;
+/// EIP-2929 first access to an account
+pub const COLD_ACCOUNT_ACCESS: u64 = 2_600;
+/// EIP-2929 first read of a storage slot
+pub const COLD_SLOAD: u64 = 2_100;
+/// Cold slots a pool swap reads: reserves or price state, and the lock
+const POOL_COLD_SLOTS: u64 = 2;
+/// Cold slots a token transfer reads: both balances
+const TOKEN_COLD_SLOTS: u64 = 2;
+
+/// Weight of a new observation against the fitted cost
+const SIMULATION_WEIGHT: f64 = 0.1;
+/// Receipts are what was actually paid, and trusted more than simulations
+const RECEIPT_WEIGHT: f64 = 0.25;
+
+/// Where an observation comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GasSource {
+    Simulation,
+    Receipt,
+}
+
+impl GasSource {
+    fn weight(&self) -> f64 {
+        match self {
+            GasSource::Simulation => SIMULATION_WEIGHT,
+            GasSource::Receipt => RECEIPT_WEIGHT,
+        }
+    }
+}
+
+/// One swap of a cycle, as the gas model sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GasLeg {
+    /// DEX label of the pool, e.g. `univ2`
+    pub dex: String,
+    pub pool: Address,
+    pub token_in: Address,
+    pub token_out: Address,
+}
+
+impl GasLeg {
+    pub fn new(pair: &CryptoPair, token_in: Address, token_out: Address) -> Self {
+        Self {
+            dex: pair.dex().clone(),
+            pool: *pair.pair_id(),
+            token_in,
+            token_out,
+        }
+    }
+}
+
+/// Legs trading on `pools`, in order, as found among `pairs`. `None` if a pool is
+/// unknown.
+pub fn legs_of(pairs: &[Arc<CryptoPair>], pools: &[Address]) -> Option<Vec<GasLeg>> {
+    pools
+        .iter()
+        .map(|pool| {
+            let pair = pairs.iter().find(|pair| pair.pair_id() == pool)?;
+            Some(GasLeg::new(pair, *pair.left_id(), *pair.right_id()))
+        })
+        .collect()
+}
+
+/// Whether the gas of `tx`, running the cycle on `pools`, can calibrate the swaps:
+/// only if it is a plain flash swap, sent to the first pool and paying no coinbase
+/// tip. A flash loan, inventory transfers and approvals, or the WETH withdrawn for a
+/// tip cost gas the model has no leg for, which would be booked against the swaps.
+pub fn calibrates_from(tx: &TypedTransaction, pools: &[Address], coinbase_tip: U256) -> bool {
+    let to = match tx.to() {
+        Some(NameOrAddress::Address(to)) => to,
+        _ => return false,
+    };
+    coinbase_tip.is_zero() && pools.first() == Some(to)
+}
+
+/// Gas the protocol charges for `data` as calldata.
+pub fn calldata_gas(data: &[u8]) -> u64 {
+    data.iter()
+        .map(|byte| if *byte == 0 { ZERO_BYTE_GAS } else { NON_ZERO_BYTE_GAS })
+        .sum()
+}
+
+/// Normalised DEX label: the loaders tag pools with labels such as `" - univ2"`.
+pub fn dex_key(dex: &str) -> String {
+    dex.trim().trim_start_matches('-').trim().to_lowercase()
+}
+
+/// Warm swap cost of a DEX.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DexGas {
+    pub warm_swap: f64,
+    pub samples: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GasModel {
+    /// Transaction and executor overhead, outside the swaps
+    pub overhead: u64,
+    /// Warm swap cost for DEXes without calibration
+    pub default_warm_swap: f64,
+    pub dexes: HashMap<String, DexGas>,
+}
+
+impl Default for GasModel {
+    fn default() -> Self {
+        // A cold V2 swap measures about 60k, 45k of which remain once warm
+        let dexes = [("univ2", 45_000.0), ("sushi", 45_000.0), ("univ3", 90_000.0)]
+            .iter()
+            .map(|(dex, warm_swap)| {
+                (
+                    dex.to_string(),
+                    DexGas {
+                        warm_swap: *warm_swap,
+                        samples: 0,
+                    },
+                )
+            })
+            .collect();
+        Self {
+            overhead: TX_BASE_GAS + EXECUTOR_OVERHEAD_GAS,
+            default_warm_swap: 45_000.0,
+            dexes,
+        }
+    }
+}
+
+impl GasModel {
+    /// Loads the coefficients saved at `path`, the defaults if there are none.
+    pub fn load(path: &str) -> Self {
+        File::open(path)
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default()
+    }
+
+    /// Loads from `BACKRUNNER_GAS_MODEL` or `gas_model.json`.
+    pub fn from_env() -> Self {
+        Self::load(&Self::path())
+    }
+
+    pub fn path() -> String {
+        env::var(GAS_MODEL_ENV).unwrap_or_else(|_| DEFAULT_GAS_MODEL_PATH.to_string())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), anyhow::Error> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn warm_swap(&self, dex: &str) -> f64 {
+        self.dexes
+            .get(&dex_key(dex))
+            .map(|gas| gas.warm_swap)
+            .unwrap_or(self.default_warm_swap)
+    }
+
+    /// Surcharge of each leg for the pools and tokens it is the first to touch.
+    fn cold_surcharges(legs: &[GasLeg]) -> Vec<u64> {
+        let mut warm = HashSet::new();
+        legs.iter()
+            .map(|leg| {
+                let mut surcharge = 0;
+                if warm.insert(leg.pool) {
+                    surcharge += COLD_ACCOUNT_ACCESS + POOL_COLD_SLOTS * COLD_SLOAD;
+                }
+                for token in [leg.token_in, leg.token_out] {
+                    if warm.insert(token) {
+                        surcharge += COLD_ACCOUNT_ACCESS + TOKEN_COLD_SLOTS * COLD_SLOAD;
+                    }
+                }
+                surcharge
+            })
+            .collect()
+    }
+
+    /// Gas of each leg, cold surcharges included.
+    pub fn leg_costs(&self, legs: &[GasLeg]) -> Vec<u64> {
+        legs.iter()
+            .zip(Self::cold_surcharges(legs))
+            .map(|(leg, surcharge)| self.warm_swap(&leg.dex).round() as u64 + surcharge)
+            .collect()
+    }
+
+    /// Gas a transaction sending `calldata` and running `legs` is expected to use.
+    pub fn estimate(&self, legs: &[GasLeg], calldata: &[u8]) -> U256 {
+        U256::from(
+            self.overhead + calldata_gas(calldata) + self.leg_costs(legs).iter().sum::<u64>(),
+        )
+    }
+
+    /// Moves the warm cost of the DEXes of `legs` towards what explains `gas_used`
+    /// once `calldata` is paid, sharing the error between legs in proportion to
+    /// their warm cost.
+    pub fn observe(
+        &mut self,
+        legs: &[GasLeg],
+        calldata: &[u8],
+        gas_used: U256,
+        source: GasSource,
+    ) {
+        if legs.is_empty() || gas_used > U256::from(u64::MAX) {
+            return;
+        }
+        let predicted = self.estimate(legs, calldata).as_u64() as f64;
+        let error = gas_used.as_u64() as f64 - predicted;
+        let total_warm: f64 = legs.iter().map(|leg| self.warm_swap(&leg.dex)).sum();
+        if total_warm <= 0.0 {
+            return;
+        }
+
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for leg in legs {
+            *counts.entry(dex_key(&leg.dex)).or_default() += 1;
+        }
+        for (dex, count) in counts {
+            let warm_swap = self.warm_swap(&dex);
+            // Error per swap of this DEX, so each observation moves the estimate by
+            // the same fraction of the error however many legs share a DEX
+            let share = error * warm_swap / total_warm;
+            let gas = self.dexes.entry(dex).or_insert(DexGas {
+                warm_swap,
+                samples: 0,
+            });
+            gas.warm_swap = (warm_swap + source.weight() * share).max(0.0);
+            gas.samples += count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leg(dex: &str, pool: u8, token_in: u8, token_out: u8) -> GasLeg {
+        GasLeg {
+            dex: dex.to_string(),
+            pool: Address::repeat_byte(0xa0 + pool),
+            token_in: Address::repeat_byte(token_in),
+            token_out: Address::repeat_byte(token_out),
+        }
+    }
+
+    #[test]
+    fn test_cold_surcharges() {
+        let model = GasModel::default();
+        let legs = vec![leg(" - univ2", 1, 1, 2), leg(" - sushi", 2, 2, 1)];
+        let costs = model.leg_costs(&legs);
+        // The first leg warms both tokens, the second only pays for its pool
+        assert_eq!(costs[0], 45_000 + 6_800 + 2 * 6_800);
+        assert_eq!(costs[1], 45_000 + 6_800);
+        assert_eq!(
+            model.estimate(&legs, &[]),
+            U256::from(81_000 + costs[0] + costs[1])
+        );
+        assert_eq!(
+            model.estimate(&legs, &[0, 0, 1, 0xff]),
+            U256::from(81_000 + 2 * 4 + 2 * 16 + costs[0] + costs[1])
+        );
+        assert_eq!(model.warm_swap("unknown"), 45_000.0);
+    }
+
+    #[test]
+    fn test_observe_converges() {
+        let mut model = GasModel::default();
+        let legs = vec![leg("univ2", 1, 1, 2), leg("univ2", 2, 2, 3), leg("univ2", 3, 3, 1)];
+        // Swaps really cost 10k more each than assumed, the calldata isn't theirs
+        let calldata = vec![0xab; 2_000];
+        let actual = model.estimate(&legs, &calldata) + U256::from(30_000);
+        for _ in 0..100 {
+            model.observe(&legs, &calldata, actual, GasSource::Receipt);
+        }
+        let fitted = model.dexes["univ2"].warm_swap;
+        assert!((fitted - 55_000.0).abs() < 1.0, "fitted {}", fitted);
+        assert_eq!(model.dexes["univ2"].samples, 300);
+        assert_eq!(model.dexes["sushi"].warm_swap, 45_000.0);
+    }
+
+    #[test]
+    fn test_calibrates_from_plain_flash_swaps() {
+        let pools = [Address::repeat_byte(0xa1), Address::repeat_byte(0xa2)];
+        let tx = |to: Address| -> TypedTransaction {
+            Eip1559TransactionRequest::new().to(to).into()
+        };
+
+        assert!(calibrates_from(&tx(pools[0]), &pools, U256::zero()));
+        // The tip is unwrapped from WETH in the transaction
+        assert!(!calibrates_from(&tx(pools[0]), &pools, U256::from(1)));
+        // Flash loan from a lender, or inventory sent to the executor
+        assert!(!calibrates_from(&tx(Address::repeat_byte(0xee)), &pools, U256::zero()));
+    }
+
+    #[test]
+    fn test_persists() {
+        // Unique per run, so concurrent test runs don't share the file
+        let path = env::temp_dir().join(format!(
+            "backrunner_gas_model_test_{}_{}.json",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let path = path.to_str().unwrap();
+        let mut model = GasModel::default();
+        model.observe(
+            &[leg("univ3", 1, 1, 2)],
+            &[],
+            U256::from(200_000),
+            GasSource::Simulation,
+        );
+        model.save(path).unwrap();
+        assert_eq!(GasModel::load(path), model);
+        std::fs::remove_file(path).unwrap();
+    }
+}
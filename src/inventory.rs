@@ -0,0 +1,452 @@
+//! Cycles funded from the searcher wallet's own token inventory.
+//!
+//! When the wallet already holds the cycle's input token, the cycle can be traded
+//! through the pools' routers directly, skipping the flash swap or loan and its
+//! callback. The executor pulls the input from the wallet, trades each run of
+//! consecutive hops on one router with a single `swapExactTokensForTokens` and sends
+//! the input back, so a cycle across several routers still lands or reverts as one
+//! transaction. The wallet approves the executor once; a WETH cycle short of WETH
+//! wraps the missing ether first. Cycles the wallet can't fund fall back to
+//! `flash_source::plan_cycle`.
+use std::sync::Arc;
+
+use ethers::abi::AbiEncode;
+use ethers::core::types::transaction::eip2718::TypedTransaction;
+use ethers::prelude::*;
+
+use crate::contracts::bindings::ierc20::{ApproveCall, TransferFromCall, IERC20};
+use crate::contracts::bindings::uniswap_v2_router_02::SwapExactTokensForTokensCall;
+use crate::executor_call::{ExecutorCall, ExecutorCallError, ExecutorLeg, Repayment};
+use crate::flash_source::{plan_cycle, FlashSource, FundingPlan};
+use crate::flash_swap::{transfer, FlashSwapError};
+use crate::opportunity_aggregator::{Hop, ReserveBook};
+use crate::slippage::SlippagePolicy;
+use crate::uniswap_providers::mainnet;
+use crate::weth::{tip_steps, Weth};
+
+/// How a cycle is funded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// Flash swap or flash loan, see `FundingPlan`
+    Flash,
+    /// Tokens held by the wallet
+    Inventory,
+}
+
+/// Consecutive hops traded through one router.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouterSegment {
+    pub router: Address,
+    pub path: Vec<Address>,
+    pub amount_in: U256,
+    /// Output simulated from the reserves
+    pub amount_out: U256,
+    /// Least output accepted, `amount_out` less the slippage tolerance
+    pub amount_out_min: U256,
+}
+
+/// Splits `hops` into router segments trading `amount_in`, with the amounts out
+/// expected from `book` and guarded by `policy`. Each later segment trades the
+/// previous one's `amount_out_min`, so it never asks for more than the executor
+/// holds. `None` if a pool has no known router.
+pub fn router_segments(
+    hops: &[Hop],
+    book: &ReserveBook,
+    amount_in: U256,
+    router_of: impl Fn(Address) -> Option<Address>,
+    policy: &SlippagePolicy,
+) -> Result<Option<Vec<RouterSegment>>, FlashSwapError> {
+    let mut book = book.clone();
+    let mut segments: Vec<RouterSegment> = Vec::new();
+    let mut amount = amount_in;
+    for hop in hops {
+        let router = match router_of(hop.pool) {
+            Some(router) => router,
+            None => return Ok(None),
+        };
+        // A new router only gets what the previous one is bound to deliver
+        if let Some(segment) = segments.last().filter(|segment| segment.router != router) {
+            amount = segment.amount_out_min;
+        }
+        let amount_out = book
+            .execute(std::slice::from_ref(hop), amount)
+            .ok_or(FlashSwapError::UnknownPool(hop.pool))?;
+        match segments.last_mut() {
+            Some(segment) if segment.router == router => {
+                segment.path.push(hop.token_out);
+                segment.amount_out = amount_out;
+                segment.amount_out_min = policy.min_out(amount_out);
+            }
+            _ => segments.push(RouterSegment {
+                router,
+                path: vec![hop.token_in, hop.token_out],
+                amount_in: amount,
+                amount_out,
+                amount_out_min: policy.min_out(amount_out),
+            }),
+        }
+        amount = amount_out;
+    }
+    Ok(Some(segments))
+}
+
+/// A cycle traded from inventory by the executor.
+#[derive(Debug, Clone)]
+pub struct InventoryPlan {
+    /// The wallet lending its input to the executor
+    pub owner: Address,
+    pub executor: Address,
+    pub segments: Vec<RouterSegment>,
+    /// Unix seconds after which the swaps revert
+    pub deadline: U256,
+    /// Deposit of the ether the wallet lacks in WETH, sent first
+    pub wrap: Option<TypedTransaction>,
+    /// Approval of the executor to pull the input, when missing
+    pub approval: Option<TypedTransaction>,
+}
+
+impl InventoryPlan {
+    pub fn token(&self) -> Address {
+        self.segments
+            .first()
+            .map(|segment| segment.path[0])
+            .unwrap_or_default()
+    }
+
+    pub fn amount_in(&self) -> U256 {
+        self.segments
+            .first()
+            .map(|segment| segment.amount_in)
+            .unwrap_or_default()
+    }
+
+    pub fn output(&self) -> U256 {
+        self.segments
+            .last()
+            .map(|segment| segment.amount_out)
+            .unwrap_or_default()
+    }
+
+    pub fn profit(&self) -> Option<U256> {
+        self.output().checked_sub(self.amount_in())
+    }
+
+    /// Executor call pulling the input from the wallet, trading every segment with
+    /// the executor as recipient and sending the input back. The profit stays with
+    /// the executor, like a flash cycle's, less `miner_tip` unwrapped from it; below
+    /// `min_profit` the call reverts.
+    pub fn executor_call(
+        &self,
+        min_profit: U256,
+        miner_tip: U256,
+    ) -> Result<ExecutorCall, ExecutorCallError> {
+        let (token, amount_in) = (self.token(), self.amount_in());
+        let mut legs = vec![ExecutorLeg::call(
+            token,
+            Bytes::from(
+                TransferFromCall {
+                    sender: self.owner,
+                    recipient: self.executor,
+                    amount: amount_in,
+                }
+                .encode(),
+            ),
+        )];
+        for segment in &self.segments {
+            legs.push(ExecutorLeg::call(
+                segment.path[0],
+                Bytes::from(
+                    ApproveCall {
+                        spender: segment.router,
+                        amount: segment.amount_in,
+                    }
+                    .encode(),
+                ),
+            ));
+            let swap = SwapExactTokensForTokensCall {
+                amount_in: segment.amount_in,
+                amount_out_min: segment.amount_out_min,
+                path: segment.path.clone(),
+                to: self.executor,
+                deadline: self.deadline,
+            };
+            legs.push(
+                ExecutorLeg::swap(
+                    segment.router,
+                    Bytes::from(swap.encode()),
+                    *segment.path.last().unwrap(),
+                    segment.amount_out,
+                )
+                .with_min_output(self.executor, segment.amount_out_min),
+            );
+        }
+        legs.push(transfer(token, self.owner, amount_in));
+
+        let repayment = Repayment {
+            pool: self.owner,
+            token,
+            amount: amount_in,
+        };
+        Ok(ExecutorCall::new(self.executor, repayment)
+            .with_legs(legs)
+            .with_legs(tip_steps(*mainnet::weth, token, miner_tip)?)
+            .with_min_profit(min_profit)
+            .with_miner_tip(miner_tip))
+    }
+
+    /// Every transaction of the plan, in sending order, to go in one bundle.
+    pub fn txs(
+        &self,
+        min_profit: U256,
+        miner_tip: U256,
+    ) -> Result<Vec<TypedTransaction>, ExecutorCallError> {
+        let call = self.executor_call(min_profit, miner_tip)?.to_transaction()?;
+        Ok(self
+            .wrap
+            .iter()
+            .chain(self.approval.iter())
+            .cloned()
+            .chain(std::iter::once(call))
+            .collect())
+    }
+}
+
+/// Funding chosen for a cycle.
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum ExecutionPlan {
+    Inventory(InventoryPlan),
+    Flash(FundingPlan),
+}
+
+impl ExecutionPlan {
+    pub fn mode(&self) -> ExecutionMode {
+        match self {
+            ExecutionPlan::Inventory(_) => ExecutionMode::Inventory,
+            ExecutionPlan::Flash(_) => ExecutionMode::Flash,
+        }
+    }
+
+    pub fn profit(&self) -> Option<U256> {
+        match self {
+            ExecutionPlan::Inventory(plan) => plan.profit(),
+            ExecutionPlan::Flash(plan) => plan.profit(),
+        }
+    }
+
+    /// Transactions running the plan, flash loans requested from `sources`.
+    pub fn txs(
+        &self,
+        sources: &[Box<dyn FlashSource>],
+        min_profit: U256,
+        miner_tip: U256,
+    ) -> anyhow::Result<Vec<TypedTransaction>> {
+        Ok(match self {
+            ExecutionPlan::Inventory(plan) => plan.txs(min_profit, miner_tip)?,
+            ExecutionPlan::Flash(plan) => {
+                vec![plan.to_transaction(sources, min_profit, miner_tip)?]
+            }
+        })
+    }
+}
+
+/// Balances and approvals of the wallet funding the cycles.
+pub struct Inventory<M> {
+    client: Arc<M>,
+    /// The wallet holding the inventory
+    pub owner: Address,
+    weth: Weth<M>,
+    /// Ether never wrapped, left for gas
+    pub gas_reserve: U256,
+}
+
+impl<M: Middleware + 'static> Inventory<M> {
+    pub fn new(client: Arc<M>, owner: Address) -> Self {
+        Self {
+            weth: Weth::new(*mainnet::weth, client.clone(), owner),
+            client,
+            owner,
+            gas_reserve: U256::exp10(17),
+        }
+    }
+
+    pub async fn balance(&self, token: Address) -> Result<U256, ContractError<M>> {
+        IERC20::new(token, self.client.clone())
+            .balance_of(self.owner)
+            .call()
+            .await
+    }
+
+    pub async fn allowance(&self, token: Address, spender: Address) -> Result<U256, ContractError<M>> {
+        IERC20::new(token, self.client.clone())
+            .allowance(self.owner, spender)
+            .call()
+            .await
+    }
+
+    /// `approve(spender, MAX)` of `token` if its allowance doesn't cover `amount`.
+    pub async fn missing_approval(
+        &self,
+        token: Address,
+        spender: Address,
+        amount: U256,
+    ) -> Result<Option<TypedTransaction>, ContractError<M>> {
+        if self.allowance(token, spender).await? >= amount {
+            return Ok(None);
+        }
+        Ok(Some(
+            IERC20::new(token, self.client.clone())
+                .approve(spender, U256::MAX)
+                .tx,
+        ))
+    }
+
+    /// Plans `hops` from inventory if the wallet holds `amount_in` of the input token,
+    /// or can wrap what it lacks of WETH, and every pool has a router. Otherwise from
+    /// the cheapest flash source.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn plan(
+        &self,
+        hops: &[Hop],
+        book: &ReserveBook,
+        amount_in: U256,
+        router_of: impl Fn(Address) -> Option<Address>,
+        deadline: U256,
+        sources: &[Box<dyn FlashSource>],
+        executor: Address,
+    ) -> Result<ExecutionPlan, anyhow::Error> {
+        let first = hops
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("{}", FlashSwapError::TooFewHops))?;
+        let segments = router_segments(hops, book, amount_in, router_of, &mainnet::slippage)?;
+        let held = self.balance(first.token_in).await?;
+        let wrap = if first.token_in == self.weth.address() {
+            self.weth
+                .wrap_shortfall(held, amount_in, self.gas_reserve)
+                .await
+                .map_err(|e| anyhow::anyhow!("{}", e))?
+        } else {
+            None
+        };
+
+        let segments = match segments {
+            Some(segments) if held >= amount_in || wrap.is_some() => segments,
+            _ => {
+                println!(
+                    "Inventory of {:?} is {}, {} needed: using flash liquidity",
+                    first.token_in, held, amount_in
+                );
+                return Ok(ExecutionPlan::Flash(plan_cycle(
+                    sources,
+                    hops,
+                    book,
+                    amount_in,
+                    executor,
+                    &mainnet::slippage,
+                )?));
+            }
+        };
+
+        let approval = self
+            .missing_approval(first.token_in, executor, amount_in)
+            .await?;
+        Ok(ExecutionPlan::Inventory(InventoryPlan {
+            owner: self.owner,
+            executor,
+            segments,
+            deadline,
+            wrap,
+            approval,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{cycle_book, eth, hop};
+    use ethers::contract::EthCall;
+
+    #[test]
+    fn test_router_segments() {
+        let hops = vec![hop(1, 1, 2), hop(2, 2, 3), hop(3, 3, 1)];
+        let uniswap = Address::repeat_byte(0x11);
+        let sushiswap = Address::repeat_byte(0x22);
+        let router_of = |pool: Address| match pool.as_bytes()[0] {
+            0xa1 | 0xa2 => Some(uniswap),
+            0xa3 => Some(sushiswap),
+            _ => None,
+        };
+
+        let policy = SlippagePolicy::new(50);
+        let segments = router_segments(&hops, &cycle_book(), eth(5), router_of, &policy)
+            .unwrap()
+            .unwrap();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].router, uniswap);
+        assert_eq!(
+            segments[0].path,
+            vec![Address::repeat_byte(1), Address::repeat_byte(2), Address::repeat_byte(3)]
+        );
+        assert_eq!(segments[1].amount_in, segments[0].amount_out_min);
+        assert_eq!(segments[1].amount_out_min, policy.min_out(segments[1].amount_out));
+        assert!(segments[1].amount_out_min > eth(5));
+
+        let unknown = |pool: Address| (pool != hops[2].pool).then(|| uniswap);
+        assert!(router_segments(&hops, &cycle_book(), eth(5), unknown, &policy)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_router_segments_survive_shortfall() {
+        let hops = vec![hop(1, 1, 2), hop(2, 2, 3), hop(3, 3, 1)];
+        let router_of = |pool: Address| Some(Address::repeat_byte(pool.as_bytes()[0] - 0x90));
+        let policy = SlippagePolicy::new(50);
+        let segments = router_segments(&hops, &cycle_book(), eth(5), router_of, &policy)
+            .unwrap()
+            .unwrap();
+        assert_eq!(segments.len(), 3);
+
+        // Every router delivering the least it accepts still funds the next one
+        let mut held = eth(5);
+        for segment in &segments {
+            assert!(segment.amount_in <= held);
+            assert!(segment.amount_out_min < segment.amount_out);
+            held = segment.amount_out_min;
+        }
+    }
+
+    #[test]
+    fn test_executor_runs_every_router() {
+        let hops = vec![hop(1, 1, 2), hop(2, 2, 3), hop(3, 3, 1)];
+        let router_of = |pool: Address| Some(Address::repeat_byte(pool.as_bytes()[0] - 0x90));
+        let segments = router_segments(&hops, &cycle_book(), eth(5), router_of, &SlippagePolicy::new(50))
+            .unwrap()
+            .unwrap();
+        let (owner, executor) = (Address::repeat_byte(0xbb), Address::repeat_byte(0xee));
+        let plan = InventoryPlan {
+            owner,
+            executor,
+            segments,
+            deadline: U256::from(1_700_000_000),
+            wrap: None,
+            approval: None,
+        };
+
+        let call = plan.executor_call(U256::zero(), U256::zero()).unwrap();
+        // Pull, then an approval and a swap per router, then the input back
+        assert_eq!(call.legs.len(), 8);
+        assert_eq!(call.legs[0].calldata[..4], TransferFromCall::selector());
+        assert_eq!(call.legs[2].target, Address::repeat_byte(0x11));
+        assert_eq!(call.legs[6].target, Address::repeat_byte(0x13));
+        assert_eq!(call.legs[7].target, Address::repeat_byte(1));
+        assert_eq!(call.profit(), plan.profit());
+
+        let txs = plan.txs(U256::zero(), U256::zero()).unwrap();
+        assert_eq!(txs.len(), 1);
+        assert_eq!(txs[0].to(), Some(&NameOrAddress::Address(executor)));
+        // The profit isn't WETH, the executor has no ether to tip with
+        assert!(plan.txs(U256::zero(), U256::one()).is_err());
+    }
+}
@@ -7,26 +7,49 @@
 pub mod arb_signal;
 pub mod arb_thread_pool;
 pub mod arbitrage_path;
+pub mod backrun;
+pub mod bid_policy;
+pub mod bundle_simulation;
+pub mod bundle_stats;
+pub mod bundle_submitter;
 pub mod call_julia;
 pub mod contracts;
+pub mod conversion;
 pub mod crypto_math;
 pub mod crypto_pair;
 pub mod dex_pool;
+pub mod executor_call;
+pub mod fee_predictor;
+pub mod flash_source;
+pub mod flash_swap;
 pub mod flashbot_strategy;
+pub mod gas_model;
 pub mod graphql_uniswapv2;
 pub mod graphql_uniswapv3;
+pub mod inventory;
+pub mod mempool;
+pub mod net_profit;
+pub mod price_oracle;
 pub mod sequence_token;
+pub mod signers;
+pub mod slippage;
 pub mod sources;
 pub mod swap_route;
+#[cfg(test)]
+pub mod test_fixtures;
 pub mod three_path_sequence;
 pub mod uniswap_providers;
 pub mod uniswap_transaction;
 pub mod uniswapv2_pairs;
 pub mod uniswapv3_pools;
+pub mod weth;
 pub mod utils;
 
 pub mod confirmed_tx_monitor;
+pub mod nonce_manager;
+pub mod opportunity_aggregator;
 pub mod path_sequence;
+pub mod private_tx;
 pub mod path_sequence_factory;
 pub mod transaction_log_utils;
 pub mod transaction_utils;
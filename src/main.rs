@@ -38,19 +38,41 @@ use backrunner_rs::three_path_sequence::{
 pub mod arb_signal;
 pub mod arb_thread_pool;
 pub mod arbitrage_path;
+pub mod backrun;
+pub mod bid_policy;
+pub mod bundle_simulation;
+pub mod bundle_stats;
+pub mod bundle_submitter;
 pub mod arbitrage_paths;
 pub mod confirmed_tx_monitor;
 pub mod contracts;
+pub mod conversion;
 pub mod crypto_math;
 pub mod crypto_pair;
 pub mod dex_pool;
+pub mod executor_call;
+pub mod fee_predictor;
+pub mod flash_source;
+pub mod flash_swap;
 pub mod flashbot_strategy;
+pub mod gas_model;
 pub mod graphql_uniswapv2;
 pub mod graphql_uniswapv3;
+pub mod inventory;
+pub mod mempool;
+pub mod net_profit;
+pub mod nonce_manager;
+pub mod opportunity_aggregator;
 pub mod path_sequence;
+pub mod price_oracle;
+pub mod private_tx;
 pub mod path_sequence_factory;
 pub mod sequence_token;
+pub mod signers;
+pub mod slippage;
 pub mod swap_route;
+#[cfg(test)]
+pub mod test_fixtures;
 pub mod three_path_sequence;
 pub mod transaction_log_utils;
 pub mod transaction_utils;
@@ -59,6 +81,7 @@ pub mod uniswap_providers;
 pub mod uniswap_transaction;
 pub mod uniswapv2_pairs;
 pub mod uniswapv3_pools;
+pub mod weth;
 pub mod cfmmrouter;
 pub mod utils;
 use std::os::raw::c_char;
@@ -117,6 +140,12 @@ async fn main() -> Result<(), Report> {
     let args: Vec<String> = env::args().collect();
     color_eyre::install()?;
 
+    /* Resolve the signers now, every client and submission task needs them */
+    uniswap_providers::mainnet::load_signers()
+        .map_err(|e| eyre!("could not load the searcher signers: {}", e))?;
+    uniswap_providers::mainnet::load_executor()
+        .map_err(|e| eyre!("could not load the executor: {}", e))?;
+
     /*
     TODO
     1.) Populate paths from GraphQL
@@ -256,6 +285,9 @@ async fn main() -> Result<(), Report> {
             }
         }
 
+        /* Pairs conversion legs may trade through */
+        conversion::register_pairs(crypto_pairs.values());
+
         /*
         Next we want to create arbitrage paths based on the contents of the serialized vector, except we will instead look to the map map above for references.
          */
@@ -270,6 +302,37 @@ async fn main() -> Result<(), Report> {
         println!("pairs: {}, paths: {}", &crypto_pairs.len(), arb_paths.len());
     }
 
+    /* Keep the pending swaps of every pair, to backrun them */
+    arb_thread_pool::spawn(mempool::watch(crypto_pairs.values().cloned().collect()));
+
+    /* Follow block fees for bids and profit estimates */
+    arb_thread_pool::spawn(fee_predictor::watch(
+        uniswap_providers::mainnet::client.clone(),
+        &uniswap_providers::mainnet::fee_predictor,
+    ));
+
+    /* Pack every path's candidates into one bundle per block */
+    arb_thread_pool::spawn(opportunity_aggregator::watch(
+        uniswap_providers::mainnet::client.clone(),
+        &uniswap_providers::mainnet::opportunity_aggregator,
+        opportunity_aggregator::window_from_env(),
+    ));
+
+    /* Follow what the flash lenders can lend */
+    arb_thread_pool::spawn(flash_source::watch_liquidity(
+        uniswap_providers::mainnet::client.clone(),
+        &uniswap_providers::mainnet::flash_sources,
+    ));
+
+    /* Smooth the reference prices of ETH and USD over a TWAP, if configured */
+    if let Some(period) = price_oracle::twap_period_from_env() {
+        price_oracle::TWAPS.write().unwrap().period = period;
+        arb_thread_pool::spawn(price_oracle::watch_twaps(
+            uniswap_providers::mainnet::client.clone(),
+            &price_oracle::TWAPS,
+        ));
+    }
+
     confirmed_tx_monitor::monitor_tx(&mut crypto_pairs.clone());
 
     /*
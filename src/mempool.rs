@@ -0,0 +1,293 @@
+//! Pending swaps from the Blocknative mempool stream, kept on the pairs they trade on.
+//!
+//! Router swaps touching a tracked pair are converted to `Victim`s and added to the
+//! pending transactions of every pair along their path. They are dropped again once
+//! Blocknative reports them confirmed, failed, dropped or cancelled.
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use blocknative::models::Blockchain;
+use blocknative::ws::filters::{FilterBuilder, FilterError};
+use blocknative::ws::models::{ContractCall, Event, WatchConfig};
+use blocknative::ws::ws::Ws;
+use ethers::prelude::*;
+use futures_util::StreamExt;
+
+use crate::backrun::Victim;
+use crate::crypto_pair::CryptoPair;
+
+pub const BLOCKNATIVE_URL: &str = "wss://api.blocknative.com/v0";
+pub const API_KEY_ENV: &str = "BACKRUNNER_BLOCKNATIVE_API_KEY";
+/// Wait before reconnecting after a Blocknative connection failed or closed
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Tracked pairs by router and token pair, to find the pairs a swap path trades on.
+#[derive(Debug, Default, Clone)]
+pub struct PairIndex {
+    routes: HashMap<(Address, Address, Address), Vec<Arc<CryptoPair>>>,
+}
+
+fn route_key(router: Address, a: Address, b: Address) -> (Address, Address, Address) {
+    if a < b {
+        (router, a, b)
+    } else {
+        (router, b, a)
+    }
+}
+
+impl PairIndex {
+    pub fn new<'a>(pairs: impl IntoIterator<Item = &'a Arc<CryptoPair>>) -> Self {
+        let mut routes: HashMap<_, Vec<Arc<CryptoPair>>> = HashMap::new();
+        for pair in pairs {
+            routes
+                .entry(route_key(pair.router(), *pair.left_id(), *pair.right_id()))
+                .or_default()
+                .push(pair.clone());
+        }
+        Self { routes }
+    }
+
+    /// Pairs traded by each hop of the `path` of a router call.
+    pub fn touched(&self, call: &ContractCall) -> Vec<Arc<CryptoPair>> {
+        let router = match call.contract_address.parse::<Address>() {
+            Ok(router) => router,
+            Err(_) => return vec![],
+        };
+        let path: Vec<Address> = match call.params["path"].as_array() {
+            Some(path) => path
+                .iter()
+                .filter_map(|token| token.as_str()?.parse().ok())
+                .collect(),
+            None => return vec![],
+        };
+        path.windows(2)
+            .filter_map(|hop| self.routes.get(&route_key(router, hop[0], hop[1])))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// One watch config per router, on swaps touching a token of its pairs.
+    pub fn watch_configs(&self) -> Result<Vec<WatchConfig>, FilterError> {
+        let routes: Vec<(String, String)> = self
+            .routes
+            .keys()
+            .flat_map(|(router, a, b)| {
+                let router = format!("{:?}", router);
+                [
+                    (router.clone(), format!("{:?}", a)),
+                    (router, format!("{:?}", b)),
+                ]
+            })
+            .collect();
+        FilterBuilder::router_swaps(
+            routes
+                .iter()
+                .map(|(router, token)| (router.as_str(), token.as_str())),
+        )
+    }
+}
+
+/// Adds or removes the transaction of `event` on the pairs it trades on. Returns
+/// the number of pairs updated.
+pub fn on_event(event: &Event, index: &PairIndex, chain_id: Option<u64>) -> usize {
+    let (tx, call) = match (&event.transaction, &event.contract_call) {
+        (Some(tx), Some(call)) => (tx, call),
+        _ => return 0,
+    };
+    let pairs = index.touched(call);
+    if pairs.is_empty() {
+        return 0;
+    }
+
+    match tx.status.as_str() {
+        "pending" | "speedup" => {
+            let pools = pairs.iter().map(|pair| *pair.pair_id()).collect();
+            let victim = match Victim::from_blocknative(tx, chain_id, pools) {
+                Ok(victim) => victim,
+                Err(e) => {
+                    println!("Skipping mempool tx {}: {}", tx.hash, e);
+                    return 0;
+                }
+            };
+            for pair in &pairs {
+                pair.add_pending_tx(victim.clone());
+            }
+        }
+        "confirmed" | "failed" | "dropped" | "cancel" => {
+            let hash = match tx.hash.parse::<H256>() {
+                Ok(hash) => hash,
+                Err(_) => return 0,
+            };
+            for pair in &pairs {
+                pair.remove_pending_tx(&hash);
+            }
+        }
+        _ => return 0,
+    }
+    pairs.len()
+}
+
+/// Follows the mempool swaps of every router of `pairs`, reconnecting whenever a
+/// connection drops. Does nothing if no Blocknative api key is configured.
+pub async fn watch(pairs: Vec<Arc<CryptoPair>>) {
+    let api_key = match env::var(API_KEY_ENV) {
+        Ok(api_key) => api_key,
+        Err(_) => {
+            println!("{} is not set, not following the mempool", API_KEY_ENV);
+            return;
+        }
+    };
+    let index = Arc::new(PairIndex::new(&pairs));
+    let configs = match index.watch_configs() {
+        Ok(configs) => configs,
+        Err(e) => {
+            println!("Invalid mempool filter: {}", e);
+            return;
+        }
+    };
+
+    // Events of a connection all go to its latest subscription, so each router
+    // gets its own connection.
+    let streams = configs.into_iter().map(|config| {
+        let index = index.clone();
+        let api_key = api_key.clone();
+        async move {
+            let mut stream = Ws::listen_reconnecting(
+                BLOCKNATIVE_URL.to_string(),
+                &api_key,
+                Blockchain::main(),
+                config,
+                RECONNECT_DELAY,
+            );
+            while let Some(response) = stream.next().await {
+                if let Some(event) = &response.event {
+                    on_event(event, &index, Blockchain::main().chain_id());
+                }
+            }
+        }
+    });
+    futures::future::join_all(streams).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backrun::compose;
+    use crate::dex_pool::DexPool;
+    use crate::uniswapv2_pairs::uniswap_pairs::UniswapPairsPairsTokens;
+    use ethers::utils::keccak256;
+
+    const ROUTER: &str = "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D";
+    const WETH: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+    const DAI: &str = "0x6B175474E89094C44Da98b954EedeAC495271d0F";
+    const USDC: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48";
+
+    fn token(id: &str) -> UniswapPairsPairsTokens {
+        UniswapPairsPairsTokens {
+            id: id.parse().unwrap(),
+            symbol: String::new(),
+            name: String::new(),
+            decimals: 18,
+            reserve: U256::exp10(21),
+        }
+    }
+
+    fn pair(id: u8, token0: &str, token1: &str) -> Arc<CryptoPair> {
+        Arc::new(CryptoPair::new(DexPool {
+            id: Address::repeat_byte(id),
+            sqrt_price: U256::zero(),
+            liquidity: U256::zero(),
+            fee_tier: 0,
+            tick: 0,
+            dex: "uni_v2".to_string(),
+            router: ROUTER.parse().unwrap(),
+            token0: token(token0),
+            token1: token(token1),
+        }))
+    }
+
+    /// A signed EIP-1559 mempool transaction swapping WETH to DAI to USDC.
+    fn event(status: &str) -> Event {
+        serde_json::from_value(serde_json::json!({
+            "timeStamp": "2022-02-05T05:47:08.506Z",
+            "categoryCode": "activeAddress",
+            "eventCode": "txPool",
+            "dappId": "test",
+            "blockchain": {"system": "ethereum", "network": "main"},
+            "contractCall": {
+                "contractType": "Uniswap V2: Router 2",
+                "contractAddress": ROUTER,
+                "methodName": "swapExactTokensForTokens",
+                "params": {"path": [WETH, DAI, USDC]},
+                "contractName": null,
+            },
+            "transaction": {
+                "status": status,
+                "monitorId": "Geth_1_C2_PROD",
+                "monitorVersion": "0.108.0",
+                "pendingTimeStamp": "2022-02-05T05:47:08.506Z",
+                "pendingBlockNumber": 14144167,
+                "hash": "0x90702ff7d6ceac84f889e0520ef2373dbfc7046a686c63a99433fbe93905ffff",
+                "from": "0x1FF60C59246A7b6B4A5090218881Af7f844458b0",
+                "to": "0xa4492fcDa2520cB68657d220f4D4aE3116359C10",
+                "value": "0",
+                "gas": 1003544,
+                "nonce": 710,
+                "v": "0x1",
+                "r": "0x135a68dbc7fd639d8c1ec2b7f6a05e4748cb460a2662d852fd79c32cb846fca3",
+                "s": "0x11f6ee30bde1efdd58048b7179ddad945115979ffb077ed30b44af1be8317d25",
+                "input": "0x677956f100000000000000000000000000000000000000000000000000000000620f5200000000000000000000000000000000000000000000000bb80000000000000000000000000000000000000000000000000000000000000000016345785d8a00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000ac1541126c04cf00",
+                "type": 2,
+                "maxFeePerGas": "5000000000",
+                "maxPriorityFeePerGas": "1500000000",
+                "asset": "ETH",
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_event_to_bundle() {
+        let weth_dai = pair(0x01, WETH, DAI);
+        let dai_usdc = pair(0x02, USDC, DAI);
+        let unrelated = pair(0x03, WETH, USDC);
+        let index = PairIndex::new(&[weth_dai.clone(), dai_usdc.clone(), unrelated.clone()]);
+
+        assert_eq!(on_event(&event("pending"), &index, Some(1)), 2);
+        assert_eq!(weth_dai.pending_txs().len(), 1);
+        assert_eq!(dai_usdc.pending_txs().len(), 1);
+        assert!(unrelated.pending_txs().is_empty());
+
+        let victims = weth_dai.pending_txs();
+        let (bundle, ordered) = compose(
+            &victims,
+            &[*weth_dai.pair_id()],
+            vec![Bytes::from(vec![0xff])],
+            U256::exp10(9),
+        );
+        assert_eq!(ordered.len(), 1);
+        assert_eq!(bundle.transactions().len(), 2);
+        assert_eq!(
+            H256::from(keccak256(&ordered[0].raw)),
+            event("pending").transaction.unwrap().hash.parse().unwrap()
+        );
+
+        assert_eq!(on_event(&event("confirmed"), &index, Some(1)), 2);
+        assert!(weth_dai.pending_txs().is_empty());
+        assert!(dai_usdc.pending_txs().is_empty());
+    }
+
+    #[test]
+    fn test_watch_configs() {
+        let index = PairIndex::new(&[pair(0x01, WETH, DAI), pair(0x02, USDC, DAI)]);
+        let configs = index.watch_configs().unwrap();
+        assert_eq!(configs.len(), 1);
+        assert_eq!(
+            configs[0].scope,
+            format!("{:?}", ROUTER.parse::<Address>().unwrap())
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Net profit of an opportunity, in wei.
+//!
+//! A cycle's gross profit is in units of its input token. It is valued in ETH by the
+//! `price_oracle` snapshot of the block, then the gas the cycle is expected to burn,
+//! see `gas_model`, at the expected base fee and the builder's tip are deducted. Only
+//! opportunities clearing `mainnet::min_net_profit` are executed.
+use std::env;
+
+use ethers::prelude::*;
+
+use crate::bid_policy::BidPolicy;
+use crate::price_oracle::PriceOracle;
+
+/// Env var overriding the least net profit executed, in wei.
+pub const MIN_NET_PROFIT_ENV: &str = "BACKRUNNER_MIN_NET_PROFIT_WEI";
+
+/// Intrinsic gas of a transaction
+pub const TX_BASE_GAS: u64 = 21_000;
+/// Executor entry, callback dispatch, repayment and coinbase transfer
+pub const EXECUTOR_OVERHEAD_GAS: u64 = 60_000;
+
+/// Breakdown of an opportunity's profit, every amount in wei.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetProfit {
+    /// Output less input, valued in ETH
+    pub gross: U256,
+    pub gas: U256,
+    pub gas_cost: U256,
+    pub tip: U256,
+}
+
+impl NetProfit {
+    /// Values `output - input` of `token` in ETH and deducts `gas` at `base_fee` and
+    /// the tip `policy` pays on what is left. `None` if the output doesn't cover the
+    /// input or `oracle` can't price `token`.
+    pub fn evaluate(
+        oracle: &PriceOracle,
+        token: Address,
+        input: U256,
+        output: U256,
+        gas: U256,
+        base_fee: U256,
+        policy: &BidPolicy,
+    ) -> Option<Self> {
+        let gross = oracle.eth_value_wei(token, output.checked_sub(input)?)?;
+        let gas_cost = gas.saturating_mul(base_fee);
+        let mut profit = Self {
+            gross,
+            gas,
+            gas_cost,
+            tip: U256::zero(),
+        };
+        profit.tip = policy.tip(profit.after_gas());
+        Some(profit)
+    }
+
+    /// Gross profit less gas, the amount the tip is a share of.
+    pub fn after_gas(&self) -> U256 {
+        self.gross.saturating_sub(self.gas_cost)
+    }
+
+    /// What is left once gas and tip are paid, `None` if they exceed the gross profit.
+    pub fn net(&self) -> Option<U256> {
+        self.gross.checked_sub(self.gas_cost)?.checked_sub(self.tip)
+    }
+
+    pub fn clears(&self, min_net_profit: U256) -> bool {
+        self.net().is_some_and(|net| net >= min_net_profit)
+    }
+
+    /// Least profit worth executing, in the input token, for a quoted `token_profit`
+    /// valued at `gross`: what pays the gas, the tip and `min_net_profit`. The executor
+    /// reverts below it, so the legs may fall short of their quotes by the rest.
+    pub fn least_token_profit(&self, token_profit: U256, min_net_profit: U256) -> U256 {
+        if self.gross.is_zero() {
+            return token_profit;
+        }
+        let least = self
+            .gas_cost
+            .saturating_add(self.tip)
+            .saturating_add(min_net_profit)
+            .min(self.gross);
+        U256::try_from(token_profit.full_mul(least) / U512::from(self.gross))
+            .unwrap_or(token_profit)
+    }
+}
+
+/// Reads `BACKRUNNER_MIN_NET_PROFIT_WEI`, zero if unset.
+pub fn min_net_profit_from_env() -> U256 {
+    env::var(MIN_NET_PROFIT_ENV)
+        .ok()
+        .and_then(|wei| U256::from_dec_str(&wei).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use num::rational::Ratio;
+
+    use super::*;
+    use crate::executor_call::{ExecutorCall, ExecutorLeg, Repayment};
+    use crate::slippage::SlippagePolicy;
+    use crate::test_fixtures::{eth, gwei};
+
+    #[test]
+    fn test_least_profit_leaves_room_for_slippage() {
+        let weth = Address::repeat_byte(0xee);
+        let oracle = PriceOracle::new(weth, vec![], vec![]);
+        let policy = BidPolicy {
+            tip_share: Ratio::new(50, 100),
+            ..BidPolicy::default()
+        };
+        let quoted = eth(1) + U256::exp10(16);
+        let profit = NetProfit::evaluate(
+            &oracle,
+            weth,
+            eth(1),
+            quoted,
+            U256::from(261_000),
+            gwei(20),
+            &policy,
+        )
+        .unwrap();
+        let least = profit.least_token_profit(U256::exp10(16), gwei(2_000_000));
+        assert_eq!(least, gwei(9_610_000));
+
+        // The last leg comes in 0.0003 ETH short, well inside its 30 bps tolerance
+        let min_out = SlippagePolicy::new(30).min_out(quoted);
+        let output = quoted - gwei(300_000);
+        assert!(output >= min_out);
+        let call = ExecutorCall::new(
+            weth,
+            Repayment {
+                pool: Address::repeat_byte(0xa1),
+                token: weth,
+                amount: eth(1),
+            },
+        )
+        .with_legs([ExecutorLeg::swap(weth, Bytes::default(), weth, quoted)
+            .with_min_output(weth, min_out)])
+        .with_min_profit(least)
+        .with_miner_tip(profit.tip)
+        .execute_call()
+        .unwrap();
+        // What the executor checks once the tip is unwrapped
+        assert!(output - eth(1) - profit.tip >= call.min_profit);
+        // Still held to gas and the least net profit
+        assert_eq!(call.min_profit, gwei(7_220_000));
+    }
+
+    #[test]
+    fn test_net_profit() {
+        let weth = Address::repeat_byte(0xee);
+        let oracle = PriceOracle::new(weth, vec![], vec![]);
+        let policy = BidPolicy {
+            tip_share: Ratio::new(50, 100),
+            ..BidPolicy::default()
+        };
+        let gas = U256::from(261_000);
+
+        // 0.01 ETH gross, 261k gas at 20 gwei
+        let profit = NetProfit::evaluate(
+            &oracle,
+            weth,
+            eth(1),
+            eth(1) + U256::exp10(16),
+            gas,
+            gwei(20),
+            &policy,
+        )
+        .unwrap();
+        assert_eq!(profit.gas_cost, gwei(5_220_000));
+        assert_eq!(profit.tip, gwei(2_390_000));
+        assert_eq!(profit.net(), Some(gwei(2_390_000)));
+        assert!(profit.clears(gwei(2_000_000)));
+        assert!(!profit.clears(gwei(3_000_000)));
+
+        // Gas alone eats the profit at 50 gwei
+        let profit = NetProfit::evaluate(
+            &oracle,
+            weth,
+            eth(1),
+            eth(1) + U256::exp10(16),
+            gas,
+            gwei(50),
+            &policy,
+        )
+        .unwrap();
+        assert_eq!(profit.net(), None);
+        assert!(!profit.clears(U256::zero()));
+
+        assert!(
+            NetProfit::evaluate(&oracle, weth, eth(1), eth(1) / 2, gas, gwei(1), &policy).is_none()
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! Nonces of the searcher account across concurrent submissions.
+//!
+//! A bundle can only land if every nonce below its own is already used, so a
+//! submission never counts on another one landing first: nonces are always reserved
+//! from the on-chain transaction count. A newer submission reusing the nonces of one
+//! still in flight replaces it, since only one of them can land: the older one's
+//! private transaction is cancelled and its bundle submission aborted. One replaced
+//! before sending its private transaction finds out when recording it, and cancels it
+//! itself. Reservations are given back when included, dropped or expired.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use ethers::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NonceError {
+    NotSynced,
+}
+
+impl fmt::Display for NonceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NonceError::NotSynced => write!(f, "the account nonce hasn't been synced yet"),
+        }
+    }
+}
+
+impl std::error::Error for NonceError {}
+
+/// Consecutive nonces held by one submission until `until_block`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NonceReservation {
+    pub id: u64,
+    /// Bundle submission sending the nonces, see `BundleSubmitter::open`
+    pub submission: u64,
+    pub nonces: Vec<U256>,
+    pub until_block: U64,
+    /// Private transaction sent with the nonces, retried by the relay
+    pub private_tx: Option<H256>,
+    /// Private transactions of the reservations this one replaced, to be cancelled
+    pub replaces: Vec<H256>,
+    /// Bundle submissions of the reservations this one replaced, to be aborted
+    pub aborts: Vec<u64>,
+}
+
+impl NonceReservation {
+    /// Account nonce once every transaction of the reservation is included.
+    pub fn next(&self) -> Option<U256> {
+        self.nonces.last().map(|nonce| *nonce + 1)
+    }
+}
+
+#[derive(Debug, Default)]
+struct NonceState {
+    /// Transaction count of the account as of the latest sync
+    confirmed: Option<U256>,
+    /// Block of the latest expiry check
+    block: U64,
+    in_flight: BTreeMap<u64, NonceReservation>,
+    next_id: u64,
+}
+
+impl NonceState {
+    /// Drops reservations whose nonces were used on chain or whose blocks passed.
+    fn prune(&mut self) {
+        let confirmed = self.confirmed;
+        let block = self.block;
+        self.in_flight.retain(|_, reservation| {
+            let used = match (confirmed, reservation.nonces.first()) {
+                (Some(confirmed), Some(first)) => *first < confirmed,
+                _ => false,
+            };
+            !used && reservation.until_block >= block
+        });
+    }
+}
+
+/// Hands out the nonces of `address` to concurrent submissions.
+pub struct NonceManager {
+    pub address: Address,
+    state: Mutex<NonceState>,
+}
+
+impl NonceManager {
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            state: Default::default(),
+        }
+    }
+
+    /// Resyncs with the on-chain transaction count of the account.
+    pub async fn sync<M: Middleware>(&self, client: &M) -> Result<U256, M::Error> {
+        let nonce = client
+            .get_transaction_count(self.address, Some(BlockId::from(BlockNumber::Latest)))
+            .await?;
+        self.set_confirmed(nonce);
+        Ok(nonce)
+    }
+
+    /// Records the on-chain transaction count. Reservations whose nonces were used
+    /// are released, whichever transaction used them.
+    pub fn set_confirmed(&self, nonce: U256) {
+        let mut state = self.state.lock().unwrap();
+        state.confirmed = Some(state.confirmed.map_or(nonce, |confirmed| confirmed.max(nonce)));
+        state.prune();
+    }
+
+    /// Releases reservations which only targeted blocks before `block`.
+    pub fn expire(&self, block: U64) {
+        let mut state = self.state.lock().unwrap();
+        state.block = state.block.max(block);
+        state.prune();
+    }
+
+    /// Reserves `count` nonces starting at the confirmed one, for `submission`
+    /// targeting blocks up to `until_block`. Reservations in flight sharing one of
+    /// them are replaced, their private transactions listed in `replaces` and their
+    /// bundle submissions in `aborts`.
+    pub fn reserve(
+        &self,
+        count: usize,
+        until_block: U64,
+        submission: u64,
+    ) -> Result<NonceReservation, NonceError> {
+        let mut state = self.state.lock().unwrap();
+        let confirmed = state.confirmed.ok_or(NonceError::NotSynced)?;
+        let nonces: Vec<U256> = (0..count as u64).map(|offset| confirmed + offset).collect();
+
+        let replaced: Vec<u64> = state
+            .in_flight
+            .values()
+            .filter(|reservation| reservation.nonces.iter().any(|nonce| nonces.contains(nonce)))
+            .map(|reservation| reservation.id)
+            .collect();
+        let replaced: Vec<NonceReservation> = replaced
+            .iter()
+            .filter_map(|id| state.in_flight.remove(id))
+            .collect();
+
+        let id = state.next_id;
+        state.next_id += 1;
+        let reservation = NonceReservation {
+            id,
+            submission,
+            nonces,
+            until_block,
+            private_tx: None,
+            replaces: replaced.iter().filter_map(|r| r.private_tx).collect(),
+            aborts: replaced.iter().map(|r| r.submission).collect(),
+        };
+        if count > 0 {
+            state.in_flight.insert(id, reservation.clone());
+        }
+        Ok(reservation)
+    }
+
+    /// Whether the reservation still holds its nonces, not replaced, released or
+    /// expired. A reservation of no nonces always does.
+    pub fn is_live(&self, reservation: &NonceReservation) -> bool {
+        reservation.nonces.is_empty()
+            || self
+                .state
+                .lock()
+                .unwrap()
+                .in_flight
+                .contains_key(&reservation.id)
+    }
+
+    /// Records the private transaction sent with a reservation, cancelled if a later
+    /// submission replaces it. Returns false if the reservation is no longer live: a
+    /// submission which replaced it in the meantime didn't know of the transaction, so
+    /// the caller has to cancel it.
+    pub fn sent_privately(&self, reservation: &NonceReservation, tx_hash: H256) -> bool {
+        if reservation.nonces.is_empty() {
+            return true;
+        }
+        let mut state = self.state.lock().unwrap();
+        match state.in_flight.get_mut(&reservation.id) {
+            Some(reservation) => {
+                reservation.private_tx = Some(tx_hash);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Gives back the nonces of a submission which wasn't included.
+    pub fn release(&self, reservation: &NonceReservation) {
+        self.state.lock().unwrap().in_flight.remove(&reservation.id);
+    }
+
+    /// Marks a reservation as included: its nonces are used and the next
+    /// submission starts after them.
+    pub fn commit(&self, reservation: &NonceReservation) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight.remove(&reservation.id);
+        if let Some(next) = reservation.next() {
+            state.confirmed = Some(state.confirmed.map_or(next, |confirmed| confirmed.max(next)));
+        }
+        state.prune();
+    }
+
+    pub fn in_flight(&self) -> Vec<NonceReservation> {
+        self.state.lock().unwrap().in_flight.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(confirmed: u64) -> NonceManager {
+        let manager = NonceManager::new(Address::zero());
+        manager.set_confirmed(U256::from(confirmed));
+        manager
+    }
+
+    #[test]
+    fn test_reserve_requires_sync() {
+        let manager = NonceManager::new(Address::zero());
+        assert_eq!(manager.reserve(1, U64::from(10), 0), Err(NonceError::NotSynced));
+    }
+
+    #[test]
+    fn test_same_nonces_replace() {
+        let manager = manager(7);
+        let first = manager.reserve(2, U64::from(10), 1).unwrap();
+        assert_eq!(first.nonces, vec![U256::from(7), U256::from(8)]);
+        let tx_hash = H256::repeat_byte(1);
+        assert!(manager.sent_privately(&first, tx_hash));
+
+        // Never stacked after the first, which may not land
+        let second = manager.reserve(1, U64::from(10), 2).unwrap();
+        assert_eq!(second.nonces, vec![U256::from(7)]);
+        assert_eq!(second.replaces, vec![tx_hash]);
+        assert_eq!(second.aborts, vec![1]);
+        assert_eq!(manager.in_flight(), vec![second.clone()]);
+
+        manager.release(&second);
+        let third = manager.reserve(1, U64::from(11), 3).unwrap();
+        assert_eq!(third.nonces, vec![U256::from(7)]);
+        assert!(third.replaces.is_empty());
+        assert!(third.aborts.is_empty());
+    }
+
+    #[test]
+    fn test_replaced_before_sending_privately() {
+        let manager = manager(7);
+        // The first is still simulating when the second replaces it
+        let first = manager.reserve(1, U64::from(10), 1).unwrap();
+        let second = manager.reserve(1, U64::from(10), 2).unwrap();
+        assert!(second.replaces.is_empty());
+        assert_eq!(second.aborts, vec![1]);
+        assert!(!manager.is_live(&first));
+        assert!(manager.is_live(&second));
+
+        // Sent anyway: not recorded, left to the first to cancel
+        assert!(!manager.sent_privately(&first, H256::repeat_byte(1)));
+        assert!(manager.sent_privately(&second, H256::repeat_byte(2)));
+        assert_eq!(manager.in_flight()[0].private_tx, Some(H256::repeat_byte(2)));
+        let third = manager.reserve(1, U64::from(10), 3).unwrap();
+        assert_eq!(third.replaces, vec![H256::repeat_byte(2)]);
+    }
+
+    #[test]
+    fn test_resync_on_inclusion_and_expiry() {
+        let manager = manager(7);
+        let first = manager.reserve(2, U64::from(10), 1).unwrap();
+        manager.commit(&first);
+        assert_eq!(manager.reserve(1, U64::from(12), 2).unwrap().nonces, vec![U256::from(9)]);
+
+        // Still held until its last block has passed
+        manager.expire(U64::from(12));
+        assert_eq!(manager.in_flight().len(), 1);
+        manager.expire(U64::from(13));
+        assert!(manager.in_flight().is_empty());
+
+        // Used by a transaction sent elsewhere
+        let third = manager.reserve(1, U64::from(20), 3).unwrap();
+        assert_eq!(third.nonces, vec![U256::from(9)]);
+        manager.set_confirmed(U256::from(10));
+        assert!(manager.in_flight().is_empty());
+        assert_eq!(manager.reserve(1, U64::from(20), 4).unwrap().nonces, vec![U256::from(10)]);
+    }
+}
@@ -0,0 +1,459 @@
+//! Per-block aggregation of arbitrage candidates.
+//!
+//! Every path evaluates independently, so cycles sharing a pool can each produce a
+//! bundle for the same block and invalidate one another. Candidates are collected per
+//! block instead and replayed most profitable first on one running copy of the
+//! block's reserves, each kept candidate trading into it. A candidate sharing pools
+//! with earlier ones is kept only if it still pays off on the reserves they leave. The
+//! survivors are packed into one bundle, submitted once per block by `watch` without
+//! holding up the blocks after it.
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ethers::core::types::transaction::eip2718::TypedTransaction;
+use ethers::prelude::*;
+
+use crate::arb_thread_pool;
+use crate::backrun::Victim;
+use crate::bundle_simulation::SimulationGate;
+use crate::conversion;
+use crate::crypto_pair::CryptoPair;
+use crate::flashbot_strategy::utils::send_flashswap_bundle;
+use crate::path_sequence::PathOutcome;
+use crate::price_oracle;
+use crate::uniswap_providers::mainnet;
+
+pub const WINDOW_ENV: &str = "BACKRUNNER_AGGREGATION_MS";
+/// Time candidates are collected after a block arrives, before being submitted
+pub const DEFAULT_WINDOW: Duration = Duration::from_millis(300);
+
+/// One swap of a cycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hop {
+    pub pool: Address,
+    pub token_in: Address,
+    pub token_out: Address,
+}
+
+/// A profitable cycle found by a path, with the transactions executing it.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub outcome: PathOutcome,
+    pub hops: Vec<Hop>,
+    pub txs: Vec<TypedTransaction>,
+    /// Pending transactions the cycle backruns
+    pub victims: Vec<Victim>,
+}
+
+impl Candidate {
+    /// Trades the candidate on `book` if it still returns more than its input and at
+    /// least what it returns on `priced`, the reserves it was built for: its
+    /// transactions ask every pool for the amounts priced there, and the executor
+    /// reverts below the profit priced there.
+    pub fn replay(&self, book: &mut ReserveBook, priced: &ReserveBook) -> bool {
+        let amount_in = self.outcome.amount_in;
+        let expected = match priced.clone().execute(&self.hops, amount_in) {
+            Some(expected) => expected,
+            None => return false,
+        };
+        let mut replayed = book.clone();
+        match replayed.execute(&self.hops, amount_in) {
+            Some(amount_out) if amount_out > amount_in && amount_out >= expected => {
+                *book = replayed;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Reserves of V2 pools, updated as candidates are replayed.
+#[derive(Debug, Clone, Default)]
+pub struct ReserveBook {
+    /// `(token0, token1, reserve0, reserve1)` by pool
+    pools: HashMap<Address, (Address, Address, U256, U256)>,
+}
+
+impl ReserveBook {
+    /// Snapshot of the pending reserves of `pairs`.
+    pub fn from_pairs<'a>(pairs: impl IntoIterator<Item = &'a Arc<CryptoPair>>) -> Self {
+        let pools = pairs
+            .into_iter()
+            .map(|pair| {
+                (
+                    *pair.pair_id(),
+                    (
+                        *pair.left_id(),
+                        *pair.right_id(),
+                        pair.pending_left_reserves(),
+                        pair.pending_right_reserves(),
+                    ),
+                )
+            })
+            .collect();
+        Self { pools }
+    }
+
+    pub fn insert(&mut self, pool: Address, token0: Address, token1: Address, r0: U256, r1: U256) {
+        self.pools.insert(pool, (token0, token1, r0, r1));
+    }
+
+    /// Pools trading `token_a` against `token_b`.
+    pub fn pools_between(&self, token_a: Address, token_b: Address) -> Vec<Address> {
+        self.pools
+            .iter()
+            .filter(|(_, (token0, token1, _, _))| {
+                (*token0, *token1) == (token_a, token_b) || (*token0, *token1) == (token_b, token_a)
+            })
+            .map(|(pool, _)| *pool)
+            .collect()
+    }
+
+    /// `(reserve_in, reserve_out)` of a hop
+    pub fn reserves(&self, hop: &Hop) -> Option<(U256, U256)> {
+        let (token0, token1, r0, r1) = self.pools.get(&hop.pool)?;
+        if *token0 == hop.token_in && *token1 == hop.token_out {
+            Some((*r0, *r1))
+        } else if *token1 == hop.token_in && *token0 == hop.token_out {
+            Some((*r1, *r0))
+        } else {
+            None
+        }
+    }
+
+    /// Records a swap of `amount_in` for `amount_out` through `hop`.
+    pub fn apply(&mut self, hop: &Hop, amount_in: U256, amount_out: U256) -> Option<()> {
+        let (token0, _, r0, r1) = self.pools.get_mut(&hop.pool)?;
+        if *token0 == hop.token_in {
+            *r0 = r0.checked_add(amount_in)?;
+            *r1 = r1.checked_sub(amount_out)?;
+        } else {
+            *r1 = r1.checked_add(amount_in)?;
+            *r0 = r0.checked_sub(amount_out)?;
+        }
+        Some(())
+    }
+
+    /// Trades `amount_in` along `hops`, updating the reserves. Returns the final
+    /// amount out, or `None` (leaving the book untouched) if a hop can't execute.
+    pub fn execute(&mut self, hops: &[Hop], amount_in: U256) -> Option<U256> {
+        let mut book = self.clone();
+        let mut amount = amount_in;
+        for hop in hops {
+            let (reserve_in, reserve_out) = book.reserves(hop)?;
+            let amount_out = CryptoPair::get_amount_out(amount, reserve_in, reserve_out)?;
+            book.apply(hop, amount, amount_out)?;
+            amount = amount_out;
+        }
+        *self = book;
+        Some(amount)
+    }
+}
+
+/// Candidates packed into a single bundle.
+#[derive(Debug, Clone, Default)]
+pub struct PackedOpportunities {
+    pub candidates: Vec<Candidate>,
+    /// Candidates dropped because another one left them unable to execute
+    pub conflicts: Vec<Candidate>,
+}
+
+impl PackedOpportunities {
+    /// Outcome of the whole pack, summing every candidate's amounts and profit. The
+    /// profit is in wei, the amounts and `token_profit` only add up across candidates
+    /// starting from the same token.
+    pub fn outcome(&self) -> Option<PathOutcome> {
+        let first = self.candidates.first()?;
+        let mut pools = Vec::new();
+        for pool in self.candidates.iter().flat_map(|c| c.outcome.pools.iter()) {
+            if !pools.contains(pool) {
+                pools.push(*pool);
+            }
+        }
+        Some(PathOutcome {
+            seq_id: first.outcome.seq_id,
+            path: self
+                .candidates
+                .iter()
+                .map(|c| c.outcome.path.as_str())
+                .collect::<Vec<_>>()
+                .join(" + "),
+            pools,
+            amount_in: self.sum(|outcome| outcome.amount_in),
+            amount_out: self.sum(|outcome| outcome.amount_out),
+            profit: self.sum(|outcome| outcome.profit),
+            token_profit: self.sum(|outcome| outcome.token_profit),
+            coinbase_tip: self.sum(|outcome| outcome.coinbase_tip),
+        })
+    }
+
+    fn sum(&self, amount: impl Fn(&PathOutcome) -> U256) -> U256 {
+        self.candidates
+            .iter()
+            .fold(U256::zero(), |total, c| total.saturating_add(amount(&c.outcome)))
+    }
+
+    /// Victims of every candidate, each once.
+    pub fn victims(&self) -> Vec<Victim> {
+        let mut victims: Vec<Victim> = Vec::new();
+        for victim in self.candidates.iter().flat_map(|c| c.victims.iter()) {
+            if !victims.iter().any(|v| v.hash == victim.hash) {
+                victims.push(victim.clone());
+            }
+        }
+        victims
+    }
+
+    /// Transactions of every candidate, most profitable first.
+    pub fn txs(&self) -> Vec<TypedTransaction> {
+        self.candidates
+            .iter()
+            .flat_map(|c| c.txs.iter().cloned())
+            .collect()
+    }
+}
+
+/// Replays `candidates` most profitable first on `book`, the reserves they were built
+/// for, updated by every candidate kept. A candidate overlapping kept ones is
+/// re-simulated on the reserves they leave and kept if it still pays off, see
+/// `Candidate::replay`. The others are conflicts.
+pub fn pack(mut candidates: Vec<Candidate>, book: &ReserveBook) -> PackedOpportunities {
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.outcome.profit));
+
+    let mut running = book.clone();
+    let mut packed = PackedOpportunities::default();
+    for candidate in candidates {
+        if candidate.replay(&mut running, book) {
+            packed.candidates.push(candidate);
+        } else {
+            packed.conflicts.push(candidate);
+        }
+    }
+    packed
+}
+
+/// Collects candidates for the block being built.
+#[derive(Default)]
+pub struct OpportunityAggregator {
+    pending: Mutex<(U64, Vec<Candidate>)>,
+}
+
+impl OpportunityAggregator {
+    /// Adds a candidate for `block`. Candidates for older blocks are discarded.
+    pub fn add(&self, block: U64, candidate: Candidate) {
+        let mut pending = self.pending.lock().unwrap();
+        if block > pending.0 {
+            *pending = (block, Vec::new());
+        }
+        if block == pending.0 {
+            pending.1.push(candidate);
+        }
+    }
+
+    /// Takes the candidates collected for `block` and packs them.
+    pub fn drain(&self, block: U64, book: &ReserveBook) -> PackedOpportunities {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.0 != block {
+            return PackedOpportunities::default();
+        }
+        let candidates = std::mem::take(&mut pending.1);
+        drop(pending);
+        pack(candidates, book)
+    }
+}
+
+/// Collection window from `BACKRUNNER_AGGREGATION_MS`, `DEFAULT_WINDOW` if unset.
+pub fn window_from_env() -> Duration {
+    env::var(WINDOW_ENV)
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_WINDOW)
+}
+
+/// After each new block, waits `window` for the paths to add their candidates for the
+/// next one, then packs them and submits the pack as one bundle.
+pub async fn watch<M: Middleware>(
+    client: Arc<M>,
+    aggregator: &OpportunityAggregator,
+    window: Duration,
+) {
+    let mut blocks = match client.watch_blocks().await {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            println!("Aggregator failed to watch blocks: {}", e);
+            return;
+        }
+    };
+    while let Some(hash) = blocks.next().await {
+        let number = match client.get_block(hash).await {
+            Ok(Some(block)) => match block.number {
+                Some(number) => number,
+                None => continue,
+            },
+            Ok(None) => continue,
+            Err(e) => {
+                println!("Aggregator failed to get block {:?}: {}", hash, e);
+                continue;
+            }
+        };
+        // Price the block once for every path triggered on it
+        price_oracle::snapshot_at(number);
+        tokio::time::sleep(window).await;
+
+        let packed = aggregator.drain(number + 1, &conversion::reserve_book_at(number));
+        let outcome = match packed.outcome() {
+            Some(outcome) => outcome,
+            None => continue,
+        };
+        println!(
+            "Block {}: packed {} candidates, {} conflicts, profit {}",
+            number + 1,
+            packed.candidates.len(),
+            packed.conflicts.len(),
+            outcome.profit
+        );
+        // Submission waits out inclusion over several blocks, the next ones are
+        // packed meanwhile
+        let (victims, txs) = (packed.victims(), packed.txs());
+        arb_thread_pool::spawn(async move {
+            let result = send_flashswap_bundle(
+                &victims,
+                txs,
+                &outcome,
+                &mainnet::bid_policy,
+                &SimulationGate::default(),
+            )
+            .await;
+            match result {
+                Ok(decision) => println!("Block {} bundle: {:?}", number + 1, decision),
+                Err(e) => println!("Packed bundle could not be submitted.  Reason: {:#}", e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{eth, hop, pool, token};
+
+    /// Pools 1, 2 and 5 between tokens 1 and 2, pools 3 and 4 between tokens 1 and 3
+    fn book() -> ReserveBook {
+        let mut book = ReserveBook::default();
+        book.insert(pool(1), token(1), token(2), eth(1000), eth(2000));
+        book.insert(pool(2), token(1), token(2), eth(1000), eth(2200));
+        book.insert(pool(3), token(1), token(3), eth(1000), eth(1000));
+        book.insert(pool(4), token(1), token(3), eth(1200), eth(1000));
+        book.insert(pool(5), token(1), token(2), eth(1000), eth(1900));
+        book
+    }
+
+    fn candidate(path: &str, hops: Vec<(u8, u8, u8)>, amount_in: U256, profit: U256) -> Candidate {
+        let hops: Vec<Hop> = hops
+            .into_iter()
+            .map(|(p, a, b)| hop(p, a, b))
+            .collect();
+        let mut book = book();
+        let amount_out = book.execute(&hops, amount_in).unwrap();
+        Candidate {
+            outcome: PathOutcome {
+                seq_id: 0,
+                path: path.to_string(),
+                pools: hops.iter().map(|hop| hop.pool).collect(),
+                amount_in,
+                amount_out,
+                profit,
+                token_profit: profit,
+                coinbase_tip: U256::zero(),
+            },
+            hops,
+            txs: vec![],
+            victims: vec![],
+        }
+    }
+
+    #[test]
+    fn test_execute_updates_reserves() {
+        let mut book = book();
+        let hop = Hop {
+            pool: pool(1),
+            token_in: token(1),
+            token_out: token(2),
+        };
+        let out = book.execute(&[hop.clone()], eth(10)).unwrap();
+        assert_eq!(book.reserves(&hop), Some((eth(1010), eth(2000) - out)));
+
+        let unknown = Hop {
+            pool: pool(9),
+            ..hop.clone()
+        };
+        assert!(book.execute(&[hop.clone(), unknown], eth(10)).is_none());
+        assert_eq!(book.reserves(&hop), Some((eth(1010), eth(2000) - out)));
+    }
+
+    #[test]
+    fn test_pack_drops_conflicts() {
+        // Both buy token 2 on pool 2 and sell it on pool 1
+        let big = candidate("big", vec![(2, 1, 2), (1, 2, 1)], eth(20), eth(3));
+        let small = candidate("small", vec![(2, 1, 2), (1, 2, 1)], eth(10), eth(1));
+        // Shares nothing with the others
+        let other = candidate("other", vec![(3, 1, 3), (4, 3, 1)], eth(1), eth(2));
+        // Doesn't return its input
+        let losing = candidate("losing", vec![(1, 1, 2), (2, 2, 1)], eth(1), eth(4));
+
+        let packed = pack(vec![small, other, losing, big], &book());
+        let paths: Vec<&str> = packed
+            .candidates
+            .iter()
+            .map(|c| c.outcome.path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["big", "other"]);
+        let conflicts: Vec<&str> = packed
+            .conflicts
+            .iter()
+            .map(|c| c.outcome.path.as_str())
+            .collect();
+        assert_eq!(conflicts, vec!["losing", "small"]);
+
+        let outcome = packed.outcome().unwrap();
+        assert_eq!(outcome.amount_in, eth(21));
+        assert_eq!(outcome.profit, eth(5));
+    }
+
+    #[test]
+    fn test_pack_keeps_overlaps_still_profitable() {
+        let big = candidate("big", vec![(2, 1, 2), (1, 2, 1)], eth(20), eth(3));
+        let small = candidate("small", vec![(2, 1, 2), (1, 2, 1)], eth(10), eth(1));
+        // Buys token 2 on pool 1, which `big` leaves cheaper, and sells it on pool 5
+        let reverse = candidate("reverse", vec![(1, 1, 2), (5, 2, 1)], eth(1), eth(2));
+
+        let packed = pack(vec![small, reverse, big], &book());
+        let paths: Vec<&str> = packed
+            .candidates
+            .iter()
+            .map(|c| c.outcome.path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["big", "reverse"]);
+        assert_eq!(packed.conflicts.len(), 1);
+        assert_eq!(packed.conflicts[0].outcome.path, "small");
+
+        let outcome = packed.outcome().unwrap();
+        assert_eq!(outcome.amount_in, eth(21));
+        assert_eq!(outcome.profit, eth(5));
+    }
+
+    #[test]
+    fn test_aggregator_resets_per_block() {
+        let aggregator = OpportunityAggregator::default();
+        let c = candidate("a", vec![(3, 1, 3), (4, 3, 1)], eth(1), eth(1));
+        aggregator.add(U64::from(1), c.clone());
+        aggregator.add(U64::from(2), c.clone());
+        aggregator.add(U64::from(1), c);
+
+        assert_eq!(aggregator.drain(U64::from(1), &book()).candidates.len(), 0);
+        assert_eq!(aggregator.drain(U64::from(2), &book()).candidates.len(), 1);
+    }
+}
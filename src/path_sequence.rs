@@ -26,3 +26,25 @@ pub trait PathSequence {
     fn a2(&self) -> &SequenceToken;
     fn b2(&self) -> &SequenceToken;
 }
+
+/// Expected result of trading along a path, estimated from pending reserves before
+/// any transaction is built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathOutcome {
+    pub seq_id: u8,
+    pub path: String,
+    /// Pools traded on, in order
+    pub pools: Vec<Address>,
+    /// Amount borrowed for the first trade
+    pub amount_in: U256,
+    /// Amount received from the last trade
+    pub amount_out: U256,
+    /// Expected profit in wei, valued in ETH and net of gas: what the tip is a share of
+    pub profit: U256,
+    /// Expected profit in units of the input token, which is what the executor's
+    /// `execute` returns when called directly
+    pub token_profit: U256,
+    /// Part of the tip our transactions transfer to `block.coinbase`, the rest is paid
+    /// as priority fee
+    pub coinbase_tip: U256,
+}
@@ -0,0 +1,719 @@
+//! Reference prices of arbitrary tokens in ETH and USD.
+//!
+//! Opportunities are sized and reported in whichever token their path starts with,
+//! so amounts of different paths can't be compared. The oracle prices any token of
+//! our pool graph against WETH through the pools we already follow: directly where it
+//! trades against WETH, otherwise through a stablecoin hub. Each pool contributes in
+//! proportion to the ETH it holds, and the confidence of a price grows with that depth
+//! and with how closely the pools agree. USD prices take the stablecoins at par
+//! against the WETH they trade for.
+//!
+//! Spot reserves can be pushed around within a block. When enabled, the WETH and
+//! stablecoin pools are also sampled every block for Uniswap V2's cumulative price,
+//! and their time-weighted average price replaces their spot price.
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::sync::{Arc, RwLock};
+
+use ethers::prelude::*;
+use once_cell::sync::Lazy;
+
+use crate::contracts::bindings::uniswap_v2_pair::UniswapV2Pair;
+use crate::conversion::{self, BlockCache, CONVERSION_PAIRS};
+use crate::crypto_pair::CryptoPair;
+use crate::dex_pool::PoolKind;
+use crate::utils::conversions::u256_to_float;
+
+/// Env var capping the USD value of a trade's input.
+pub const MAX_NOTIONAL_USD_ENV: &str = "BACKRUNNER_MAX_NOTIONAL_USD";
+/// Env var setting the least confidence a trade's valuation needs, in `0..=1`.
+pub const MIN_CONFIDENCE_ENV: &str = "BACKRUNNER_MIN_PRICE_CONFIDENCE";
+/// Env var enabling TWAP smoothing over the given number of seconds.
+pub const TWAP_PERIOD_ENV: &str = "BACKRUNNER_TWAP_SECS";
+/// Env var setting the least ETH a path's arbitrage index must promise to be optimised.
+pub const MIN_TRIGGER_ETH_ENV: &str = "BACKRUNNER_MIN_TRIGGER_ETH";
+/// Env var setting the least USD a path's arbitrage index must promise to be optimised.
+pub const MIN_TRIGGER_USD_ENV: &str = "BACKRUNNER_MIN_TRIGGER_USD";
+
+/// ETH a token's pools must hold between them for full confidence in its price
+const REFERENCE_DEPTH_ETH: f64 = 100.0;
+/// Uniswap V2 cumulative prices are UQ112x112 fixed point
+const Q112: f64 = 5_192_296_858_534_827_628_530_496_329_220_096.0;
+
+/// Stablecoins taken at one dollar: USDC, USDT and DAI.
+pub static USD_TOKENS: Lazy<Vec<Address>> = Lazy::new(|| {
+    [
+        "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+        "0xdAC17F958D2ee523a2206206994597C13D831ec7",
+        "0x6B175474E89094C44Da98b954EedA27d02cC65a1",
+    ]
+    .iter()
+    .map(|token| token.parse().unwrap())
+    .collect()
+});
+
+/// Cumulative price samples, fed by `watch_twaps`.
+pub static TWAPS: Lazy<RwLock<TwapBook>> = Lazy::new(|| RwLock::new(TwapBook::new(0)));
+
+/// A price or a value, and how far it can be trusted, from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub value: f64,
+    pub confidence: f64,
+}
+
+/// A pool as the oracle sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolQuote {
+    pub pool: Address,
+    pub token0: Address,
+    pub token1: Address,
+    pub decimals0: i32,
+    pub decimals1: i32,
+    pub reserve0: U256,
+    pub reserve1: U256,
+}
+
+impl PoolQuote {
+    /// Pending reserves of `pair`.
+    pub fn from_pair(pair: &CryptoPair) -> Self {
+        Self {
+            pool: *pair.pair_id(),
+            token0: *pair.left_id(),
+            token1: *pair.right_id(),
+            decimals0: pair.left_decimal(),
+            decimals1: pair.right_decimal(),
+            reserve0: pair.pending_left_reserves(),
+            reserve1: pair.pending_right_reserves(),
+        }
+    }
+
+    fn trades(&self, a: Address, b: Address) -> bool {
+        (self.token0, self.token1) == (a, b) || (self.token0, self.token1) == (b, a)
+    }
+
+    /// Whole units of the other token per whole unit of `base`, and the whole units of
+    /// the other token the pool holds. `raw_price0` overrides the reserve ratio as the
+    /// price of token0 in base units of token1.
+    fn spot(&self, base: Address, raw_price0: Option<f64>) -> Option<(f64, f64)> {
+        let reserve0 = u256_to_float(&self.reserve0)?;
+        let reserve1 = u256_to_float(&self.reserve1)?;
+        if reserve0 <= 0.0 || reserve1 <= 0.0 {
+            return None;
+        }
+        let raw_price0 = raw_price0.unwrap_or(reserve1 / reserve0);
+        let price0 = raw_price0 * 10f64.powi(self.decimals0 - self.decimals1);
+        if base == self.token0 {
+            Some((price0, reserve1 / 10f64.powi(self.decimals1)))
+        } else if base == self.token1 && price0 > 0.0 {
+            Some((1.0 / price0, reserve0 / 10f64.powi(self.decimals0)))
+        } else {
+            None
+        }
+    }
+}
+
+/// Liquidity-weighted mean of `(price, depth in ETH, confidence)` samples. The
+/// confidence is that of the samples scaled by the depth behind them and by how
+/// closely they agree.
+fn aggregate(samples: &[(f64, f64, f64)]) -> Option<Estimate> {
+    let total_depth: f64 = samples.iter().map(|(_, depth, _)| depth).sum();
+    if total_depth <= 0.0 {
+        return None;
+    }
+    let value = samples
+        .iter()
+        .map(|(price, depth, _)| price * depth)
+        .sum::<f64>()
+        / total_depth;
+    if value <= 0.0 || !value.is_finite() {
+        return None;
+    }
+    let dispersion = samples
+        .iter()
+        .map(|(price, depth, _)| ((price - value) / value).abs() * depth)
+        .sum::<f64>()
+        / total_depth;
+    let confidence = samples
+        .iter()
+        .map(|(_, depth, confidence)| confidence * depth)
+        .sum::<f64>()
+        / total_depth
+        * (total_depth / (total_depth + REFERENCE_DEPTH_ETH))
+        * (1.0 - dispersion).max(0.0);
+    Some(Estimate { value, confidence })
+}
+
+/// Prices of the tokens of a set of pools.
+#[derive(Debug, Clone)]
+pub struct PriceOracle {
+    pub weth: Address,
+    pub usd_tokens: Vec<Address>,
+    pools: Vec<PoolQuote>,
+    /// Smoothed price of token0 in base units of token1, by pool
+    twaps: HashMap<Address, f64>,
+}
+
+impl PriceOracle {
+    pub fn new(weth: Address, usd_tokens: Vec<Address>, pools: Vec<PoolQuote>) -> Self {
+        Self {
+            weth,
+            usd_tokens,
+            pools,
+            twaps: HashMap::new(),
+        }
+    }
+
+    pub fn from_pairs<'a>(
+        weth: Address,
+        usd_tokens: Vec<Address>,
+        pairs: impl IntoIterator<Item = &'a Arc<CryptoPair>>,
+    ) -> Self {
+        let pools = pairs
+            .into_iter()
+            .map(|pair| PoolQuote::from_pair(pair))
+            .collect();
+        Self::new(weth, usd_tokens, pools)
+    }
+
+    /// Prices pools with a TWAP in `twaps` at their TWAP instead of their reserves.
+    pub fn with_twaps(mut self, twaps: &TwapBook) -> Self {
+        self.twaps = self
+            .pools
+            .iter()
+            .filter_map(|pool| Some((pool.pool, twaps.twap0(pool.pool)?)))
+            .collect();
+        self
+    }
+
+    pub fn decimals(&self, token: Address) -> Option<i32> {
+        self.pools.iter().find_map(|pool| {
+            if pool.token0 == token {
+                Some(pool.decimals0)
+            } else if pool.token1 == token {
+                Some(pool.decimals1)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// `(price of base in quote, quote held)` of each pool trading the two.
+    fn spots(&self, base: Address, quote: Address) -> Vec<(f64, f64)> {
+        self.pools
+            .iter()
+            .filter(|pool| pool.trades(base, quote))
+            .filter_map(|pool| pool.spot(base, self.twaps.get(&pool.pool).copied()))
+            .collect()
+    }
+
+    /// Price of `token` in ETH, through its WETH pools or, without any, through the
+    /// stablecoins' WETH price.
+    pub fn eth_price(&self, token: Address) -> Option<Estimate> {
+        if token == self.weth {
+            return Some(Estimate {
+                value: 1.0,
+                confidence: 1.0,
+            });
+        }
+        let mut samples = self.weth_samples(token);
+        if samples.is_empty() {
+            for hub in self.usd_tokens.iter().filter(|hub| **hub != token) {
+                let hub_price = match aggregate(&self.weth_samples(*hub)) {
+                    Some(hub_price) => hub_price,
+                    None => continue,
+                };
+                samples.extend(
+                    self.spots(token, *hub)
+                        .into_iter()
+                        .map(|(price, hub_depth)| {
+                            (
+                                price * hub_price.value,
+                                hub_depth * hub_price.value,
+                                hub_price.confidence,
+                            )
+                        }),
+                );
+            }
+        }
+        aggregate(&samples)
+    }
+
+    fn weth_samples(&self, token: Address) -> Vec<(f64, f64, f64)> {
+        self.spots(token, self.weth)
+            .into_iter()
+            .map(|(price, weth_depth)| (price, weth_depth, 1.0))
+            .collect()
+    }
+
+    /// Dollars per ETH, the stablecoins at par.
+    pub fn eth_usd(&self) -> Option<Estimate> {
+        let samples: Vec<(f64, f64, f64)> = self
+            .usd_tokens
+            .iter()
+            .flat_map(|usd| {
+                self.spots(self.weth, *usd)
+                    .into_iter()
+                    .map(|(price, usd_depth)| (price, usd_depth / price, 1.0))
+            })
+            .collect();
+        aggregate(&samples)
+    }
+
+    pub fn usd_price(&self, token: Address) -> Option<Estimate> {
+        if self.usd_tokens.contains(&token) {
+            return Some(Estimate {
+                value: 1.0,
+                confidence: 1.0,
+            });
+        }
+        let (eth_price, eth_usd) = (self.eth_price(token)?, self.eth_usd()?);
+        Some(Estimate {
+            value: eth_price.value * eth_usd.value,
+            confidence: eth_price.confidence * eth_usd.confidence,
+        })
+    }
+
+    /// ETH `amount` base units of `token` are worth.
+    pub fn eth_value(&self, token: Address, amount: U256) -> Option<Estimate> {
+        self.value(token, amount, self.eth_price(token)?)
+    }
+
+    /// `eth_value` in wei, exact for WETH itself.
+    pub fn eth_value_wei(&self, token: Address, amount: U256) -> Option<U256> {
+        if token == self.weth {
+            return Some(amount);
+        }
+        let value = self.eth_value(token, amount)?.value;
+        Some(U256::from((value * 1e18) as u128))
+    }
+
+    /// Dollars `amount` base units of `token` are worth.
+    pub fn usd_value(&self, token: Address, amount: U256) -> Option<Estimate> {
+        self.value(token, amount, self.usd_price(token)?)
+    }
+
+    fn value(&self, token: Address, amount: U256, price: Estimate) -> Option<Estimate> {
+        let decimals = if token == self.weth {
+            18
+        } else {
+            self.decimals(token)?
+        };
+        Some(Estimate {
+            value: u256_to_float(&amount)? / 10f64.powi(decimals) * price.value,
+            confidence: price.confidence,
+        })
+    }
+}
+
+/// Oracle over the pairs registered for conversions, smoothed by `TWAPS`.
+pub fn snapshot() -> PriceOracle {
+    PriceOracle::from_pairs(
+        *crate::uniswap_providers::mainnet::weth,
+        USD_TOKENS.clone(),
+        CONVERSION_PAIRS.read().unwrap().iter(),
+    )
+    .with_twaps(&TWAPS.read().unwrap())
+}
+
+static SNAPSHOTS: Lazy<BlockCache<PriceOracle>> = Lazy::new(Default::default);
+
+/// `snapshot` as of `block`, built once per block.
+pub fn snapshot_at(block: U64) -> Arc<PriceOracle> {
+    SNAPSHOTS.at(block, snapshot)
+}
+
+/// The snapshot of the latest block priced, for callers which don't follow blocks.
+pub fn latest_snapshot() -> Arc<PriceOracle> {
+    SNAPSHOTS.latest(snapshot)
+}
+
+/// Least value a path's arbitrage index must promise before the path is optimised.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriggerThreshold {
+    pub min_eth: f64,
+    /// Also required in USD, if set
+    pub min_usd: Option<f64>,
+}
+
+impl Default for TriggerThreshold {
+    fn default() -> Self {
+        Self {
+            min_eth: 0.01,
+            min_usd: None,
+        }
+    }
+}
+
+impl TriggerThreshold {
+    /// Reads `BACKRUNNER_MIN_TRIGGER_ETH` and `BACKRUNNER_MIN_TRIGGER_USD`.
+    pub fn from_env() -> Self {
+        let var = |name| env::var(name).ok().and_then(|v| v.parse::<f64>().ok());
+        Self {
+            min_eth: var(MIN_TRIGGER_ETH_ENV).unwrap_or(Self::default().min_eth),
+            min_usd: var(MIN_TRIGGER_USD_ENV),
+        }
+    }
+
+    /// Whether a cycle with arbitrage index `index`, starting from a pool holding
+    /// `reserve` of `token`, may be worth optimising. It can't gain more than
+    /// `(index - 1) * reserve` of `token`; a token the oracle can't price never passes.
+    pub fn allows(&self, oracle: &PriceOracle, token: Address, reserve: U256, index: f64) -> bool {
+        if index <= 1.0 {
+            return false;
+        }
+        let eth = match oracle.eth_value(token, reserve) {
+            Some(value) => value.value * (index - 1.0),
+            None => return false,
+        };
+        let usd = || Some(oracle.usd_value(token, reserve)?.value * (index - 1.0));
+        eth >= self.min_eth
+            && self
+                .min_usd
+                .is_none_or(|min_usd| usd().is_some_and(|usd| usd >= min_usd))
+    }
+}
+
+/// Bounds on the trades sent, valued by the oracle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiskLimits {
+    /// Largest input in USD, no limit if `None`
+    pub max_notional_usd: Option<f64>,
+    /// Least confidence of the input's valuation
+    pub min_confidence: f64,
+}
+
+impl Default for RiskLimits {
+    fn default() -> Self {
+        Self {
+            max_notional_usd: None,
+            min_confidence: 0.0,
+        }
+    }
+}
+
+impl RiskLimits {
+    /// Reads `BACKRUNNER_MAX_NOTIONAL_USD` and `BACKRUNNER_MIN_PRICE_CONFIDENCE`.
+    pub fn from_env() -> Self {
+        let var = |name| env::var(name).ok().and_then(|v| v.parse::<f64>().ok());
+        Self {
+            max_notional_usd: var(MAX_NOTIONAL_USD_ENV),
+            min_confidence: var(MIN_CONFIDENCE_ENV).unwrap_or(0.0).clamp(0.0, 1.0),
+        }
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.max_notional_usd.is_none() && self.min_confidence <= 0.0
+    }
+
+    /// Whether a trade whose input is worth `notional` dollars may be sent. A trade
+    /// that can't be valued only passes without limits.
+    pub fn allows(&self, notional: Option<Estimate>) -> bool {
+        match notional {
+            Some(notional) => {
+                notional.confidence >= self.min_confidence
+                    && self
+                        .max_notional_usd
+                        .is_none_or(|max| notional.value <= max)
+            }
+            None => self.is_unlimited(),
+        }
+    }
+}
+
+/// A pool's cumulative price of token0 at a point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TwapObservation {
+    pub price0_cumulative: U256,
+    pub timestamp: u32,
+}
+
+/// `price0CumulativeLast` brought forward to `now` at the current reserves, as the
+/// pair would on its next update. Cumulatives wrap by design.
+pub fn cumulative_at(
+    price0_cumulative_last: U256,
+    reserve0: u128,
+    reserve1: u128,
+    timestamp_last: u32,
+    now: u32,
+) -> U256 {
+    let elapsed = now.wrapping_sub(timestamp_last);
+    if elapsed == 0 || reserve0 == 0 {
+        return price0_cumulative_last;
+    }
+    let price0 = (U256::from(reserve1) << 112) / U256::from(reserve0);
+    price0_cumulative_last
+        .overflowing_add(price0.overflowing_mul(U256::from(elapsed)).0)
+        .0
+}
+
+/// Cumulative price samples per pool, kept over a sliding period.
+#[derive(Debug, Clone, Default)]
+pub struct TwapBook {
+    /// Seconds the average spans
+    pub period: u32,
+    observations: HashMap<Address, VecDeque<TwapObservation>>,
+}
+
+impl TwapBook {
+    pub fn new(period: u32) -> Self {
+        Self {
+            period,
+            observations: HashMap::new(),
+        }
+    }
+
+    /// Records `observation`, dropping samples older than needed to span the period.
+    pub fn observe(&mut self, pool: Address, observation: TwapObservation) {
+        let samples = self.observations.entry(pool).or_default();
+        if matches!(samples.back(), Some(last) if last.timestamp >= observation.timestamp) {
+            return;
+        }
+        samples.push_back(observation);
+        while samples.len() > 2 && observation.timestamp - samples[1].timestamp >= self.period {
+            samples.pop_front();
+        }
+    }
+
+    /// Average price of token0 in base units of token1 between the oldest and newest
+    /// samples of `pool`, once they span the period.
+    pub fn twap0(&self, pool: Address) -> Option<f64> {
+        let samples = self.observations.get(&pool)?;
+        let (first, last) = (samples.front()?, samples.back()?);
+        let elapsed = last.timestamp - first.timestamp;
+        if elapsed == 0 || elapsed < self.period {
+            return None;
+        }
+        let growth = last
+            .price0_cumulative
+            .overflowing_sub(first.price0_cumulative)
+            .0;
+        Some(u256_to_float(&(growth / U256::from(elapsed)))? / Q112)
+    }
+}
+
+/// Reads `BACKRUNNER_TWAP_SECS`, `None` when TWAP smoothing is off. A period of 0
+/// turns it off too: it would keep only the last two samples, a one-block average.
+pub fn twap_period_from_env() -> Option<u32> {
+    env::var(TWAP_PERIOD_ENV)
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|period| *period > 0)
+}
+
+/// Samples the cumulative price of a V2 `pool` at the time of the latest block.
+pub async fn sample<M: Middleware + 'static>(
+    client: Arc<M>,
+    pool: Address,
+) -> Result<TwapObservation, anyhow::Error> {
+    let pair = UniswapV2Pair::new(pool, client.clone());
+    let cumulative = pair.price_0_cumulative_last().call().await?;
+    let (reserve0, reserve1, timestamp_last) = pair.reserves().call().await?;
+    let block = client
+        .get_block(BlockNumber::Latest)
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", e))?
+        .ok_or_else(|| anyhow::anyhow!("no latest block"))?;
+    let now = block.timestamp.low_u32();
+    Ok(TwapObservation {
+        price0_cumulative: cumulative_at(cumulative, reserve0, reserve1, timestamp_last, now),
+        timestamp: now,
+    })
+}
+
+/// Samples every block, into `twaps`, the V2 pools trading two hub tokens: the
+/// reference pools of ETH and USD prices.
+pub async fn watch_twaps<M: Middleware + 'static>(client: Arc<M>, twaps: &RwLock<TwapBook>) {
+    let pools: Vec<Address> = CONVERSION_PAIRS
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|pair| pair.kind() == PoolKind::V2)
+        .filter(|pair| {
+            conversion::HUB_TOKENS.contains(pair.left_id())
+                && conversion::HUB_TOKENS.contains(pair.right_id())
+        })
+        .map(|pair| *pair.pair_id())
+        .collect();
+    let mut blocks = match client.watch_blocks().await {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            println!("Price oracle failed to watch blocks: {}", e);
+            return;
+        }
+    };
+    while blocks.next().await.is_some() {
+        for pool in &pools {
+            match sample(client.clone(), *pool).await {
+                Ok(observation) => twaps.write().unwrap().observe(*pool, observation),
+                Err(e) => println!("Price oracle failed to sample {:?}: {}", pool, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn units(amount: u64, decimals: usize) -> U256 {
+        U256::from(amount) * U256::exp10(decimals)
+    }
+
+    fn pool(
+        n: u8,
+        token0: Address,
+        token1: Address,
+        reserves: (U256, U256),
+        decimals: (i32, i32),
+    ) -> PoolQuote {
+        PoolQuote {
+            pool: Address::repeat_byte(0xa0 + n),
+            token0,
+            token1,
+            decimals0: decimals.0,
+            decimals1: decimals.1,
+            reserve0: reserves.0,
+            reserve1: reserves.1,
+        }
+    }
+
+    #[test]
+    fn test_prices_through_deepest_pools() {
+        let (weth, usdc, dai) = (
+            Address::repeat_byte(0xee),
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+        );
+        let (token, orphan) = (Address::repeat_byte(3), Address::repeat_byte(4));
+        let oracle = PriceOracle::new(
+            weth,
+            vec![usdc, dai],
+            vec![
+                // 2000 USDC (6 decimals) per ETH, deep
+                pool(
+                    1,
+                    usdc,
+                    weth,
+                    (units(20_000_000, 6), units(10_000, 18)),
+                    (6, 18),
+                ),
+                // 2200 DAI per ETH, shallow
+                pool(2, weth, dai, (units(10, 18), units(22_000, 18)), (18, 18)),
+                // 0.01 ETH per token, on two pools
+                pool(
+                    3,
+                    token,
+                    weth,
+                    (units(100_000, 18), units(1_000, 18)),
+                    (18, 18),
+                ),
+                pool(4, weth, token, (units(1, 18), units(90, 18)), (18, 18)),
+                // Only trades against USDC, at 5 USDC
+                pool(
+                    5,
+                    orphan,
+                    usdc,
+                    (units(1_000, 18), units(5_000, 6)),
+                    (18, 6),
+                ),
+            ],
+        );
+
+        let eth_usd = oracle.eth_usd().unwrap();
+        assert!(
+            (eth_usd.value - 2000.2).abs() < 0.1,
+            "eth_usd {:?}",
+            eth_usd
+        );
+
+        // The deep pool outweighs the shallow one
+        let price = oracle.eth_price(token).unwrap();
+        assert!((price.value - 0.01).abs() < 0.0001, "price {:?}", price);
+        assert!(price.confidence > 0.85 && price.confidence < 1.0);
+
+        let orphan_price = oracle.eth_price(orphan).unwrap();
+        assert!((orphan_price.value - 0.0025).abs() < 0.0001);
+        // 5000 USDC backs it: a few ETH of depth
+        assert!(orphan_price.confidence < 0.05);
+
+        // 300 tokens are 3 ETH, valued through both pools
+        let wei = oracle.eth_value_wei(token, units(300, 18)).unwrap();
+        assert!(wei > units(2_999, 15) && wei < units(3_001, 15), "wei {}", wei);
+        assert_eq!(oracle.eth_value_wei(weth, units(3, 18)), Some(units(3, 18)));
+        assert!(oracle.eth_value_wei(Address::repeat_byte(9), units(1, 18)).is_none());
+
+        let value = oracle.usd_value(token, units(50, 18)).unwrap();
+        assert!((value.value - 1000.0).abs() < 1.0, "value {:?}", value);
+        assert_eq!(oracle.usd_value(usdc, units(5, 6)).unwrap().value, 5.0);
+        assert!(oracle.eth_price(Address::repeat_byte(9)).is_none());
+
+        let limits = RiskLimits {
+            max_notional_usd: Some(500.0),
+            min_confidence: 0.5,
+        };
+        assert!(!limits.allows(Some(value)));
+        assert!(limits.allows(oracle.usd_value(token, units(20, 18))));
+        assert!(!limits.allows(oracle.usd_value(orphan, units(1, 18))));
+        assert!(!limits.allows(None));
+        assert!(RiskLimits::default().allows(None));
+
+        // 100k tokens are 1000 ETH, 2M USD: 1% is at most 10 ETH, 20k USD
+        let reserve = units(100_000, 18);
+        let trigger = TriggerThreshold {
+            min_eth: 5.0,
+            min_usd: Some(25_000.0),
+        };
+        assert!(!trigger.allows(&oracle, token, reserve, 1.01));
+        assert!(trigger.allows(&oracle, token, reserve, 1.02));
+        assert!(!trigger.allows(&oracle, token, reserve, 0.9));
+        assert!(!trigger.allows(&oracle, Address::repeat_byte(9), reserve, 2.0));
+    }
+
+    #[test]
+    fn test_twap() {
+        let pool = Address::repeat_byte(0xa1);
+        let mut twaps = TwapBook::new(60);
+        // Token0 priced at 2, then 4 base units of token1
+        let c0 = cumulative_at(U256::zero(), 1_000, 2_000, 0, 30);
+        let c1 = cumulative_at(c0, 1_000, 4_000, 30, 60);
+        twaps.observe(
+            pool,
+            TwapObservation {
+                price0_cumulative: U256::zero(),
+                timestamp: 0,
+            },
+        );
+        twaps.observe(
+            pool,
+            TwapObservation {
+                price0_cumulative: c0,
+                timestamp: 30,
+            },
+        );
+        assert_eq!(twaps.twap0(pool), None);
+        twaps.observe(
+            pool,
+            TwapObservation {
+                price0_cumulative: c1,
+                timestamp: 60,
+            },
+        );
+        assert!((twaps.twap0(pool).unwrap() - 3.0).abs() < 1e-9);
+
+        let quote = PoolQuote {
+            pool,
+            token0: Address::repeat_byte(3),
+            token1: Address::repeat_byte(0xee),
+            decimals0: 18,
+            decimals1: 18,
+            reserve0: units(1_000, 18),
+            reserve1: units(4_000, 18),
+        };
+        let oracle = PriceOracle::new(Address::repeat_byte(0xee), vec![], vec![quote]);
+        assert!((oracle.eth_price(Address::repeat_byte(3)).unwrap().value - 4.0).abs() < 1e-9);
+        let smoothed = oracle.with_twaps(&twaps);
+        assert!((smoothed.eth_price(Address::repeat_byte(3)).unwrap().value - 3.0).abs() < 1e-9);
+    }
+}
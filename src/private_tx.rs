@@ -0,0 +1,158 @@
+//! Private transaction submission through a relay's `eth_sendPrivateTransaction`.
+//!
+//! A lone arbitrage which doesn't backrun anything doesn't need a bundle: the relay
+//! keeps the transaction out of the public mempool and tries it in every block up to
+//! `maxBlockNumber`, here the block the opportunity was priced for.
+use ethers::core::types::transaction::eip2718::TypedTransaction;
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::backrun::Victim;
+use crate::bundle_submitter::RelayEndpoint;
+use crate::signers::SearcherSigner;
+
+/// Blocks a private transaction is retried for: only the block it was priced for,
+/// so its nonce isn't held past the opportunity.
+pub const DEFAULT_MAX_BLOCKS: u64 = 1;
+
+/// How an opportunity is sent to the relays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmissionMode {
+    Bundle,
+    PrivateTransaction,
+}
+
+impl SubmissionMode {
+    /// Private transactions only for a single transaction of ours. Anything backrunning
+    /// `victims` or needing several transactions in order has to be a bundle.
+    pub fn choose(victims: &[Victim], txs: &[TypedTransaction]) -> Self {
+        if victims.is_empty() && txs.len() == 1 {
+            SubmissionMode::PrivateTransaction
+        } else {
+            SubmissionMode::Bundle
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse<R> {
+    result: Option<R>,
+    error: Option<Value>,
+}
+
+pub struct PrivateTxClient {
+    http: reqwest::Client,
+    relay: RelayEndpoint,
+    signer: SearcherSigner,
+    pub max_blocks: u64,
+}
+
+impl PrivateTxClient {
+    /// `signer` is the searcher identity also used to sign bundles.
+    pub fn new(relay: RelayEndpoint, signer: SearcherSigner) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            relay,
+            signer,
+            max_blocks: DEFAULT_MAX_BLOCKS,
+        }
+    }
+
+    async fn request<T: Serialize, R: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: T,
+    ) -> Result<R, anyhow::Error> {
+        let body = serde_json::to_string(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        }))?;
+        let digest = format!("0x{:x}", H256::from(keccak256(body.as_bytes())));
+        let signature = self.signer.sign_message(digest).await?;
+
+        let response: RpcResponse<R> = self
+            .http
+            .post(self.relay.url.clone())
+            .header("Content-Type", "application/json")
+            .header(
+                "X-Flashbots-Signature",
+                format!("{:?}:0x{}", self.signer.address(), signature),
+            )
+            .body(body)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        match (response.result, response.error) {
+            (Some(result), _) => Ok(result),
+            (None, Some(error)) => Err(anyhow::anyhow!(
+                "{} {} failed: {}",
+                self.relay.name,
+                method,
+                error
+            )),
+            (None, None) => Err(anyhow::anyhow!("{} {} returned nothing", self.relay.name, method)),
+        }
+    }
+
+    /// Sends a signed transaction, retried until it lands or `max_block_number` passes.
+    pub async fn send_private_transaction(
+        &self,
+        raw: Bytes,
+        max_block_number: U64,
+    ) -> Result<H256, anyhow::Error> {
+        self.request(
+            "eth_sendPrivateTransaction",
+            [json!({
+                "tx": raw,
+                "maxBlockNumber": max_block_number,
+            })],
+        )
+        .await
+    }
+
+    /// Stops the relay from retrying a private transaction. Returns false if it was
+    /// already included or unknown to the relay.
+    pub async fn cancel_private_transaction(&self, tx_hash: H256) -> Result<bool, anyhow::Error> {
+        self.request("eth_cancelPrivateTransaction", [json!({ "txHash": tx_hash })])
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_mode() {
+        let tx: TypedTransaction = TransactionRequest::new().into();
+        let victim = Victim {
+            hash: H256::zero(),
+            raw: Bytes::default(),
+            from: Address::zero(),
+            nonce: U256::zero(),
+            gas_price: None,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            pools: vec![],
+        };
+
+        assert_eq!(
+            SubmissionMode::choose(&[], &[tx.clone()]),
+            SubmissionMode::PrivateTransaction
+        );
+        assert_eq!(
+            SubmissionMode::choose(&[victim], &[tx.clone()]),
+            SubmissionMode::Bundle
+        );
+        assert_eq!(
+            SubmissionMode::choose(&[], &[tx.clone(), tx]),
+            SubmissionMode::Bundle
+        );
+    }
+}
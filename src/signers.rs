@@ -0,0 +1,451 @@
+//! Signer backends for the transaction wallet and the Flashbots reputation key.
+//!
+//! Each signer is configured from the environment under its own prefix, so the
+//! account holding funds and the identity relays rank us by are kept apart:
+//!
+//! - `<PREFIX>_KEYSTORE` and `<PREFIX>_KEYSTORE_PASSWORD`: encrypted JSON keystore
+//! - `<PREFIX>_PRIVATE_KEY`: hex private key
+//! - `<PREFIX>_REMOTE_SIGNER_URL` and `<PREFIX>_ADDRESS`: remote signer, see `RemoteSigner`
+//!
+//! Keys and passwords are never printed: `Debug` of every type here redacts them and
+//! errors only name the variable or file which failed.
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use ethers::core::types::transaction::eip2718::TypedTransaction;
+use ethers::core::types::transaction::eip712::Eip712;
+use ethers::prelude::*;
+use ethers::utils::hash_message;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use url::Url;
+
+/// EIP-155 `v` of a signature with `recovery_id` on `chain_id`.
+fn to_eip155_v(recovery_id: u8, chain_id: u64) -> u64 {
+    u64::from(recovery_id) + 35 + chain_id * 2
+}
+
+/// Environment prefix of the account sending our transactions
+pub const WALLET_ENV: &str = "BACKRUNNER_WALLET";
+/// Environment prefix of the key authenticating us with the relays
+pub const FLASHBOTS_ENV: &str = "BACKRUNNER_FLASHBOTS";
+/// Environment prefix of the Kovan test account
+pub const KOVAN_WALLET_ENV: &str = "BACKRUNNER_KOVAN_WALLET";
+
+#[derive(Debug)]
+pub enum SignerError {
+    /// A variable is missing or invalid, named without its value
+    Config(String),
+    Wallet(WalletError),
+    Remote(String),
+    Eip712(String),
+}
+
+impl fmt::Display for SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignerError::Config(message) => write!(f, "signer configuration: {}", message),
+            SignerError::Wallet(e) => write!(f, "local signer: {}", e),
+            SignerError::Remote(message) => write!(f, "remote signer: {}", message),
+            SignerError::Eip712(message) => write!(f, "eip712 encoding: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SignerError {}
+
+impl From<WalletError> for SignerError {
+    fn from(e: WalletError) -> Self {
+        SignerError::Wallet(e)
+    }
+}
+
+/// Where a signer's key lives.
+#[derive(Clone)]
+pub enum SignerConfig {
+    Keystore { path: PathBuf, password: String },
+    PrivateKey(String),
+    Remote { url: Url, address: Address },
+    /// Fresh key for this run only, enough for a Flashbots identity
+    Random,
+}
+
+impl fmt::Debug for SignerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignerConfig::Keystore { path, .. } => f
+                .debug_struct("Keystore")
+                .field("path", path)
+                .field("password", &"<redacted>")
+                .finish(),
+            SignerConfig::PrivateKey(_) => f.debug_tuple("PrivateKey").field(&"<redacted>").finish(),
+            SignerConfig::Remote { url, address } => f
+                .debug_struct("Remote")
+                .field("url", url)
+                .field("address", address)
+                .finish(),
+            SignerConfig::Random => f.write_str("Random"),
+        }
+    }
+}
+
+impl SignerConfig {
+    /// Reads the configuration under `prefix`, `None` if nothing is set.
+    pub fn from_env(prefix: &str) -> Result<Option<Self>, SignerError> {
+        Self::from_vars(prefix, |name| env::var(name).ok())
+    }
+
+    fn from_vars(
+        prefix: &str,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<Self>, SignerError> {
+        let name = |suffix: &str| format!("{}_{}", prefix, suffix);
+        let required = |suffix: &str| {
+            var(&name(suffix)).ok_or_else(|| SignerError::Config(format!("{} is not set", name(suffix))))
+        };
+
+        if let Some(path) = var(&name("KEYSTORE")) {
+            return Ok(Some(SignerConfig::Keystore {
+                path: PathBuf::from(path),
+                password: required("KEYSTORE_PASSWORD")?,
+            }));
+        }
+        if let Some(key) = var(&name("PRIVATE_KEY")) {
+            return Ok(Some(SignerConfig::PrivateKey(key)));
+        }
+        if let Some(url) = var(&name("REMOTE_SIGNER_URL")) {
+            let url = Url::parse(&url)
+                .map_err(|_| SignerError::Config(format!("{} is not a url", name("REMOTE_SIGNER_URL"))))?;
+            let address = required("ADDRESS")?
+                .parse()
+                .map_err(|_| SignerError::Config(format!("{} is not an address", name("ADDRESS"))))?;
+            return Ok(Some(SignerConfig::Remote { url, address }));
+        }
+        Ok(None)
+    }
+
+    pub fn load(&self, chain_id: u64) -> Result<SearcherSigner, SignerError> {
+        let signer = match self {
+            SignerConfig::Keystore { path, password } => SearcherSigner::Local(
+                LocalWallet::decrypt_keystore(path, password).map_err(|_| {
+                    SignerError::Config(format!("could not decrypt keystore {}", path.display()))
+                })?,
+            ),
+            SignerConfig::PrivateKey(key) => SearcherSigner::Local(
+                key.trim_start_matches("0x")
+                    .parse::<LocalWallet>()
+                    .map_err(|_| SignerError::Config("invalid private key".to_string()))?,
+            ),
+            SignerConfig::Remote { url, address } => {
+                SearcherSigner::Remote(RemoteSigner::new(url.clone(), *address, chain_id))
+            }
+            SignerConfig::Random => {
+                SearcherSigner::Local(LocalWallet::new(&mut rand::thread_rng()))
+            }
+        };
+        Ok(signer.with_chain_id(chain_id))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: Option<Bytes>,
+    error: Option<Value>,
+}
+
+/// Signer holding its key in another process, such as an HSM or KMS proxy.
+///
+/// Digests are sent as JSON-RPC `signer_signHash` requests with params
+/// `[address, hash]`, answered with a 65 bytes `r || s || v` signature, `v` being the
+/// recovery id or 27 plus it. Anything answering that, a local stub included, can sign.
+#[derive(Debug, Clone)]
+pub struct RemoteSigner {
+    http: reqwest::Client,
+    url: Url,
+    address: Address,
+    chain_id: u64,
+}
+
+impl RemoteSigner {
+    pub fn new(url: Url, address: Address, chain_id: u64) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url,
+            address,
+            chain_id,
+        }
+    }
+
+    /// Signature of `hash` with `v` set to 27 plus the recovery id.
+    pub async fn sign_hash(&self, hash: H256) -> Result<Signature, SignerError> {
+        let response: RpcResponse = self
+            .http
+            .post(self.url.clone())
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "signer_signHash",
+                "params": [self.address, hash],
+            }))
+            .send()
+            .await
+            .map_err(|e| SignerError::Remote(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| SignerError::Remote(e.to_string()))?;
+
+        let raw = match (response.result, response.error) {
+            (Some(raw), _) => raw,
+            (None, Some(error)) => return Err(SignerError::Remote(error.to_string())),
+            (None, None) => return Err(SignerError::Remote("empty response".to_string())),
+        };
+        let mut signature = Signature::try_from(raw.as_ref())
+            .map_err(|e| SignerError::Remote(e.to_string()))?;
+        if signature.v < 27 {
+            signature.v += 27;
+        }
+        if signature.recover(hash).ok() != Some(self.address) {
+            return Err(SignerError::Remote(format!(
+                "signature doesn't recover to {:?}",
+                self.address
+            )));
+        }
+        Ok(signature)
+    }
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+    type Error = SignerError;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(
+        &self,
+        message: S,
+    ) -> Result<Signature, Self::Error> {
+        self.sign_hash(hash_message(message)).await
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, Self::Error> {
+        let mut tx = tx.clone();
+        if tx.chain_id().is_none() {
+            tx.set_chain_id(self.chain_id);
+        }
+        let chain_id = tx.chain_id().map(|id| id.as_u64()).unwrap_or(self.chain_id);
+        let mut signature = self.sign_hash(tx.sighash()).await?;
+        signature.v = to_eip155_v(signature.v as u8 - 27, chain_id);
+        Ok(signature)
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(
+        &self,
+        payload: &T,
+    ) -> Result<Signature, Self::Error> {
+        let hash = payload
+            .encode_eip712()
+            .map_err(|e| SignerError::Eip712(e.to_string()))?;
+        self.sign_hash(H256::from(hash)).await
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    fn with_chain_id<T: Into<u64>>(mut self, chain_id: T) -> Self {
+        self.chain_id = chain_id.into();
+        self
+    }
+}
+
+/// Any configured signer. `Debug` only shows the address.
+#[derive(Debug, Clone)]
+pub enum SearcherSigner {
+    Local(LocalWallet),
+    Remote(RemoteSigner),
+}
+
+impl SearcherSigner {
+    /// Loads the signer configured under `prefix`, falling back to `default`.
+    pub fn from_env(
+        prefix: &str,
+        default: Option<SignerConfig>,
+        chain_id: u64,
+    ) -> Result<Self, SignerError> {
+        SignerConfig::from_env(prefix)?
+            .or(default)
+            .ok_or_else(|| SignerError::Config(format!("no signer configured under {}", prefix)))?
+            .load(chain_id)
+    }
+}
+
+#[async_trait]
+impl Signer for SearcherSigner {
+    type Error = SignerError;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(
+        &self,
+        message: S,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            SearcherSigner::Local(wallet) => Ok(wallet.sign_message(message).await?),
+            SearcherSigner::Remote(remote) => remote.sign_message(message).await,
+        }
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, Self::Error> {
+        match self {
+            SearcherSigner::Local(wallet) => Ok(wallet.sign_transaction(tx).await?),
+            SearcherSigner::Remote(remote) => remote.sign_transaction(tx).await,
+        }
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(
+        &self,
+        payload: &T,
+    ) -> Result<Signature, Self::Error> {
+        match self {
+            SearcherSigner::Local(wallet) => Ok(wallet.sign_typed_data(payload).await?),
+            SearcherSigner::Remote(remote) => remote.sign_typed_data(payload).await,
+        }
+    }
+
+    fn address(&self) -> Address {
+        match self {
+            SearcherSigner::Local(wallet) => wallet.address(),
+            SearcherSigner::Remote(remote) => remote.address(),
+        }
+    }
+
+    fn chain_id(&self) -> u64 {
+        match self {
+            SearcherSigner::Local(wallet) => wallet.chain_id(),
+            SearcherSigner::Remote(remote) => remote.chain_id(),
+        }
+    }
+
+    fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+        match self {
+            SearcherSigner::Local(wallet) => SearcherSigner::Local(wallet.with_chain_id(chain_id)),
+            SearcherSigner::Remote(remote) => SearcherSigner::Remote(remote.with_chain_id(chain_id)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    /// Answers a single `signer_signHash` request with `wallet`'s signature.
+    async fn stub_remote_signer(wallet: LocalWallet) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            let body = loop {
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| line.to_lowercase().strip_prefix("content-length: ").map(str::to_string))
+                        .and_then(|length| length.trim().parse::<usize>().ok())
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break body.to_string();
+                    }
+                }
+            };
+            let request: Value = serde_json::from_str(&body).unwrap();
+            let hash: H256 = serde_json::from_value(request["params"][1].clone()).unwrap();
+            let signature = wallet.sign_hash(hash);
+            let response = json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": Bytes::from(signature.to_vec()),
+            })
+            .to_string();
+            socket
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                        response.len(),
+                        response
+                    )
+                    .as_bytes(),
+                )
+                .await
+                .unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn test_config_from_vars() {
+        let config = SignerConfig::from_vars(
+            "TEST",
+            vars(&[("TEST_KEYSTORE", "/keys/a.json"), ("TEST_KEYSTORE_PASSWORD", "hunter2")]),
+        )
+        .unwrap()
+        .unwrap();
+        let printed = format!("{:?}", config);
+        assert!(printed.contains("/keys/a.json"));
+        assert!(!printed.contains("hunter2"));
+
+        let config = SignerConfig::from_vars("TEST", vars(&[("TEST_PRIVATE_KEY", KEY)]))
+            .unwrap()
+            .unwrap();
+        assert!(!format!("{:?}", config).contains(KEY));
+        let signer = config.load(1).unwrap();
+        assert!(!format!("{:?}", signer).contains(KEY));
+        assert_eq!(signer.chain_id(), 1);
+
+        assert!(SignerConfig::from_vars("TEST", vars(&[("TEST_KEYSTORE", "/keys/a.json")])).is_err());
+        assert!(SignerConfig::from_vars("TEST", vars(&[])).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_remote_signer_matches_local() {
+        let wallet: LocalWallet = KEY.parse::<LocalWallet>().unwrap().with_chain_id(1u64);
+        let url = stub_remote_signer(wallet.clone()).await;
+        let remote = SearcherSigner::from_env(
+            "UNSET_TEST_SIGNER",
+            Some(SignerConfig::Remote {
+                url,
+                address: wallet.address(),
+            }),
+            1,
+        )
+        .unwrap();
+
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .to(Address::repeat_byte(1))
+            .nonce(3)
+            .chain_id(1u64)
+            .into();
+        assert_eq!(
+            remote.sign_transaction(&tx).await.unwrap(),
+            wallet.sign_transaction(&tx).await.unwrap()
+        );
+    }
+}
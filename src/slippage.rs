@@ -0,0 +1,124 @@
+//! Per-leg slippage guards and swap deadlines.
+//!
+//! Every swap leg is sent with the least output we accept, derived from its simulated
+//! output and a tolerance. Router, V3 and Vault swaps take it as their own minimum;
+//! the executor also checks it on each step's recipient, reverting the cycle at the
+//! first leg falling short rather than once its profit is counted. Direct `pair.swap`
+//! legs still ask for their quoted output, see `flash_swap::chain_legs`: a pair pays
+//! exactly what it is asked, and one whose reserves moved against us reverts on its
+//! invariant.
+//! Deadlines are unix seconds, as routers compare them to `block.timestamp`.
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ethers::prelude::*;
+
+/// Env var overriding the default tolerance, in basis points.
+pub const SLIPPAGE_ENV: &str = "BACKRUNNER_SLIPPAGE_BPS";
+/// Env var overriding how many seconds a swap stays valid.
+pub const DEADLINE_ENV: &str = "BACKRUNNER_DEADLINE_SECS";
+
+const BPS: u32 = 10_000;
+
+/// Output tolerance applied to the simulated output of each leg.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlippagePolicy {
+    pub tolerance_bps: u32,
+}
+
+impl Default for SlippagePolicy {
+    fn default() -> Self {
+        Self { tolerance_bps: 30 }
+    }
+}
+
+impl SlippagePolicy {
+    pub fn new(tolerance_bps: u32) -> Self {
+        Self {
+            tolerance_bps: tolerance_bps.min(BPS),
+        }
+    }
+
+    /// Reads `BACKRUNNER_SLIPPAGE_BPS`, falling back to the default tolerance.
+    pub fn from_env() -> Self {
+        env::var(SLIPPAGE_ENV)
+            .ok()
+            .and_then(|bps| bps.parse().ok())
+            .map(Self::new)
+            .unwrap_or_default()
+    }
+
+    /// Least output accepted for a leg simulated to return `expected`, rounded down.
+    pub fn min_out(&self, expected: U256) -> U256 {
+        expected
+            .full_mul(U256::from(BPS - self.tolerance_bps))
+            .checked_div(U256::from(BPS).into())
+            .and_then(|min_out| U256::try_from(min_out).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// How long after the block the swaps were priced on they may still be mined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlinePolicy {
+    pub valid_for_secs: u64,
+}
+
+impl Default for DeadlinePolicy {
+    fn default() -> Self {
+        // Two or three slots: a bundle that misses them is priced on stale reserves
+        Self { valid_for_secs: 36 }
+    }
+}
+
+impl DeadlinePolicy {
+    pub fn new(valid_for_secs: u64) -> Self {
+        Self { valid_for_secs }
+    }
+
+    /// Reads `BACKRUNNER_DEADLINE_SECS`, falling back to the default validity.
+    pub fn from_env() -> Self {
+        env::var(DEADLINE_ENV)
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .map(Self::new)
+            .unwrap_or_default()
+    }
+
+    /// Deadline for swaps priced on a block mined at `block_timestamp` seconds.
+    pub fn deadline(&self, block_timestamp: U256) -> U256 {
+        block_timestamp.saturating_add(U256::from(self.valid_for_secs))
+    }
+
+    /// Deadline counted from the local clock, when no block timestamp is at hand.
+    pub fn from_now(&self) -> U256 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        self.deadline(U256::from(now.as_secs()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min_out() {
+        let policy = SlippagePolicy::new(30);
+        assert_eq!(policy.min_out(U256::from(10_000)), U256::from(9_970));
+        assert_eq!(policy.min_out(U256::from(999)), U256::from(996));
+        assert!(policy.min_out(U256::MAX) > U256::MAX / 10_000 * 9_969);
+        assert_eq!(SlippagePolicy::new(20_000).min_out(U256::from(500)), U256::zero());
+        assert_eq!(SlippagePolicy::new(0).min_out(U256::from(500)), U256::from(500));
+    }
+
+    #[test]
+    fn test_deadline_is_in_seconds() {
+        let policy = DeadlinePolicy::new(36);
+        assert_eq!(
+            policy.deadline(U256::from(1_700_000_000_u64)),
+            U256::from(1_700_000_036_u64)
+        );
+        // Milliseconds since the epoch would be three orders of magnitude larger
+        assert!(policy.from_now() < U256::from(10_000_000_000_u64));
+    }
+}
@@ -1,4 +1,3 @@
-use crate::contracts::bindings::uniswap_v2_router_02::UniswapV2Router02;
 use crate::utils::conversions::big_rational_to_u256;
 use async_std::sync::Arc;
 use bigdecimal::BigDecimal;
@@ -24,7 +23,31 @@ use ethers::contract::{
     Contract, Lazy,
 };
 
-use crate::contracts::bindings::uniswap_v2_pair::UniswapV2Pair;
+use crate::contracts::bindings::ierc20::{ApproveCall, TransferCall};
+use crate::contracts::bindings::uniswap_v2_pair::SwapCall as PairSwapCall;
+use crate::contracts::bindings::uniswap_v2_router_02::SwapExactTokensForTokensCall;
+use crate::contracts::bindings::uniswap_v3_router::{ExactInputSingleCall, ExactInputSingleParams};
+use crate::crypto_pair::CryptoPair;
+use crate::dex_pool::PoolKind;
+use crate::executor_call::{ExecutorCall, ExecutorLeg, Repayment};
+use crate::flash_source::BALANCER_VAULT;
+use crate::slippage::SlippagePolicy;
+use crate::weth::tip_steps;
+use ethers::abi::{self, AbiEncode};
+use ethers::utils::id;
+
+const BALANCER_SWAP: &str =
+    "swap((bytes32,uint8,address,address,uint256,bytes),(address,bool,address,bool),uint256,uint256)";
+const BALANCER_BATCH_SWAP: &str = "batchSwap(uint8,(bytes32,uint256,uint256,uint256,bytes)[],address[],(address,bool,address,bool),int256[],uint256)";
+
+/// Whether a V2 swap goes straight to the pair or through the DEX's router.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum V2Encoding {
+    /// `pair.swap`, the input must already be in the pair
+    PairDirect,
+    /// `router.swapExactTokensForTokens`, pulling the input from the sender
+    Router,
+}
 
 #[derive(Clone, Debug)]
 pub struct SwapRoute {
@@ -36,7 +59,15 @@ pub struct SwapRoute {
     pub dest_amount: U256,
     pub router: Address,
     pair_id: H160,
-    pub pair_id_2: H160
+    pub pair_id_2: H160,
+    pub kind: PoolKind,
+    pub v2_encoding: V2Encoding,
+    /// Least output accepted, `dest_amount` unless set, see `with_slippage`
+    pub amount_out_min: Option<U256>,
+    /// Unix seconds after which the swap reverts, `mainnet::valid_timestamp` unless set
+    pub deadline: Option<U256>,
+    /// Receiver of the output for router, V3 and Balancer swaps
+    pub recipient: Option<Address>,
 }
 
 impl SwapRoute {
@@ -60,39 +91,206 @@ impl SwapRoute {
             dest_amount: dest,
             router,
             pair_id,
-            pair_id_2
+            pair_id_2,
+            kind: PoolKind::V2,
+            v2_encoding: V2Encoding::PairDirect,
+            amount_out_min: None,
+            deadline: None,
+            recipient: None,
+        }
+    }
+
+    /// Leg swapping `amount_in` of `tokens.0` for `expected_out` of `tokens.1` on
+    /// `pair_id`, sending the output to `to`.
+    pub fn leg(
+        tokens: (Address, Address),
+        amount_in: U256,
+        expected_out: U256,
+        router: Address,
+        pair_id: Address,
+        to: Address,
+    ) -> Self {
+        // Pair outputs are ordered by token address
+        let (out0, out1) = if tokens.1 < tokens.0 {
+            (expected_out, U256::zero())
+        } else {
+            (U256::zero(), expected_out)
+        };
+        Self::new(tokens, amount_in, out0, out1, expected_out, router, pair_id, to)
+    }
+
+    /// Route through `pair`, encoded according to its kind and router.
+    pub fn on(mut self, pair: &CryptoPair) -> Self {
+        self.kind = pair.kind();
+        self.router = pair.router();
+        self
+    }
+
+    pub fn with_kind(mut self, kind: PoolKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_v2_encoding(mut self, encoding: V2Encoding) -> Self {
+        self.v2_encoding = encoding;
+        self
+    }
+
+    pub fn with_amount_out_min(mut self, amount_out_min: U256) -> Self {
+        self.amount_out_min = Some(amount_out_min);
+        self
+    }
+
+    /// Least output `policy` accepts for the simulated `dest_amount`.
+    pub fn with_slippage(mut self, policy: &SlippagePolicy) -> Self {
+        self.amount_out_min = Some(policy.min_out(self.dest_amount));
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: U256) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_recipient(mut self, recipient: Address) -> Self {
+        self.recipient = Some(recipient);
+        self
+    }
+
+    pub fn amount_out_min(&self) -> U256 {
+        self.amount_out_min.unwrap_or(self.dest_amount)
+    }
+
+    pub fn deadline(&self) -> U256 {
+        self.deadline.unwrap_or_else(mainnet::valid_timestamp)
+    }
+
+    fn recipient(&self) -> Address {
+        self.recipient.unwrap_or(*mainnet::to)
+    }
+
+    /// Contract the calldata is sent to.
+    pub fn target(&self) -> Address {
+        match (self.kind, self.v2_encoding) {
+            (PoolKind::V2, V2Encoding::PairDirect) => self.pair_id,
+            (PoolKind::V2, V2Encoding::Router) | (PoolKind::V3 { .. }, _) => self.router,
+            (PoolKind::Balancer { .. }, _) => *BALANCER_VAULT,
         }
     }
 
     pub async fn swap(&self)->Result<Bytes, anyhow::Error> {
-        let pair_contract = UniswapV2Pair::new(self.pair_id, mainnet::client.clone());
-        let contract_call = pair_contract.swap(self.source_amount_0, self.source_amount_1 , self.pair_id_2, Bytes::default());
-        
-        Ok(contract_call.calldata().unwrap())
+        Ok(Bytes::from(
+            PairSwapCall {
+                amount_0_out: self.source_amount_0,
+                amount_1_out: self.source_amount_1,
+                to: self.pair_id_2,
+                data: Bytes::default(),
+            }
+            .encode(),
+        ))
     }
 
+    /// Router swap of exactly `source_amount` on the pair's own DEX.
     pub async fn swap_tokens_for_exact_tokens(&self) -> Result<Bytes, anyhow::Error> {
-        let router_contract =
-            UniswapV2Router02::new(*mainnet::router_v2, mainnet::client.clone());
-/* 
-        let pair_contract = UniswapV2Pair::new(self.pair_id, mainnet::client.clone());
-        let contract_call = pair_contract.swap(self.source_amount.add(U256::from(1000_i32)), self.dest_amount.add(U256::from(1000_i32)), mainnet::flash_contract.clone(), Bytes::default());
-        Ok(contract_call.calldata().unwrap())
-   */     
-        let payload = router_contract
-            .swap_exact_tokens_for_tokens(
-                self.source_amount,
-                self.dest_amount,
-                vec![self.pair.0, self.pair.1],
-                *mainnet::to,
-                mainnet::valid_timestamp(),
-            )
-            .calldata()
-            .unwrap();
-        Ok(payload)
-        
+        Ok(Bytes::from(
+            SwapExactTokensForTokensCall {
+                amount_in: self.source_amount,
+                amount_out_min: self.amount_out_min(),
+                path: vec![self.pair.0, self.pair.1],
+                to: self.recipient(),
+                deadline: self.deadline(),
+            }
+            .encode(),
+        ))
+    }
 
-        //Ok(Bytes::from(vec![3_u8, 1_u8]))
+    /// `SwapRouter.exactInputSingle` of exactly `source_amount`.
+    pub fn exact_input_single(&self, fee: u32) -> Bytes {
+        Bytes::from(
+            ExactInputSingleCall {
+                params: ExactInputSingleParams {
+                    token_in: self.pair.0,
+                    token_out: self.pair.1,
+                    fee,
+                    recipient: self.recipient(),
+                    deadline: self.deadline(),
+                    amount_in: self.source_amount,
+                    amount_out_minimum: self.amount_out_min(),
+                    sqrt_price_limit_x96: U256::zero(),
+                },
+            }
+            .encode(),
+        )
+    }
+
+    /// Vault `FundManagement`: `executor` pays the input, the recipient gets the output.
+    fn balancer_funds(&self, executor: Address) -> Token {
+        Token::Tuple(vec![
+            Token::Address(executor),
+            Token::Bool(false),
+            Token::Address(self.recipient()),
+            Token::Bool(false),
+        ])
+    }
+
+    /// Vault `swap` of exactly `source_amount` in `pool_id`, funded by `executor`.
+    pub fn balancer_swap(&self, pool_id: H256, executor: Address) -> Bytes {
+        let single_swap = Token::Tuple(vec![
+            Token::FixedBytes(pool_id.as_bytes().to_vec()),
+            // GIVEN_IN
+            Token::Uint(U256::zero()),
+            Token::Address(self.pair.0),
+            Token::Address(self.pair.1),
+            Token::Uint(self.source_amount),
+            Token::Bytes(vec![]),
+        ]);
+        let mut calldata = id(BALANCER_SWAP).to_vec();
+        calldata.extend(abi::encode(&[
+            single_swap,
+            self.balancer_funds(executor),
+            Token::Uint(self.amount_out_min()),
+            Token::Uint(self.deadline()),
+        ]));
+        Bytes::from(calldata)
+    }
+
+    /// Vault `batchSwap` chaining consecutive Balancer routes, the output of each
+    /// feeding the next, funded by `executor`. `None` unless every route is a Balancer
+    /// one.
+    pub fn balancer_batch_swap(routes: &[SwapRoute], executor: Address) -> Option<Bytes> {
+        let (first, last) = (routes.first()?, routes.last()?);
+        let mut assets = vec![first.pair.0];
+        let mut swaps = Vec::new();
+        for (i, route) in routes.iter().enumerate() {
+            let pool_id = match route.kind {
+                PoolKind::Balancer { pool_id } => pool_id,
+                _ => return None,
+            };
+            assets.push(route.pair.1);
+            swaps.push(Token::Tuple(vec![
+                Token::FixedBytes(pool_id.as_bytes().to_vec()),
+                Token::Uint(U256::from(i)),
+                Token::Uint(U256::from(i + 1)),
+                // Zero takes the whole output of the previous swap
+                Token::Uint(if i == 0 { route.source_amount } else { U256::zero() }),
+                Token::Bytes(vec![]),
+            ]));
+        }
+        // Most we send of each asset, negative for the least we receive
+        let mut limits = vec![Token::Int(U256::zero()); assets.len()];
+        limits[0] = Token::Int(first.source_amount);
+        limits[assets.len() - 1] = Token::Int(I256::from_raw(last.amount_out_min()).wrapping_neg().into_raw());
+
+        let mut calldata = id(BALANCER_BATCH_SWAP).to_vec();
+        calldata.extend(abi::encode(&[
+            Token::Uint(U256::zero()),
+            Token::Array(swaps),
+            Token::Array(assets.into_iter().map(Token::Address).collect()),
+            last.balancer_funds(executor),
+            Token::Array(limits),
+            Token::Uint(last.deadline()),
+        ]));
+        Some(Bytes::from(calldata))
     }
 
     /*
@@ -147,7 +345,7 @@ impl SwapRoute {
     /
     Provided some amount for some pair, return abi-encoded data for swap
      */
-    pub async fn calldata(&self) -> Result<Bytes, anyhow::Error> {
+    pub async fn calldata(&self, executor: Address) -> Result<Bytes, anyhow::Error> {
         /*
         match (
             self.pair.0.symbol().as_str(),
@@ -160,53 +358,281 @@ impl SwapRoute {
             (_, _) => self.swap_tokens_for_exact_tokens(),
         }
         */
-        self.swap().await
-        //self.swap_tokens_for_exact_tokens().await
+        match (self.kind, self.v2_encoding) {
+            (PoolKind::V2, V2Encoding::PairDirect) => self.swap().await,
+            (PoolKind::V2, V2Encoding::Router) => self.swap_tokens_for_exact_tokens().await,
+            (PoolKind::V3 { fee }, _) => Ok(self.exact_input_single(fee)),
+            (PoolKind::Balancer { pool_id }, _) => Ok(self.balancer_swap(pool_id, executor)),
+        }
+    }
+
+    /// Points each route's output at the next route: straight into its pair when it is
+    /// swapped directly, to `executor` otherwise and after the last route.
+    pub fn chain(mut routes: Vec<SwapRoute>, executor: Address) -> Vec<SwapRoute> {
+        let payees: Vec<Address> = routes
+            .iter()
+            .skip(1)
+            .map(|next| match (next.kind, next.v2_encoding) {
+                (PoolKind::V2, V2Encoding::PairDirect) => next.target(),
+                _ => executor,
+            })
+            .chain(std::iter::once(executor))
+            .collect();
+        for (route, payee) in routes.iter_mut().zip(payees) {
+            route.pair_id_2 = payee;
+            route.recipient = Some(payee);
+        }
+        routes
     }
 
-    pub async fn route_calldata(
+    /// Executor legs running `swap_routes` in order on behalf of `executor`. An input
+    /// held by the executor is first transferred into a direct pair, or approved to the
+    /// router or vault pulling it; a pair the previous route already paid needs neither.
+    /// Consecutive Balancer routes are run as one Vault `batchSwap`.
+    pub async fn executor_legs(
+        swap_routes: &[SwapRoute],
+        executor: Address,
+    ) -> Result<Vec<ExecutorLeg>> {
+        let mut legs = Vec::new();
+        let mut paid = None;
+        let mut i = 0;
+        while i < swap_routes.len() {
+            let route = &swap_routes[i];
+            let batch = swap_routes[i..]
+                .iter()
+                .take_while(|route| matches!(route.kind, PoolKind::Balancer { .. }))
+                .count()
+                .max(1);
+            let last = &swap_routes[i + batch - 1];
+            let target = route.target();
+            match (route.kind, route.v2_encoding) {
+                (PoolKind::V2, V2Encoding::PairDirect) if paid == Some(target) => {}
+                (PoolKind::V2, V2Encoding::PairDirect) => legs.push(ExecutorLeg::call(
+                    route.pair.0,
+                    Bytes::from(
+                        TransferCall {
+                            recipient: target,
+                            amount: route.source_amount,
+                        }
+                        .encode(),
+                    ),
+                )),
+                _ => legs.push(ExecutorLeg::call(
+                    route.pair.0,
+                    Bytes::from(
+                        ApproveCall {
+                            spender: target,
+                            amount: route.source_amount,
+                        }
+                        .encode(),
+                    ),
+                )),
+            }
+            let calldata = match batch {
+                1 => route.calldata(executor).await?,
+                _ => Self::balancer_batch_swap(&swap_routes[i..i + batch], executor)
+                    .ok_or_else(|| anyhow::anyhow!("not a run of Balancer routes"))?,
+            };
+            let payee = match (last.kind, last.v2_encoding) {
+                (PoolKind::V2, V2Encoding::PairDirect) => last.pair_id_2,
+                _ => last.recipient(),
+            };
+            legs.push(
+                ExecutorLeg::swap(target, calldata, last.pair.1, last.dest_amount)
+                    .with_min_output(payee, last.amount_out_min()),
+            );
+            paid = Some(payee);
+            i += batch;
+        }
+        Ok(legs)
+    }
+
+    /// Calldata of `executor` running `swap_routes` then `calls`, reverting unless the
+    /// cycle returns at least the last route's least output on the first route's input. `miner_tip` is
+    /// transferred to `block.coinbase` by the executor, see `BidPolicy::coinbase_tip`.
+    pub async fn route_calldata<M: Middleware>(
         swap_routes: Vec<SwapRoute>,
-        calls: Vec<
-            ethers::prelude::builders::ContractCall<
-                ethers::prelude::SignerMiddleware<
-                    ethers::prelude::Provider<ethers::prelude::Http>,
-                    ethers::prelude::Wallet<ethers::prelude::k256::ecdsa::SigningKey>,
-                >,
-                bool,
-            >,
-        >,
+        executor: Address,
+        miner_tip: U256,
+        calls: Vec<ethers::prelude::builders::ContractCall<M, bool>>,
     ) -> Result<Bytes, anyhow::Error> {
-        /* For each pair, get abi-encoded swap call */
-        let miner_tip = Token::Uint(U256::zero());
-
-        let mut trade_routers = Vec::<Token>::new();
-        let mut sell_tokens = Vec::<Token>::new();
-        let mut call_data = Vec::<Token>::new();
-        let mut transfer_amts = Vec::<Token>::new();
-
-        /* Build data */
-        for trade in swap_routes {
-            trade_routers.push(Token::Address(trade.pair_id));
-            sell_tokens.push(Token::Address(trade.pair.0));
-            sell_tokens.push(Token::Address(trade.pair.1));
-            call_data.push(Token::Bytes(trade.calldata().await?.to_vec()));
-            transfer_amts.push(Token::Uint(trade.transfer_amt));
-            
-          
-        }
+        let (first, last) = match (swap_routes.first(), swap_routes.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(anyhow::anyhow!("no swap routes")),
+        };
+        let repayment = Repayment {
+            pool: first.target(),
+            token: first.pair.0,
+            amount: first.source_amount,
+        };
 
-        for call in calls {
-            call_data.push(Token::Bytes(call.calldata().unwrap().to_vec()));
+        let tip = tip_steps(*mainnet::weth, repayment.token, miner_tip)?;
+        let mut call = ExecutorCall::new(executor, repayment)
+            .with_legs(Self::executor_legs(&swap_routes, executor).await?)
+            .with_miner_tip(miner_tip);
+        for extra in calls {
+            let target = match extra.tx.to() {
+                Some(NameOrAddress::Address(target)) => *target,
+                _ => return Err(anyhow::anyhow!("call without a target address")),
+            };
+            let calldata = extra
+                .calldata()
+                .ok_or_else(|| anyhow::anyhow!("call without calldata"))?;
+            call.push(ExecutorLeg::call(target, calldata));
         }
-        dbg!(&transfer_amts);
-        /* abi encode data */
-        let tokens = vec![
-            miner_tip,
-            Token::Array(trade_routers),
-            Token::Array(sell_tokens),
-            Token::Array(call_data),
-            Token::Array(transfer_amts),
-        ];
-        Ok(Bytes::from(abi::encode(&tokens)))
+        let min_profit = last.amount_out_min().saturating_sub(first.source_amount);
+        Ok(call.with_legs(tip).with_min_profit(min_profit).encode()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{pool, token};
+    use ethers::abi::AbiDecode;
+
+    /// Token 1 to token 2 on pool 1, paying pool 2, through router `0x11..`.
+    fn route(kind: PoolKind) -> SwapRoute {
+        SwapRoute::new(
+            (token(1), token(2)),
+            U256::from(1000),
+            U256::zero(),
+            U256::from(1990),
+            U256::from(1990),
+            Address::repeat_byte(0x11),
+            pool(1),
+            pool(2),
+        )
+        .with_kind(kind)
+        .with_amount_out_min(U256::from(1980))
+        .with_deadline(U256::from(1_700_000_000))
+        .with_recipient(Address::repeat_byte(0xee))
+    }
+
+    #[test]
+    fn test_targets_and_selectors() {
+        let v2 = route(PoolKind::V2);
+        assert_eq!(v2.target(), Address::repeat_byte(0xa1));
+        let routed = v2.with_v2_encoding(V2Encoding::Router);
+        assert_eq!(routed.target(), Address::repeat_byte(0x11));
+
+        let v3 = route(PoolKind::V3 { fee: 500 });
+        assert_eq!(v3.target(), Address::repeat_byte(0x11));
+        let data = v3.exact_input_single(500);
+        assert_eq!(data[..4], [65, 75, 243, 137]);
+        let decoded = ExactInputSingleCall::decode(&data).unwrap();
+        assert_eq!(decoded.params.amount_out_minimum, U256::from(1980));
+        assert_eq!(decoded.params.deadline, U256::from(1_700_000_000));
+
+        let pool_id = H256::repeat_byte(0xb1);
+        let balancer = route(PoolKind::Balancer { pool_id });
+        assert_eq!(balancer.target(), *BALANCER_VAULT);
+        assert_eq!(
+            balancer.balancer_swap(pool_id, Address::repeat_byte(0xee))[..4],
+            id(BALANCER_SWAP)
+        );
+    }
+
+    #[test]
+    fn test_leg_guards() {
+        let (weth, dai) = (Address::repeat_byte(2), Address::repeat_byte(1));
+        let leg = SwapRoute::leg(
+            (weth, dai),
+            U256::from(1000),
+            U256::from(2_000_000),
+            Address::repeat_byte(0x11),
+            Address::repeat_byte(0xa1),
+            Address::repeat_byte(0xa2),
+        );
+        // dai sorts first, so it is the pair's token0
+        assert_eq!(leg.source_amount_0, U256::from(2_000_000));
+        assert_eq!(leg.source_amount_1, U256::zero());
+        assert_eq!(leg.amount_out_min(), U256::from(2_000_000));
+
+        let leg = leg.with_slippage(&SlippagePolicy::new(50));
+        assert_eq!(leg.amount_out_min(), U256::from(1_990_000));
+    }
+
+    #[tokio::test]
+    async fn test_executor_legs() {
+        let first = route(PoolKind::V2);
+        let mut second = route(PoolKind::V2);
+        second.pair = (Address::repeat_byte(2), Address::repeat_byte(1));
+        second.pair_id = Address::repeat_byte(0xa2);
+        second.pair_id_2 = Address::repeat_byte(0xee);
+        let third = route(PoolKind::V3 { fee: 500 });
+
+        let legs = SwapRoute::executor_legs(&[first, second, third], Address::repeat_byte(0xee))
+            .await
+            .unwrap();
+        let targets: Vec<Address> = legs.iter().map(|leg| leg.target).collect();
+        // The second pair is paid by the first, the router pulls from the executor
+        assert_eq!(
+            targets,
+            vec![
+                Address::repeat_byte(1),
+                Address::repeat_byte(0xa1),
+                Address::repeat_byte(0xa2),
+                Address::repeat_byte(1),
+                Address::repeat_byte(0x11),
+            ]
+        );
+        // The first pair is paid the route's input, not its expected output
+        let transfer = TransferCall::decode(&legs[0].calldata).unwrap();
+        assert_eq!(transfer.recipient, Address::repeat_byte(0xa1));
+        assert_eq!(transfer.amount, U256::from(1000));
+        assert_eq!(legs[3].calldata[..4], ApproveCall::selector());
+        assert_eq!(legs[4].token_out, Address::repeat_byte(2));
+        assert_eq!(legs[4].expected_output, U256::from(1990));
+        assert_eq!(legs[4].recipient, Address::repeat_byte(0xee));
+        assert_eq!(legs[4].min_output, U256::from(1980));
+    }
+
+    #[test]
+    fn test_chain_pays_next_route() {
+        let executor = Address::repeat_byte(0xee);
+        let direct = route(PoolKind::V2);
+        let v3 = route(PoolKind::V3 { fee: 3000 });
+        let routes = SwapRoute::chain(vec![v3.clone(), direct, v3], executor);
+
+        // The V3 output goes straight into the next pair, whose output the executor
+        // approves to the last router
+        assert_eq!(routes[0].recipient, Some(Address::repeat_byte(0xa1)));
+        assert_eq!(routes[1].pair_id_2, executor);
+        assert_eq!(routes[2].recipient, Some(executor));
+    }
+
+    #[tokio::test]
+    async fn test_balancer_batch_swap() {
+        let executor = Address::repeat_byte(0xee);
+        let first = route(PoolKind::Balancer { pool_id: H256::repeat_byte(0xb1) });
+        let mut second = route(PoolKind::Balancer { pool_id: H256::repeat_byte(0xb2) });
+        second.pair = (Address::repeat_byte(2), Address::repeat_byte(1));
+        let data = SwapRoute::balancer_batch_swap(&[first.clone(), second.clone()], executor)
+            .unwrap();
+        assert_eq!(data[..4], id(BALANCER_BATCH_SWAP));
+        // `funds` is inline after the kind and two offsets, the executor sending
+        assert_eq!(Address::from_slice(&data[4 + 3 * 32 + 12..4 + 4 * 32]), executor);
+
+        assert!(SwapRoute::balancer_batch_swap(&[first.clone(), route(PoolKind::V2)], executor)
+            .is_none());
+        assert!(SwapRoute::balancer_batch_swap(&[], executor).is_none());
+
+        // Consecutive Balancer routes are approved and swapped once through the Vault
+        let legs = SwapRoute::executor_legs(&[first, second, route(PoolKind::V2)], executor)
+            .await
+            .unwrap();
+        let targets: Vec<Address> = legs.iter().map(|leg| leg.target).collect();
+        assert_eq!(
+            targets,
+            vec![
+                Address::repeat_byte(1),
+                *BALANCER_VAULT,
+                Address::repeat_byte(1),
+                Address::repeat_byte(0xa1),
+            ]
+        );
+        assert_eq!(legs[1].calldata[..4], id(BALANCER_BATCH_SWAP));
+        assert_eq!(legs[1].token_out, Address::repeat_byte(1));
     }
 }
@@ -0,0 +1,40 @@
+//! Fixtures shared by the unit tests.
+//!
+//! Tokens are numbered `token(n)`, pools `pool(n)`, so a hop reads as
+//! `hop(pool, token_in, token_out)`. Amounts are in whole units of 18 decimals.
+use ethers::prelude::*;
+
+use crate::opportunity_aggregator::{Hop, ReserveBook};
+
+pub fn eth(amount: u64) -> U256 {
+    U256::from(amount) * U256::exp10(18)
+}
+
+pub fn gwei(amount: u64) -> U256 {
+    U256::from(amount) * U256::exp10(9)
+}
+
+pub fn token(id: u8) -> Address {
+    Address::repeat_byte(id)
+}
+
+pub fn pool(id: u8) -> Address {
+    Address::repeat_byte(0xa0 + id)
+}
+
+pub fn hop(pool_id: u8, token_in: u8, token_out: u8) -> Hop {
+    Hop {
+        pool: pool(pool_id),
+        token_in: token(token_in),
+        token_out: token(token_out),
+    }
+}
+
+/// Pools 1, 2 and 3 trading tokens 1 -> 2 -> 3 -> 1, a profitable cycle.
+pub fn cycle_book() -> ReserveBook {
+    let mut book = ReserveBook::default();
+    book.insert(pool(1), token(1), token(2), eth(1000), eth(2000));
+    book.insert(pool(2), token(2), token(3), eth(2000), eth(3000));
+    book.insert(pool(3), token(3), token(1), eth(2800), eth(1000));
+    book
+}
@@ -11,12 +11,19 @@ use std::cmp;
 
 use super::uniswap_providers::*;
 use crate::arb_thread_pool::spawn;
+use crate::backrun::Victim;
+use crate::bid_policy::next_base_fee_of;
+use crate::conversion;
 use crate::contracts::bindings::ierc20::IERC20;
 use crate::crypto_math::{optimize_a_prime, optimize_a_prime_2};
 use crate::crypto_pair::CryptoPair;
 use crate::dex_pool::DexPool;
 use crate::flashbot_strategy::utils::*;
-use crate::path_sequence::PathSequence;
+use crate::gas_model::{self, GasLeg};
+use crate::net_profit::NetProfit;
+use crate::opportunity_aggregator::{Candidate, Hop};
+use crate::path_sequence::{PathOutcome, PathSequence};
+use crate::price_oracle;
 use crate::sequence_token::SequenceToken;
 use crate::swap_route::SwapRoute;
 use crate::three_path_sequence;
@@ -26,7 +33,7 @@ use crate::utils::common::DIRECTION;
 use crate::utils::u256_decimal::format_units;
 use async_trait::async_trait;
 use bigdecimal::{BigDecimal, FromPrimitive, ToPrimitive};
-use ethers::prelude::Address;
+use ethers::prelude::{Address, BlockNumber, Middleware};
 use ethers::prelude::U256;
 use ethers::types::transaction::eip2718::TypedTransaction;
 use futures_signals::map_ref;
@@ -464,43 +471,110 @@ impl ThreePathSequence {
         
 
                 let dec = (sequence.a1().decimal() - sequence.b1().decimal()).abs();
-                let trade1 = SwapRoute::new(
-                    (*sequence.a1().id(), *sequence.b1().id()),
-                    delta_a_amt_out.unwrap(),
-                    U256::zero(),
-                    U256::zero(),
-                    U256::zero(),
-                    sequence.a1().token.pair.router,
-                    *sequence.a1().pair_id(),
-                    *sequence.a2().pair_id()
-                );
+                /* Reserves are priced on the latest block, swaps expire counting from its timestamp */
+                let latest = match mainnet::client.get_block(BlockNumber::Latest).await {
+                    Ok(Some(block)) => block,
+                    _ => return,
+                };
+                let block = match latest.number {
+                    Some(block) => block,
+                    None => return,
+                };
+                let deadline = mainnet::deadline_policy.deadline(latest.timestamp);
+                /* Reserves and prices are snapshotted once per block, for every path */
+                let book = conversion::reserve_book_at(block);
+                let oracle = price_oracle::snapshot_at(block);
+                /* A path ending in another coin needs a fourth trade back to the flash-borrowed token */
+                let mut gross_output = delta_c_amt_out.unwrap();
+                let mut hops = vec![
+                    Hop { pool: *sequence.a1().pair_id(), token_in: *sequence.a1().id(), token_out: *sequence.b1().id() },
+                    Hop { pool: *sequence.a2().pair_id(), token_in: *sequence.a2().id(), token_out: *sequence.b2().id() },
+                    Hop { pool: *sequence.a3().pair_id(), token_in: *sequence.a3().id(), token_out: *sequence.b3().id() },
+                ];
+                if sequence.a1().id() != sequence.b3().id() {
+                    let closed = conversion::close_cycle(
+                        &book,
+                        &hops,
+                        delta_a,
+                        *sequence.a1().id(),
+                        &conversion::HUB_TOKENS,
+                    );
+                    let net_profit = closed.as_ref().and_then(|closed| closed.profit());
+                    println!(
+                        "Path: {} ends in {}, conversion: {:?} net profit: {:?}",
+                        sequence.path(),
+                        sequence.b3().symbol(),
+                        closed.as_ref().and_then(|closed| closed.conversion.as_ref()).map(|c| c.hops.len()),
+                        net_profit
+                    );
+                    let output = closed.as_ref().map(|closed| closed.output);
+                    let conversion = closed.and_then(|closed| closed.conversion);
+                    match (net_profit, output, conversion) {
+                        (Some(net_profit), Some(output), Some(conversion)) if net_profit > U256::zero() => {
+                            gross_output = output;
+                            hops.extend(conversion.hops.iter().cloned());
+                        }
+                        _ => return,
+                    }
+                }
 
-                let trade2 = SwapRoute::new(
-                    (*sequence.a2().id(), *sequence.b2().id()),
-                    delta_b_amt_in.unwrap(),
-                    delta_b_amt_out.unwrap(),
-                    U256::zero(),
-                    U256::zero(),
-                    sequence.a2().token.pair.router,
-                    *sequence.a2().pair_id(),
-                    *sequence.a2().pair_id()
-                );
+                let mut gas_legs = vec![
+                    GasLeg::new(&sequence.a1().token, *sequence.a1().id(), *sequence.b1().id()),
+                    GasLeg::new(&sequence.a2().token, *sequence.a2().id(), *sequence.b2().id()),
+                    GasLeg::new(&sequence.a3().token, *sequence.a3().id(), *sequence.b3().id()),
+                ];
+                let pairs = conversion::CONVERSION_PAIRS.read().unwrap().clone();
+                let conversion_pools: Vec<Address> = hops[3..].iter().map(|hop| hop.pool).collect();
+                match gas_model::legs_of(&pairs, &conversion_pools) {
+                    Some(legs) => gas_legs.extend(legs),
+                    None => {
+                        println!("Path: {} converts through an unknown pool, can't price its gas", sequence.path());
+                        return;
+                    }
+                }
 
-                let trade3 = SwapRoute::new(
-                    (*sequence.a3().id(), *sequence.b3().id()),
-                    delta_c_amt_in.unwrap(),
-                    delta_c_amt_out.unwrap(),
-                    U256::zero(),
-                    U256::zero(),
-                    sequence.a3().token.pair.router,
-                    *sequence.a3().pair_id(),
-                    *sequence.a2().pair_id()
+                /* Gate on the profit in ETH, net of gas at the next base fee and of the tip */
+                let predicted_base_fee = mainnet::fee_predictor.read().unwrap().next_base_fee();
+                let base_fee = predicted_base_fee.unwrap_or_else(|| next_base_fee_of(&latest));
+                // Calldata is priced once the plan is encoded, below
+                let gas = mainnet::gas_model.read().unwrap().estimate(&gas_legs, &[]);
+                let net = NetProfit::evaluate(
+                    &oracle,
+                    *sequence.a1().id(),
+                    delta_a,
+                    gross_output,
+                    gas,
+                    base_fee,
+                    &mainnet::bid_policy,
                 );
+                match &net {
+                    Some(net) if net.clears(*mainnet::min_net_profit) => {}
+                    _ => {
+                        println!(
+                            "Path: {} below the net profit threshold: {:?}",
+                            sequence.path(),
+                            net.as_ref().map(|net| net.net())
+                        );
+                        return;
+                    }
+                }
 
-                /* Consider whether we will need a fouth trade to convert final token for flash repayment */
-                if &sequence.a1().symbol() != &sequence.b3().symbol()
-                    && !&sequence.b3().symbol().eq("WETH")
-                {}
+                /* Value the trade in USD, comparable across paths whatever token they start with */
+                let notional = oracle.usd_value(*sequence.a1().id(), delta_a);
+                let net_usd = net
+                    .as_ref()
+                    .and_then(|net| net.net())
+                    .and_then(|net| oracle.usd_value(*mainnet::weth, net));
+                println!(
+                    "Path: {} notional: {:?} net profit: {:?}",
+                    sequence.path(),
+                    notional,
+                    net_usd
+                );
+                if !mainnet::risk_limits.allows(notional) {
+                    println!("Path: {} outside the risk limits", sequence.path());
+                    return;
+                }
 
                 let method = "optimize_a_prime_2";
                 println!(
@@ -584,69 +658,100 @@ impl ThreePathSequence {
                     &sequence.b3().pending_reserve(),
                     &optimal
                 );
-                // }
-                    /*
-                let first_pair =
-                    Address::from_str("0x144eC5ABF328f8d477Cd6238bAE5aa027bDDfD1E").unwrap();
-
-                let trade_vec = vec![trade1, trade2, trade3];
-
-                let flash_token = IERC20::new(sequence.a1().pair_id().clone(), mainnet::client.clone());
-                let flash_repayment =
-                    flash_token.transfer(first_pair.clone(), delta_a.mul(U256::from(997_i16)));
-
-                let calls: Vec<
-                    ethers::prelude::builders::ContractCall<
-                        ethers::prelude::SignerMiddleware<
-                            ethers::prelude::Provider<ethers::prelude::Http>,
-                            ethers::prelude::Wallet<ethers::prelude::k256::ecdsa::SigningKey>,
-                        >,
-                        bool,
-                    >,
-                > = vec![flash_repayment];
-
-                let tx_out = SwapRoute::route_calldata(trade_vec.clone(), calls.clone()).await.unwrap();
-                println!("{:#02x}", tx_out);
-                
-                    let flash_tx: TypedTransaction = flash_swap_v2(
-                        first_pair.clone(),
+
+                /* Trade from the wallet's inventory, or fund from the cheapest flash source */
+                let sources = mainnet::flash_sources.read().unwrap().clone();
+                let plan = match mainnet::inventory
+                    .plan(
+                        &hops,
+                        &book,
                         delta_a,
-                        delta_b,
-                        SwapRoute::route_calldata(trade_vec.clone(), calls.clone()).await.unwrap(),
+                        conversion::router_of,
+                        deadline,
+                        &sources,
+                        *mainnet::flash_contract,
                     )
                     .await
-                    .unwrap();
-                
-
-                let mut tx_vect: Vec<TypedTransaction> = Default::default();
-
-                // Acquire pending tx
-                if pending {
-                    tx_vect = sequence.pending_txs();
-                }
-                tx_vect.push(flash_tx);
-
-                let flash_tx = flash_swap_v2(
-                    *sequence.a1().token.pair_id(),
+                {
+                    Ok(plan) => plan,
+                    Err(e) => {
+                        println!("Path: {} can't be funded: {}", sequence.path(), e);
+                        return;
+                    }
+                };
+
+                /* Value what the plan makes once funded in ETH, and tip on that */
+                let token_profit = plan.profit().unwrap_or_default();
+                // A draft of the call prices its calldata, only its amounts change later
+                let draft_tip = net
+                    .as_ref()
+                    .map(|net| {
+                        mainnet::bid_policy.coinbase_tip_in(*sequence.a1().id(), *mainnet::weth, net.after_gas())
+                    })
+                    .unwrap_or_default();
+                let calldata = match plan.txs(&sources, token_profit, draft_tip) {
+                    Ok(txs) => txs.last().and_then(|tx| tx.data()).cloned().unwrap_or_default(),
+                    Err(e) => {
+                        println!("Path: {} can't be encoded: {}", sequence.path(), e);
+                        return;
+                    }
+                };
+                let gas = mainnet::gas_model.read().unwrap().estimate(&gas_legs, &calldata);
+                let net = match NetProfit::evaluate(
+                    &oracle,
+                    *sequence.a1().id(),
                     delta_a,
-                    delta_b,
-                    SwapRoute::route_calldata(trade_vec.clone(), calls.clone())
-                        .await
-                        .unwrap(),
+                    delta_a.saturating_add(token_profit),
+                    gas,
+                    base_fee,
+                    &mainnet::bid_policy,
+                ) {
+                    Some(net) if net.clears(*mainnet::min_net_profit) => net,
+                    net => {
+                        println!(
+                            "Path: {} below the net profit threshold once funded: {:?}",
+                            sequence.path(),
+                            net.as_ref().map(|net| net.net())
+                        );
+                        return;
+                    }
+                };
+                let outcome = PathOutcome {
+                    seq_id: sequence.seq_id(),
+                    path: sequence.path(),
+                    pools: hops.iter().map(|hop| hop.pool).collect(),
+                    amount_in: delta_a,
+                    amount_out: gross_output,
+                    profit: net.after_gas(),
+                    token_profit,
+                    coinbase_tip: mainnet::bid_policy.coinbase_tip_in(
+                        *sequence.a1().id(),
+                        *mainnet::weth,
+                        net.after_gas(),
+                    ),
+                };
+                // The executor reverts below what pays for gas, tip and the threshold,
+                // leaving the rest to the legs' slippage tolerance
+                let least_profit =
+                    net.least_token_profit(outcome.token_profit, *mainnet::min_net_profit);
+                let txs = match plan.txs(&sources, least_profit, outcome.coinbase_tip) {
+                    Ok(txs) => txs,
+                    Err(e) => {
+                        println!("Path: {} can't be encoded: {}", sequence.path(), e);
+                        return;
+                    }
+                };
+
+                /* Packed with the other paths' candidates and submitted once for the next block */
+                mainnet::opportunity_aggregator.add(
+                    block + 1,
+                    Candidate {
+                        outcome,
+                        hops,
+                        txs,
+                        victims: if pending { sequence.pending_txs() } else { Vec::new() },
+                    },
                 );
-
-                // println!("Flash Tx: {}", &flash_tx.data().unwrap());
-
-                
-
-                let bundle_result = send_flashswap_bundle(tx_vect).await;
-                if bundle_result.as_ref().is_err() {
-                    println!(
-                        "Flash bundle could not be submitted.  Reason: {:#}",
-                        bundle_result.as_ref().err().unwrap()
-                    );
-                }
-                 */
             }
             
         }
@@ -654,15 +759,13 @@ impl ThreePathSequence {
         }
     }
 
-    pub fn pending_txs(&self) -> Vec<TypedTransaction> {
-        let txs: Vec<TypedTransaction> = Default::default();
-
-        let pair_tx: Vec<TypedTransaction> = self
-            .pairs
+    /// Pending transactions on any pair of the sequence, each once
+    pub fn pending_txs(&self) -> Vec<Victim> {
+        self.pairs
             .iter()
             .flat_map(|pair| pair.pending_txs())
-            .collect_vec();
-        pair_tx
+            .unique_by(|victim| victim.hash)
+            .collect_vec()
     }
 
     pub async fn dec_to_u256(delta_a: &BigDecimal, delta_b: &BigDecimal) -> (U256, U256) {
@@ -804,7 +907,12 @@ impl PathSequence for ThreePathSequence {
                 v.to_f64().unwrap()
             );
 
-            if v > BigDecimal::from_f64(1.05).unwrap() {
+            if mainnet::trigger_threshold.allows(
+                &price_oracle::latest_snapshot(),
+                *pending_seq.a1().id(),
+                pending_seq.a1().pending_reserve(),
+                v.to_f64().unwrap_or_default(),
+            ) {
                 spawn(ThreePathSequence::calculate(
                     Arc::new(pending_seq.clone()),
                     true,
@@ -827,7 +935,12 @@ impl PathSequence for ThreePathSequence {
                             confirmed_seq.clone().b3().pending_reserve()
                         );
             
-            if v > BigDecimal::from_f64(1.05).unwrap() {
+            if mainnet::trigger_threshold.allows(
+                &price_oracle::latest_snapshot(),
+                *confirmed_seq.a1().id(),
+                confirmed_seq.a1().pending_reserve(),
+                v.to_f64().unwrap_or_default(),
+            ) {
                 spawn(ThreePathSequence::calculate(
                     Arc::new(confirmed_seq.clone()),
                     false,
@@ -1359,7 +1472,7 @@ let d_a_prime_minus_a = if d_a > d_a_prime {
             ethers::prelude::builders::ContractCall<
                 ethers::prelude::SignerMiddleware<
                     ethers::prelude::Provider<ethers::prelude::Http>,
-                    ethers::prelude::Wallet<ethers::prelude::k256::ecdsa::SigningKey>,
+                    crate::signers::SearcherSigner,
                 >,
                 bool,
             >,
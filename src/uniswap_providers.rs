@@ -1,4 +1,20 @@
+use crate::bid_policy::BidPolicy;
+use crate::bundle_stats::BundleStatsLog;
+use crate::bundle_submitter::{BundleSubmitter, RelayEndpoint};
 use crate::contracts::bindings::uniswap_v2_router_02::UniswapV2Router02;
+use crate::fee_predictor::FeePredictor;
+use crate::flash_source::FlashSources;
+use crate::gas_model::GasModel;
+use crate::inventory::Inventory;
+use crate::net_profit::min_net_profit_from_env;
+use crate::nonce_manager::NonceManager;
+use crate::opportunity_aggregator::OpportunityAggregator;
+use crate::price_oracle::{RiskLimits, TriggerThreshold};
+use crate::private_tx::PrivateTxClient;
+use crate::signers::{
+    SearcherSigner, SignerConfig, SignerError, FLASHBOTS_ENV, KOVAN_WALLET_ENV, WALLET_ENV,
+};
+use crate::slippage::{DeadlinePolicy, SlippagePolicy};
 use anyhow;
 use ethers::core::k256::ecdsa::SigningKey;
 use ethers::middleware::SignerMiddleware;
@@ -9,13 +25,12 @@ use ethers::providers::{Http, Provider};
 use ethers::signers::Signer;
 use ethers::signers::Wallet;
 use ethers_flashbots::FlashbotsMiddleware;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, RwLock};
 use url::Url;
 
 //  Mainnet
@@ -34,13 +49,11 @@ use url::Url;
 pub mod mainnet {
     use super::*;
 
-    static private_key: &str = "7005b56052be4776bffe00ff781879c65aa87ac3d5f8945c0452f27e11fa9236";
-
     pub static flashbots_client: Lazy<
         Arc<
             SignerMiddleware<
-                FlashbotsMiddleware<Provider<Http>, Wallet<SigningKey>>,
-                Wallet<SigningKey>,
+                FlashbotsMiddleware<Provider<Http>, SearcherSigner>,
+                SearcherSigner,
             >,
         >,
     > = Lazy::new(|| {
@@ -54,22 +67,59 @@ pub mod mainnet {
         ))
     });
 
-    pub static flashbots_bundle_signer: Lazy<Wallet<SigningKey>> = Lazy::new(|| {
-        private_key
-            .parse::<LocalWallet>()
-            .unwrap()
-            .with_chain_id(1u64)
+    /// Sends bundles to every mainnet relay, for up to 3 blocks
+    pub static bundle_submitter: Lazy<Arc<BundleSubmitter>> = Lazy::new(|| {
+        Arc::new(BundleSubmitter::new(
+            infura_provider_http.clone(),
+            flashbots_bundle_signer.clone(),
+            RelayEndpoint::mainnet(),
+            3,
+        ))
     });
 
-    pub static wallet: Lazy<Wallet<SigningKey>> = Lazy::new(|| {
-        private_key
-            .parse::<LocalWallet>()
-            .unwrap()
-            .with_chain_id(1u64)
+    /// Private transactions go through the Flashbots relay
+    pub static private_tx_client: Lazy<Arc<PrivateTxClient>> = Lazy::new(|| {
+        Arc::new(PrivateTxClient::new(
+            RelayEndpoint::mainnet().remove(0),
+            flashbots_bundle_signer.clone(),
+        ))
     });
 
+    pub static bundle_stats: Lazy<Arc<BundleStatsLog>> = Lazy::new(Default::default);
+
+    /// Nonces of `wallet`, shared by every submission
+    pub static nonce_manager: Lazy<Arc<NonceManager>> =
+        Lazy::new(|| Arc::new(NonceManager::new(wallet.address())));
+
+    /// Signers resolved by `load_signers`, the wallet's then the Flashbots key's
+    static searcher_signers: OnceCell<(SearcherSigner, SearcherSigner)> = OnceCell::new();
+
+    /// Resolves `wallet` and `flashbots_bundle_signer` from the environment. `main`
+    /// calls it before spawning anything, so a missing or invalid configuration stops
+    /// the bot there rather than in whichever task first touches a client.
+    pub fn load_signers() -> Result<(), SignerError> {
+        let wallet = SearcherSigner::from_env(WALLET_ENV, None, 1)?;
+        let flashbots = SearcherSigner::from_env(FLASHBOTS_ENV, Some(SignerConfig::Random), 1)?;
+        // Loaded once: a second call keeps the signers already in use
+        let _ = searcher_signers.set((wallet, flashbots));
+        Ok(())
+    }
+
+    fn loaded_signers() -> &'static (SearcherSigner, SearcherSigner) {
+        searcher_signers
+            .get()
+            .expect("mainnet::load_signers must run before the signers are used")
+    }
+
+    /// Flashbots reputation key, a fresh one per run unless configured
+    pub static flashbots_bundle_signer: Lazy<SearcherSigner> =
+        Lazy::new(|| loaded_signers().1.clone());
+
+    /// Account sending our transactions, see `signers` for its configuration
+    pub static wallet: Lazy<SearcherSigner> = Lazy::new(|| loaded_signers().0.clone());
+
     /* Clients */
-    pub static client: Lazy<Arc<SignerMiddleware<Provider<Http>, Wallet<SigningKey>>>> =
+    pub static client: Lazy<Arc<SignerMiddleware<Provider<Http>, SearcherSigner>>> =
         Lazy::new(|| {
             Arc::new(SignerMiddleware::new(
                 infura_provider_http.clone(),
@@ -77,7 +127,7 @@ pub mod mainnet {
             ))
         });
     pub static alchemy_http_client: Lazy<
-        Arc<SignerMiddleware<Provider<Http>, Wallet<SigningKey>>>,
+        Arc<SignerMiddleware<Provider<Http>, SearcherSigner>>,
     > = Lazy::new(|| {
         Arc::new(SignerMiddleware::new(
             infura_provider_http.clone(),
@@ -104,24 +154,64 @@ pub mod mainnet {
 
     pub static router_v2: Lazy<Address> =
         Lazy::new(|| Address::from_str("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D").unwrap());
-    pub static flash_contract: Lazy<Address> =
-        Lazy::new(|| Address::from_str("0xa8924Cb51Aa626553C5eE4545172D9caC326ef4e").unwrap());
+    /// Env var holding the address `contracts/Executor.sol` is deployed at
+    pub const EXECUTOR_ENV: &str = "BACKRUNNER_EXECUTOR";
+    static executor_address: OnceCell<Address> = OnceCell::new();
+
+    /// Reads the executor's address, failing in `main` like `load_signers`.
+    pub fn load_executor() -> anyhow::Result<()> {
+        let address = std::env::var(EXECUTOR_ENV)
+            .map_err(|_| anyhow::anyhow!("{} is not set", EXECUTOR_ENV))?
+            .parse()
+            .map_err(|_| anyhow::anyhow!("{} is not an address", EXECUTOR_ENV))?;
+        let _ = executor_address.set(address);
+        Ok(())
+    }
+
+    /// Our executor, running every cycle, see `executor_call`
+    pub static flash_contract: Lazy<Address> = Lazy::new(|| {
+        *executor_address
+            .get()
+            .expect("mainnet::load_executor must run before the executor is used")
+    });
+    pub static weth: Lazy<Address> =
+        Lazy::new(|| Address::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap());
 
     pub static from: Lazy<Address> =
         Lazy::new(|| Address::from_str("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D").unwrap());
-    pub static to: Lazy<Address> =
-        Lazy::new(|| Address::from_str("0xa8924Cb51Aa626553C5eE4545172D9caC326ef4e").unwrap());
+    /// Default receiver of swap outputs, the executor
+    pub static to: Lazy<Address> = Lazy::new(|| *flash_contract);
     pub static max_amount: Lazy<U256> =
         Lazy::new(|| U256::from_dec_str("9999999999999999999999999999999999").unwrap());
 
 
 // 0x8d740B459Ec221c87B54f1747953999FfEBa1D2c
 
+    pub static slippage: Lazy<SlippagePolicy> = Lazy::new(SlippagePolicy::from_env);
+    pub static bid_policy: Lazy<BidPolicy> = Lazy::new(BidPolicy::from_env);
+    /// Next block's fees, fed by `fee_predictor::watch`
+    pub static fee_predictor: Lazy<RwLock<FeePredictor>> = Lazy::new(Default::default);
+    /// Per-DEX swap gas, calibrated as our transactions are simulated and mined
+    pub static gas_model: Lazy<RwLock<GasModel>> = Lazy::new(|| RwLock::new(GasModel::from_env()));
+    /// Least profit in wei, net of gas and tip, worth executing
+    pub static min_net_profit: Lazy<U256> = Lazy::new(min_net_profit_from_env);
+    /// Notional and valuation limits of the trades sent
+    pub static risk_limits: Lazy<RiskLimits> = Lazy::new(RiskLimits::from_env);
+    pub static trigger_threshold: Lazy<TriggerThreshold> = Lazy::new(TriggerThreshold::from_env);
+    pub static deadline_policy: Lazy<DeadlinePolicy> = Lazy::new(DeadlinePolicy::from_env);
+    /// Candidates for the next block, packed and submitted by `opportunity_aggregator::watch`
+    pub static opportunity_aggregator: Lazy<OpportunityAggregator> = Lazy::new(Default::default);
+
+    /// Flash lenders and what they can lend, reloaded by `flash_source::watch_liquidity`
+    pub static flash_sources: Lazy<RwLock<FlashSources>> = Lazy::new(Default::default);
+
+    /// Tokens of `wallet` cycles are traded from before falling back to flash liquidity
+    pub static inventory: Lazy<Inventory<SignerMiddleware<Provider<Http>, SearcherSigner>>> =
+        Lazy::new(|| Inventory::new(client.clone(), wallet.address()));
+
+    /// Swap deadline in unix seconds, counted from the local clock
     pub fn valid_timestamp() -> U256 {
-        let start = SystemTime::now();
-        let since_epoch = start.duration_since(UNIX_EPOCH).unwrap();
-        let time_millis = since_epoch.as_millis().checked_add(30000_u128).unwrap();
-        U256::from(time_millis)
+        deadline_policy.from_now()
     }
 }
 
@@ -153,17 +243,14 @@ pub async fn FLASHBOTS_GOERLI_PROVIDER: Lazy<
 pub mod kovan {
     use super::*;
 
-    static private_key: &str = "7005b56052be4776bffe00ff781879c65aa87ac3d5f8945c0452f27e11fa9236";
-
-    pub static wallet: Lazy<Wallet<SigningKey>> = Lazy::new(|| {
-        private_key
-            .parse::<LocalWallet>()
-            .unwrap()
-            .with_chain_id(1u64)
+    /// Test account, configured under `BACKRUNNER_KOVAN_WALLET` like the mainnet wallet
+    pub static wallet: Lazy<SearcherSigner> = Lazy::new(|| {
+        SearcherSigner::from_env(KOVAN_WALLET_ENV, None, 1)
+            .unwrap_or_else(|e| panic!("kovan wallet: {}", e))
     });
 
     /* Clients */
-    pub static client: Lazy<Arc<SignerMiddleware<Provider<Http>, Wallet<SigningKey>>>> =
+    pub static client: Lazy<Arc<SignerMiddleware<Provider<Http>, SearcherSigner>>> =
         Lazy::new(|| {
             Arc::new(SignerMiddleware::new(
                 infura_provider_http.clone(),
@@ -190,11 +277,9 @@ pub mod kovan {
     pub static max_amount: Lazy<U256> =
         Lazy::new(|| U256::from_dec_str("9999999999999999999999999999999999").unwrap());
 
+    /// Swap deadline in unix seconds, counted from the local clock
     pub fn valid_timestamp() -> U256 {
-        let start = SystemTime::now();
-        let since_epoch = start.duration_since(UNIX_EPOCH).unwrap();
-        let time_millis = since_epoch.as_millis().checked_add(30000_u128).unwrap();
-        U256::from(time_millis)
+        DeadlinePolicy::default().from_now()
     }
 }
 /*
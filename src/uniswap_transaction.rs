@@ -10,15 +10,16 @@ use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn valid_timestamp(future_millis: U256) -> U256 {
+/// Unix time in seconds, `future_secs` from now
+pub fn valid_timestamp(future_secs: U256) -> U256 {
     let start = SystemTime::now();
     let since_epoch = start.duration_since(UNIX_EPOCH).unwrap();
-    let time_millis = since_epoch
-        .as_millis()
-        .checked_add(u128::try_from(future_millis).unwrap())
+    let time_secs = since_epoch
+        .as_secs()
+        .checked_add(u64::try_from(future_secs).unwrap())
         .unwrap();
 
-    U256::from(time_millis)
+    U256::from(time_secs)
 }
 
 pub async fn flash_swap_v2(
@@ -123,7 +124,7 @@ pub fn test() {
             Address::from_str("0xc778417E063141139Fce010982780140Aa0cD5Ab").unwrap(),
             Address::from_str("0x110a13FC3efE6A245B50102D2d79B3E76125Ae83").unwrap(),
         ];
-        let timestamp = valid_timestamp(U256::from(30000));
+        let timestamp = valid_timestamp(U256::from(30));
         /* Amount out is amount we want then multiply by 10^(decimals) */
         let call = router_contract.swap_tokens_for_exact_tokens(
             U256::from_dec_str("1").unwrap(),
@@ -38,7 +38,8 @@ pub fn populate_uniswapv3_pools(pair_map: &mut DashMap<String, Vec<Arc<CryptoPai
         pairs,
         pair_map,
         " - univ3".to_string(),
-        Address::from_str("0x68b3465833fb72A70ecDF485E0e4C7bD8665Fc45").unwrap(),
+        // SwapRouter, whose exactInputSingle still takes a deadline
+        Address::from_str("0xE592427A0AEce92De3Edee1F18E0157C05861564").unwrap(),
     );
 }
 
@@ -0,0 +1,129 @@
+//! Wrapping and unwrapping around the WETH legs of a cycle.
+//!
+//! Pools only trade WETH, while the wallet may hold native ETH and the coinbase tip
+//! must be paid in it. Cycles funded from inventory wrap the ether they are short of
+//! before the first swap. Cycles run by the executor unwrap the tip out of their WETH
+//! profit in the same transaction, so the executor needs no ether balance of its own;
+//! a cycle profiting in another token can't pay a coinbase tip.
+use std::sync::Arc;
+
+use ethers::abi::AbiEncode;
+use ethers::core::types::transaction::eip2718::TypedTransaction;
+use ethers::prelude::*;
+
+use crate::contracts::bindings::iweth9::{WithdrawCall, IWETH9};
+use crate::executor_call::{ExecutorCallError, ExecutorLeg};
+
+/// Placeholder some aggregators and pool lists use for native ether.
+pub const NATIVE_ETH: Address = H160([0xee; 20]);
+
+/// Executor step unwrapping `amount` of `weth` into ether.
+pub fn withdraw_step(weth: Address, amount: U256) -> ExecutorLeg {
+    ExecutorLeg::call(weth, Bytes::from(WithdrawCall { wad: amount }.encode()))
+}
+
+/// Steps funding a `miner_tip` paid in ether out of a cycle whose profit is in
+/// `profit_token`: an unwrap of `weth`. The executor holds no ether, so a tip out of
+/// any other token is refused.
+pub fn tip_steps(
+    weth: Address,
+    profit_token: Address,
+    miner_tip: U256,
+) -> Result<Vec<ExecutorLeg>, ExecutorCallError> {
+    if miner_tip.is_zero() {
+        Ok(Vec::new())
+    } else if profit_token == weth {
+        Ok(vec![withdraw_step(weth, miner_tip)])
+    } else {
+        Err(ExecutorCallError::TipNotInWeth { profit_token })
+    }
+}
+
+/// The token pools trade for `token`: `weth` in place of native ether.
+pub fn pool_token(weth: Address, token: Address) -> Address {
+    if token == NATIVE_ETH {
+        weth
+    } else {
+        token
+    }
+}
+
+/// WETH held by `owner`, and wrapping and unwrapping of its ether.
+pub struct Weth<M> {
+    contract: IWETH9<M>,
+    client: Arc<M>,
+    pub owner: Address,
+}
+
+impl<M: Middleware + 'static> Weth<M> {
+    pub fn new(weth: Address, client: Arc<M>, owner: Address) -> Self {
+        Self {
+            contract: IWETH9::new(weth, client.clone()),
+            client,
+            owner,
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        self.contract.address()
+    }
+
+    /// Ether of the owner.
+    pub async fn ether(&self) -> Result<U256, M::Error> {
+        self.client.get_balance(self.owner, None).await
+    }
+
+    /// `deposit` sending `amount` of ether.
+    pub fn deposit(&self, amount: U256) -> TypedTransaction {
+        let mut tx = self.contract.deposit().tx;
+        tx.set_value(amount);
+        tx
+    }
+
+    /// `withdraw` of `amount`.
+    pub fn withdraw(&self, amount: U256) -> TypedTransaction {
+        self.contract.withdraw(amount).tx
+    }
+
+    /// Deposit covering what `held` lacks of `needed`, keeping `reserve` of ether for
+    /// gas. `None` when nothing is missing or the ether doesn't cover it.
+    pub async fn wrap_shortfall(
+        &self,
+        held: U256,
+        needed: U256,
+        reserve: U256,
+    ) -> Result<Option<TypedTransaction>, M::Error> {
+        let shortfall = match needed.checked_sub(held) {
+            Some(shortfall) if !shortfall.is_zero() => shortfall,
+            _ => return Ok(None),
+        };
+        let spendable = self.ether().await?.saturating_sub(reserve);
+        Ok((spendable >= shortfall).then(|| self.deposit(shortfall)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::AbiDecode;
+
+    #[test]
+    fn test_tip_steps() {
+        let weth = Address::repeat_byte(0xaa);
+        let tip = U256::from(1_000);
+
+        let steps = tip_steps(weth, weth, tip).unwrap();
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].target, weth);
+        assert_eq!(WithdrawCall::decode(&steps[0].calldata).unwrap().wad, tip);
+
+        let dai = Address::repeat_byte(1);
+        assert_eq!(
+            tip_steps(weth, dai, tip),
+            Err(ExecutorCallError::TipNotInWeth { profit_token: dai })
+        );
+        assert!(tip_steps(weth, dai, U256::zero()).unwrap().is_empty());
+        assert!(tip_steps(weth, weth, U256::zero()).unwrap().is_empty());
+        assert_eq!(pool_token(weth, NATIVE_ETH), weth);
+    }
+}